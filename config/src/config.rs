@@ -15,11 +15,11 @@
 //! Configuration file management
 
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
 use std::path::PathBuf;
 
-use cuckoo::{CuckooMinerError, PluginConfig};
+use cuckoo::{is_cpu_plugin, CuckooMinerError, DeviceInfo, PluginConfig};
 use toml;
 use types::MinerConfig;
 use types::{ConfigError, ConfigMembers, GlobalConfig, GrinMinerPluginConfig};
@@ -44,6 +44,7 @@ fn resolve_param(config: &mut PluginConfig, name: &str, value: u32) {
 				_ => false,
 			}
 		}
+		"mutate_nonce" => config.params.mutate_nonce = value != 0,
 		"device" => config.params.device = value,
 		"blocks" => config.params.blocks = value,
 		"tbp" => config.params.tpb = value,
@@ -63,13 +64,120 @@ fn resolve_param(config: &mut PluginConfig, name: &str, value: u32) {
 	};
 }
 
-/// Transforms a set of grin-miner plugin configs to cuckoo-miner plugins configs
-pub fn read_configs(
-	plugin_dir: Option<PathBuf>,
-	conf_in: Vec<GrinMinerPluginConfig>,
+/// Transforms a grin-miner plugin config to a cuckoo-miner plugin config
+fn read_config(
+	plugin_dir: PathBuf,
+	conf: GrinMinerPluginConfig,
+) -> Result<PluginConfig, CuckooMinerError> {
+	let mut c = PluginConfig::new(plugin_dir, &conf.plugin_name)?;
+	if let Some(params) = conf.parameters {
+		for k in params.keys() {
+			resolve_param(&mut c, k, *params.get(k).unwrap());
+		}
+	}
+	if let Some(scaling) = conf.difficulty_scaling {
+		c.scaling = scaling;
+	}
+	c.expected_gps = conf.expected_gps;
+	c.critical = conf.critical.unwrap_or(false);
+	c.nonce_seed = conf.nonce_seed;
+	c.range_target_secs = conf.adaptive_range_secs;
+	c.cpu_affinity = conf.cpu_affinity;
+	if let Some(nonce_range) = conf.nonce_range {
+		c.nonce_range = nonce_range;
+	}
+	if let Some(min_range) = conf.min_range {
+		c.min_range = min_range;
+	}
+	if let Some(max_range) = conf.max_range {
+		c.max_range = max_range;
+	}
+	if let Some(edge_bits) = conf.edge_bits {
+		c.params.edge_bits = edge_bits;
+	}
+	if conf.sequential_nonce.unwrap_or(false) {
+		c.sequential_nonce = true;
+		c.params.mutate_nonce = true;
+	}
+	Ok(c)
+}
+
+/// Devices given their own GPU plugin config, as (platform, device). The
+/// platform is None if left to the plugin's default
+fn manual_devices(conf_in: &[GrinMinerPluginConfig]) -> Vec<(Option<u32>, u32)> {
+	conf_in
+		.iter()
+		.filter(|c| !c.auto_gpu.unwrap_or(false) && !is_cpu_plugin(&c.plugin_name))
+		.map(|c| {
+			let param = |name| c.parameters.as_ref().and_then(|p| p.get(name)).cloned();
+			(param("platform"), param("device").unwrap_or(0))
+		})
+		.collect()
+}
+
+/// Expands an auto_gpu plugin config into one config per device the plugin
+/// finds, leaving out devices that have a config of their own
+fn expand_auto_gpu(
+	c: PluginConfig,
+	manual_devices: &[(Option<u32>, u32)],
 ) -> Result<Vec<PluginConfig>, CuckooMinerError> {
-	// Resolve a final plugin path, either config-provided or from the current executable path
-	let plugin_dir_absolute_path = match plugin_dir {
+	match c.enumerate_devices()? {
+		Some(devices) => Ok(auto_gpu_configs(c, devices, manual_devices)),
+		None => {
+			warn!(
+				LOGGER,
+				"Plugin {} can't list its devices, ignoring auto_gpu", c.name
+			);
+			Ok(vec![c])
+		}
+	}
+}
+
+/// One copy of an auto_gpu plugin config per device found, other than
+/// the manually configured ones
+fn auto_gpu_configs(
+	c: PluginConfig,
+	devices: Vec<DeviceInfo>,
+	manual_devices: &[(Option<u32>, u32)],
+) -> Vec<PluginConfig> {
+	let mut configs = vec![];
+	for d in devices {
+		let manual = manual_devices
+			.iter()
+			.any(|(p, dev)| *dev == d.device && p.unwrap_or(d.platform) == d.platform);
+		if manual {
+			debug!(
+				LOGGER,
+				"auto_gpu: {} device {} on platform {} is configured separately",
+				c.name,
+				d.device,
+				d.platform
+			);
+			continue;
+		}
+		info!(
+			LOGGER,
+			"auto_gpu: mining with {} on device {} ({}) on platform {}",
+			c.name,
+			d.device,
+			d.get_name(),
+			d.platform
+		);
+		let mut dc = c.clone();
+		dc.params.platform = d.platform;
+		dc.params.device = d.device;
+		configs.push(dc);
+	}
+	if configs.is_empty() {
+		warn!(LOGGER, "auto_gpu: {} found no devices to mine on", c.name);
+	}
+	configs
+}
+
+/// Resolve a final plugin path, either config-provided or from the current
+/// executable path
+pub fn resolve_plugin_dir(plugin_dir: Option<PathBuf>) -> Result<PathBuf, CuckooMinerError> {
+	match plugin_dir {
 		Some(path) => {
 			let absolute_path = path.canonicalize().map_err(CuckooMinerError::from);
 			if let Ok(path) = &absolute_path {
@@ -101,24 +209,82 @@ pub fn read_configs(
 			};
 			absolute_path
 		}
-	}?;
+	}
+}
 
-	let mut return_vec = vec![];
-	for conf in conf_in {
-		let res = PluginConfig::new(plugin_dir_absolute_path.clone(), &conf.plugin_name);
-		match res {
+/// The cuckoo-miner plugin configs of a miner config, with its CPU fallback
+/// if enabled
+pub fn read_miner_configs(
+	mining_config: &MinerConfig,
+) -> Result<Vec<PluginConfig>, CuckooMinerError> {
+	let cpu_fallback = match mining_config.gpu_fallback_to_cpu {
+		Some(true) => mining_config.cpu_fallback_plugin_config.clone(),
+		_ => None,
+	};
+	read_configs(
+		mining_config.miner_plugin_dir.clone(),
+		mining_config.miner_plugin_config.clone(),
+		cpu_fallback,
+	)
+}
+
+/// Transforms a set of grin-miner plugin configs to cuckoo-miner plugins configs.
+/// If a CPU fallback is given, it's used in place of GPU plugins that can't
+/// be loaded, and attached to the others in case their device fails
+pub fn read_configs(
+	plugin_dir: Option<PathBuf>,
+	conf_in: Vec<GrinMinerPluginConfig>,
+	cpu_fallback: Option<GrinMinerPluginConfig>,
+) -> Result<Vec<PluginConfig>, CuckooMinerError> {
+	let plugin_dir_absolute_path = resolve_plugin_dir(plugin_dir)?;
+
+	let fallback = match cpu_fallback {
+		Some(conf) => match read_config(plugin_dir_absolute_path.clone(), conf) {
+			Ok(c) => Some(c),
 			Err(e) => {
+				error!(LOGGER, "Error reading CPU fallback plugin config: {:?}", e);
+				return Err(e);
+			}
+		},
+		None => None,
+	};
+
+	let manual_devices = manual_devices(&conf_in);
+	let mut return_vec: Vec<PluginConfig> = vec![];
+	for conf in conf_in {
+		let name = conf.plugin_name.clone();
+		let auto_gpu = conf.auto_gpu.unwrap_or(false);
+		let res = match read_config(plugin_dir_absolute_path.clone(), conf) {
+			Ok(c) if auto_gpu => expand_auto_gpu(c, &manual_devices),
+			res => res.map(|c| vec![c]),
+		};
+		match (res, &fallback) {
+			(Err(e), Some(fb)) if !is_cpu_plugin(&name) => {
+				warn!(
+					LOGGER,
+					"Error reading GPU plugin config {}: {:?}, falling back to {}",
+					name,
+					e,
+					fb.name
+				);
+				// a single CPU solver covers all failed GPUs
+				if !return_vec.iter().any(|c| c.file == fb.file) {
+					return_vec.push(fb.clone());
+				}
+			}
+			(Err(e), _) => {
 				error!(LOGGER, "Error reading plugin config: {:?}", e);
 				return Err(e);
 			}
-			Ok(mut c) => {
-				if conf.parameters.is_some() {
-					let params = conf.parameters.unwrap();
-					for k in params.keys() {
-						resolve_param(&mut c, k, *params.get(k).unwrap());
+			(Ok(configs), fb) => {
+				for mut c in configs {
+					if let Some(fb) = fb {
+						if !c.is_cpu() {
+							c.cpu_fallback = Some(Box::new(fb.clone()));
+						}
 					}
+					return_vec.push(c);
 				}
-				return_vec.push(c)
 			}
 		}
 	}
@@ -215,6 +381,34 @@ impl GlobalConfig {
 		return_value.read_config()
 	}
 
+	/// As `new`, but if the config file can't be parsed, back it up next to
+	/// the original and carry on with the defaults rather than failing
+	pub fn new_or_repair(file_path: Option<&str>) -> Result<GlobalConfig, ConfigError> {
+		match GlobalConfig::new(file_path) {
+			Err(ConfigError::ParseError(file_name, message)) => {
+				let backup = format!("{}.broken", file_name);
+				fs::copy(&file_name, &backup).map_err(|e| {
+					ConfigError::FileIOError(
+						file_name.clone(),
+						format!("Error backing up broken config file: {}", e),
+					)
+				})?;
+				// the logger isn't up yet, so make sure this gets seen
+				eprintln!(
+					"WARNING: Error parsing config file at {} - {}",
+					file_name, message
+				);
+				eprintln!(
+					"WARNING: Backed it up to {} and starting with default settings!",
+					backup
+				);
+				// no config file is in use, so it isn't reloaded either
+				Ok(GlobalConfig::default())
+			}
+			r => r,
+		}
+	}
+
 	/// Read config
 	pub fn read_config(mut self) -> Result<GlobalConfig, ConfigError> {
 		let mut file = File::open(self.config_file_path.as_mut().unwrap())?;
@@ -246,3 +440,105 @@ impl GlobalConfig {
 		}
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn plugin_config(name: &str) -> PluginConfig {
+		PluginConfig {
+			name: name.to_string(),
+			file: format!("{}.so", name),
+			params: Default::default(),
+			scaling: Default::default(),
+			expected_gps: None,
+			cpu_fallback: None,
+			sequential_nonce: false,
+			critical: false,
+			nonce_seed: None,
+			range_target_secs: None,
+			nonce_range: 1,
+			min_range: 1,
+			max_range: 16,
+			cpu_affinity: None,
+		}
+	}
+
+	fn miner_plugin_config(
+		name: &str,
+		auto_gpu: bool,
+		params: &[(&str, u32)],
+	) -> GrinMinerPluginConfig {
+		GrinMinerPluginConfig {
+			plugin_name: name.to_string(),
+			parameters: Some(params.iter().map(|(k, v)| (k.to_string(), *v)).collect()),
+			auto_gpu: Some(auto_gpu),
+			..Default::default()
+		}
+	}
+
+	fn device(platform: u32, device: u32) -> DeviceInfo {
+		DeviceInfo {
+			platform,
+			device,
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn test_manual_devices() {
+		let confs = vec![
+			miner_plugin_config("cuckatoo_lean_cuda_31", true, &[]),
+			miner_plugin_config("cuckatoo_mean_cpu_compat_31", false, &[]),
+			miner_plugin_config("cuckatoo_lean_cuda_31", false, &[("device", 1)]),
+			miner_plugin_config("ocl_cuckatoo", false, &[("platform", 1)]),
+		];
+		// only GPU plugins not expanded by auto_gpu, the platform left to
+		// the plugin if not given
+		assert_eq!(manual_devices(&confs), vec![(None, 1), (Some(1), 0)]);
+	}
+
+	#[test]
+	fn test_expand_auto_gpu() {
+		let devices = || vec![device(0, 0), device(0, 1), device(1, 0)];
+		let found = |configs: Vec<PluginConfig>| -> Vec<(u32, u32)> {
+			configs
+				.iter()
+				.map(|c| (c.params.platform, c.params.device))
+				.collect()
+		};
+		let configs = auto_gpu_configs(plugin_config("ocl_cuckatoo"), devices(), &[]);
+		assert_eq!(found(configs), vec![(0, 0), (0, 1), (1, 0)]);
+		// devices with a config of their own take precedence, on any
+		// platform if the manual config doesn't give one
+		let configs = auto_gpu_configs(
+			plugin_config("ocl_cuckatoo"),
+			devices(),
+			&[(None, 1), (Some(1), 0)],
+		);
+		assert_eq!(found(configs), vec![(0, 0)]);
+		let configs = auto_gpu_configs(plugin_config("ocl_cuckatoo"), devices(), &[(Some(1), 1)]);
+		assert_eq!(found(configs), vec![(0, 0), (0, 1), (1, 0)]);
+		assert!(auto_gpu_configs(plugin_config("ocl_cuckatoo"), vec![], &[]).is_empty());
+	}
+
+	#[test]
+	fn test_repair_broken_config() {
+		let dir = env::temp_dir().join(format!("grin_miner_config_{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let path = dir.join(CONFIG_FILE_NAME);
+		fs::write(&path, "[mining\nrun_tui = ").unwrap();
+		let path_str = path.to_str().unwrap();
+		assert!(GlobalConfig::new(Some(path_str)).is_err());
+		let config = GlobalConfig::new_or_repair(Some(path_str)).unwrap();
+		let backed_up = fs::read_to_string(dir.join(format!("{}.broken", CONFIG_FILE_NAME)));
+		fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(backed_up.unwrap(), "[mining\nrun_tui = ");
+		assert!(config.config_file_path.is_none());
+		assert!(!config.using_config_file);
+		assert_eq!(
+			config.members.unwrap().mining.stratum_server_addr,
+			MinerConfig::default().stratum_server_addr
+		);
+	}
+}
@@ -34,5 +34,7 @@ extern crate grin_miner_util as util;
 mod config;
 mod types;
 
-pub use config::read_configs;
-pub use types::{ConfigError, ConfigMembers, GlobalConfig, GrinMinerPluginConfig, MinerConfig};
+pub use config::{read_configs, read_miner_configs, resolve_plugin_dir};
+pub use types::{
+	ConfigError, ConfigMembers, GlobalConfig, GrinMinerPluginConfig, MinerConfig, StratumProtocol,
+};
@@ -18,6 +18,7 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 use std::{fmt, io};
 
+use cuckoo::DifficultyScaling;
 use util;
 
 /// CuckooMinerPlugin configuration
@@ -28,6 +29,54 @@ pub struct GrinMinerPluginConfig {
 
 	///
 	pub parameters: Option<HashMap<String, u32>>,
+
+	/// How solution difficulty is scaled for this plugin's algorithm
+	/// (Unscaled, Cuckatoo or Cuckaroo)
+	pub difficulty_scaling: Option<DifficultyScaling>,
+
+	/// Graphs per second the device is expected to deliver, to warn
+	/// when it falls behind
+	pub expected_gps: Option<f64>,
+
+	/// Step through nonces from zero for each job rather than picking them
+	/// at random, for solo miners wanting reproducible nonce coverage. Also
+	/// lets the plugin write the nonce into the header itself
+	pub sequential_nonce: Option<bool>,
+
+	/// Graph size for this device, for plugins that support more than one,
+	/// so devices can mine different sizes from the same job stream
+	pub edge_bits: Option<u32>,
+
+	/// Whether this is a critical device, e.g. a rig's main card, whose
+	/// errors or stalls are escalated rather than just reported
+	pub critical: Option<bool>,
+
+	/// Seed for the nonces tried, so a specific solve can be reproduced
+	/// when debugging. Leave unset to mine, nonces are OS random otherwise
+	pub nonce_seed: Option<u64>,
+
+	/// For plugins given mutate_nonce, target seconds per solver call,
+	/// trying more nonces per call on fast devices and fewer on slow ones
+	pub adaptive_range_secs: Option<f64>,
+
+	/// For plugins given mutate_nonce, nonces tried per solver call when
+	/// adaptive_range_secs isn't set (defaults to 1)
+	pub nonce_range: Option<u32>,
+
+	/// Least nonces tried per call with an adaptive range (defaults to 1)
+	pub min_range: Option<u32>,
+
+	/// Most nonces tried per call with an adaptive range (defaults to 16)
+	pub max_range: Option<u32>,
+
+	/// Mine on every device the plugin finds, rather than the one given by
+	/// the device and platform parameters. Devices that have a config of
+	/// their own are left to it
+	pub auto_gpu: Option<bool>,
+
+	/// Logical CPU cores, numbered from 0, to pin the solver thread to.
+	/// It's pinned to the first one that exists
+	pub cpu_affinity: Option<Vec<usize>>,
 }
 
 impl Default for GrinMinerPluginConfig {
@@ -35,6 +84,18 @@ impl Default for GrinMinerPluginConfig {
 		GrinMinerPluginConfig {
 			plugin_name: String::new(),
 			parameters: None,
+			difficulty_scaling: None,
+			expected_gps: None,
+			sequential_nonce: None,
+			edge_bits: None,
+			critical: None,
+			nonce_seed: None,
+			adaptive_range_secs: None,
+			nonce_range: None,
+			min_range: None,
+			max_range: None,
+			auto_gpu: None,
+			cpu_affinity: None,
 		}
 	}
 }
@@ -85,6 +146,17 @@ impl From<io::Error> for ConfigError {
 	}
 }
 
+/// How the miner talks to the stratum server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StratumProtocol {
+	/// Line based JSON-RPC over a raw TCP connection
+	#[default]
+	Tcp,
+	/// JSON-RPC POSTed over HTTP(S), polling for new jobs
+	Http,
+}
+
 /// basic mining configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MinerConfig {
@@ -94,32 +166,191 @@ pub struct MinerConfig {
 	/// mining loop by adding a sleep to the thread
 	pub stratum_server_addr: String,
 
+	/// stratum servers to fail over to, in order, when the one in use
+	/// can't be reached
+	pub stratum_server_failover: Option<Vec<String>>,
+
+	/// while failed over, go back to a higher priority stratum server once
+	/// it's reachable again (defaults to true)
+	pub stratum_server_failback: Option<bool>,
+
+	/// seconds between checks of the higher priority stratum servers while
+	/// failed over (defaults to 60)
+	pub stratum_server_failback_probe_secs: Option<u64>,
+
 	/// login for the stratum server
 	pub stratum_server_login: Option<String>,
 
 	/// password for the stratum server
 	pub stratum_server_password: Option<String>,
 
+	/// starting share difficulty suggested to the pool on login
+	pub stratum_server_suggested_difficulty: Option<u64>,
+
+	/// methods sent to the stratum server on connect, before asking for a
+	/// job (defaults to just "login")
+	pub stratum_server_handshake: Option<Vec<String>>,
+
+	/// whether to send the stratum server a logout request on shutdown
+	pub stratum_server_logout_on_shutdown: Option<bool>,
+
+	/// times a share that fails to send is retried before reconnecting
+	/// (defaults to 3)
+	pub stratum_server_submit_retries: Option<u32>,
+
+	/// seconds between keepalive requests sent to the stratum server, so
+	/// pools with a read timeout don't drop idle connections (defaults to
+	/// 30, 0 disables them)
+	pub stratum_server_keepalive_secs: Option<u64>,
+
+	/// local address to connect to the stratum server from, for rigs with
+	/// more than one network interface. Applies to plain and TLS tcp
+	/// connections
+	pub stratum_bind_addr: Option<String>,
+
 	/// whether tls is enabled for the stratum server
 	pub stratum_server_tls_enabled: Option<bool>,
 
+	/// CA certificate file, PEM or DER, to trust for the stratum server's
+	/// TLS certificate along with the system's, e.g. for a pool with a
+	/// self-signed certificate
+	pub stratum_server_tls_ca: Option<String>,
+
+	/// protocol used to talk to the stratum server, "tcp" or "http"
+	/// (defaults to "tcp")
+	pub stratum_protocol: Option<StratumProtocol>,
+
+	/// whether to submit the proof bit-packed and hex encoded
+	/// rather than as a list of nonces
+	pub stratum_server_compact_pow: Option<bool>,
+
+	/// fraction of a device's expected_gps below which it's flagged as
+	/// underperforming (defaults to 0.8)
+	pub underperforming_gps_fraction: Option<f64>,
+
+	/// seconds a critical device can go without completing a graph, while
+	/// there's work for it, before it's considered down (defaults to 120)
+	pub critical_stall_secs: Option<u64>,
+
+	/// lowest job target difficulty considered sane, lower targets are
+	/// warned about (no lower bound if not set)
+	pub min_accept_difficulty: Option<u64>,
+
+	/// highest job target difficulty considered sane, higher targets are
+	/// warned about (no upper bound if not set)
+	pub max_accept_difficulty: Option<u64>,
+
+	/// whether to refuse to mine jobs with a target difficulty outside
+	/// the bounds above, rather than just warning
+	pub refuse_out_of_bounds_difficulty: Option<bool>,
+
+	/// least unscaled difficulty of the solutions submitted, when above
+	/// the job's target, to cut down on shares sent to the pool
+	pub min_share_difficulty: Option<u64>,
+
+	/// pause mining if no new job has been received for this many seconds
+	/// (disabled if not set)
+	pub max_job_age_secs: Option<u64>,
+
+	/// restart the solvers if no graphs are completed for this many seconds
+	/// while there's a job to work on (disabled if not set)
+	pub stall_watchdog_secs: Option<u64>,
+
+	/// exit with a distinct code for a supervisor to restart the miner,
+	/// rather than restarting the solvers, when the watchdog fires
+	pub stall_watchdog_exit: Option<bool>,
+
+	/// address of a local control socket accepting pause/resume/stop/status
+	/// commands, which must be a loopback address (disabled if not set)
+	pub control_socket_addr: Option<String>,
+
+	/// address of an HTTP server serving the current stats as JSON at
+	/// /stats, and Prometheus metrics at /metrics, for builds with the
+	/// http-stats and metrics features (disabled if not set)
+	pub stats_http_addr: Option<String>,
+
+	/// seconds a lost connection is still shown as connected in the TUI,
+	/// so brief reconnects don't flash up as disconnected (defaults to 3)
+	pub tui_disconnect_grace_secs: Option<u64>,
+
+	/// seconds between concise summary lines logged at info level when
+	/// running without the tui (disabled if not set)
+	pub headless_summary_secs: Option<u64>,
+
+	/// file to append a record of each block found to, with its proof
+	pub blocks_found_file: Option<String>,
+
+	/// file to append compact binary solver stats samples to on each stat
+	/// interval (disabled if not set)
+	pub stats_series_file: Option<String>,
+
+	/// size in MB at which the stats series file is rotated (defaults to 64)
+	pub stats_series_max_mb: Option<u64>,
+
+	/// milliseconds to wait between initializing each solver's device, so
+	/// large rigs don't set them all up at once (defaults to 0)
+	pub solver_start_stagger_ms: Option<u64>,
+
+	/// seconds to wait for solvers to exit on shutdown, before leaving any
+	/// stuck in their plugin behind (defaults to 10)
+	pub solver_shutdown_timeout_secs: Option<u64>,
+
 	/// plugin dir
 	pub miner_plugin_dir: Option<PathBuf>,
 
 	/// Cuckoo miner plugin configuration, one for each plugin
 	pub miner_plugin_config: Vec<GrinMinerPluginConfig>,
+
+	/// whether to mine with `cpu_fallback_plugin_config` in place of
+	/// GPU plugins that fail to initialize their device
+	pub gpu_fallback_to_cpu: Option<bool>,
+
+	/// CPU plugin to fall back to when `gpu_fallback_to_cpu` is set
+	pub cpu_fallback_plugin_config: Option<GrinMinerPluginConfig>,
 }
 
 impl Default for MinerConfig {
 	fn default() -> MinerConfig {
 		MinerConfig {
 			run_tui: false,
+			tui_disconnect_grace_secs: None,
+			headless_summary_secs: None,
+			blocks_found_file: None,
+			stats_series_file: None,
+			stats_series_max_mb: None,
+			solver_start_stagger_ms: None,
+			solver_shutdown_timeout_secs: None,
 			miner_plugin_dir: None,
 			miner_plugin_config: vec![],
+			gpu_fallback_to_cpu: None,
+			cpu_fallback_plugin_config: None,
 			stratum_server_addr: String::from("http://127.0.0.1:13416"),
+			stratum_server_failover: None,
+			stratum_server_failback: None,
+			stratum_server_failback_probe_secs: None,
 			stratum_server_login: None,
 			stratum_server_password: None,
+			stratum_server_suggested_difficulty: None,
+			stratum_server_handshake: None,
+			stratum_server_logout_on_shutdown: None,
+			stratum_server_submit_retries: None,
+			stratum_server_keepalive_secs: None,
+			stratum_bind_addr: None,
 			stratum_server_tls_enabled: None,
+			stratum_server_tls_ca: None,
+			stratum_protocol: None,
+			stratum_server_compact_pow: None,
+			underperforming_gps_fraction: None,
+			critical_stall_secs: None,
+			min_accept_difficulty: None,
+			max_accept_difficulty: None,
+			refuse_out_of_bounds_difficulty: None,
+			min_share_difficulty: None,
+			max_job_age_secs: None,
+			stall_watchdog_secs: None,
+			stall_watchdog_exit: None,
+			control_socket_addr: None,
+			stats_http_addr: None,
 		}
 	}
 }
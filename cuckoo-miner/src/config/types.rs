@@ -14,15 +14,20 @@
 
 //! Public Types used for cuckoo-miner module
 
-use plugin::SolverParams;
-use std::path::PathBuf;
-use std::{fmt, io};
+use glob::glob;
+use miner::consensus::DifficultyScaling;
+use plugin::{DeviceInfo, PluginBuildInfo, SolverParams};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::{cmp, fmt, io, thread};
+use util::LOGGER;
 use {CuckooMinerError, PluginLibrary};
 
 pub static SO_SUFFIX: &str = ".cuckooplugin";
 
 /// CuckooMinerPlugin configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PluginConfig {
 	/// The display name of the plugin to load
 	pub name: String,
@@ -32,11 +37,87 @@ pub struct PluginConfig {
 
 	/// device params
 	pub params: SolverParams,
+
+	/// how solution difficulty is scaled when checking against the target
+	#[serde(default)]
+	pub scaling: DifficultyScaling,
+
+	/// graphs per second the device is expected to deliver, if known
+	#[serde(default)]
+	pub expected_gps: Option<f64>,
+
+	/// CPU plugin to mine with instead if this plugin fails to initialize
+	/// its device
+	#[serde(default)]
+	pub cpu_fallback: Option<Box<PluginConfig>>,
+
+	/// step through nonces deterministically from zero for each job,
+	/// rather than picking them at random
+	#[serde(default)]
+	pub sequential_nonce: bool,
+
+	/// whether this device is critical to the rig, escalating its errors
+	/// and stalls beyond the usual per-device reporting
+	#[serde(default)]
+	pub critical: bool,
+
+	/// seed for the random nonces tried, to reproduce a solve when
+	/// debugging. OS random if not set
+	#[serde(default)]
+	pub nonce_seed: Option<u64>,
+
+	/// for plugins mutating the nonce themselves, target seconds per
+	/// run_solver call, adapting the number of nonces tried per call to the
+	/// device's graph time. A single nonce per call if not set
+	#[serde(default)]
+	pub range_target_secs: Option<f64>,
+
+	/// for plugins mutating the nonce themselves, nonces tried per
+	/// run_solver call when the range isn't adaptive
+	#[serde(default = "default_nonce_range")]
+	pub nonce_range: u32,
+
+	/// least nonces tried per call with an adaptive range
+	#[serde(default = "default_min_range")]
+	pub min_range: u32,
+
+	/// most nonces tried per call with an adaptive range
+	#[serde(default = "default_max_range")]
+	pub max_range: u32,
+
+	/// CPU cores to pin the solver thread to, as indices into the logical
+	/// CPUs the OS reports, numbered from 0 (as in /proc/cpuinfo or Task
+	/// Manager). A thread can only be pinned to a single core, so it goes
+	/// to the first listed core that exists, the rest being fallbacks.
+	/// Threads the plugin starts inherit it on Linux. Unpinned if not set
+	#[serde(default)]
+	pub cpu_affinity: Option<Vec<usize>>,
+}
+
+fn default_nonce_range() -> u32 {
+	1
+}
+
+fn default_min_range() -> u32 {
+	1
+}
+
+fn default_max_range() -> u32 {
+	16
 }
 
 impl PluginConfig {
 	/// create new!
-	pub fn new(mut plugin_dir: PathBuf, name: &str) -> Result<PluginConfig, CuckooMinerError> {
+	pub fn new(plugin_dir: PathBuf, name: &str) -> Result<PluginConfig, CuckooMinerError> {
+		PluginConfig::load(plugin_dir, name).map(|(config, _)| config)
+	}
+
+	/// Config for the named plugin along with its build info, read while
+	/// the plugin is loaded for its default params
+	fn load(
+		mut plugin_dir: PathBuf,
+		name: &str,
+	) -> Result<(PluginConfig, PluginBuildInfo), CuckooMinerError> {
 		plugin_dir.push(format!("{}{}", name, SO_SUFFIX).as_str());
 		let plugin_file_str = plugin_dir.to_str().ok_or_else(|| {
 			CuckooMinerError::PluginNotFoundError(
@@ -46,14 +127,173 @@ impl PluginConfig {
 
 		PluginLibrary::new(plugin_file_str).map(|plugin_library| {
 			let params = plugin_library.get_default_params();
+			let build_info = plugin_library.get_build_info();
 			plugin_library.unload();
-			PluginConfig {
-				name: name.to_owned(),
-				file: plugin_file_str.to_owned(),
-				params,
-			}
+			let config = PluginConfig::with_params(name, plugin_file_str, params);
+			(config, build_info)
 		})
 	}
+
+	/// Config for the plugin in the given file, with the given params and
+	/// defaults for everything else
+	fn with_params(name: &str, file: &str, params: SolverParams) -> PluginConfig {
+		PluginConfig {
+			name: name.to_owned(),
+			file: file.to_owned(),
+			params,
+			scaling: DifficultyScaling::default(),
+			expected_gps: None,
+			cpu_fallback: None,
+			sequential_nonce: false,
+			critical: false,
+			nonce_seed: None,
+			range_target_secs: None,
+			nonce_range: default_nonce_range(),
+			min_range: default_min_range(),
+			max_range: default_max_range(),
+			cpu_affinity: None,
+		}
+	}
+
+	/// Number of nonces to try in the next run_solver call, given the
+	/// device's last graph time in nanoseconds. Only plugins writing the
+	/// nonce into the header themselves can try more than one per call,
+	/// and sequential nonces stick to one to keep coverage interleaved.
+	/// Without a target time per call, the configured nonce range is used
+	pub fn solver_range(&self, last_solution_time: u64) -> u32 {
+		if !self.params.mutate_nonce || self.sequential_nonce {
+			return 1;
+		}
+		let target = match self.range_target_secs {
+			Some(t) => t,
+			None => return self.nonce_range.max(1),
+		};
+		let min = self.min_range.max(1);
+		let max = self.max_range.max(min);
+		if last_solution_time == 0 {
+			return min;
+		}
+		let graph_secs = last_solution_time as f64 / 1_000_000_000.0;
+		let range = (target / graph_secs).floor() as u32;
+		range.max(min).min(max)
+	}
+
+	/// List the devices the plugin can mine on, None if the plugin can't
+	/// list them
+	pub fn enumerate_devices(&self) -> Result<Option<Vec<DeviceInfo>>, CuckooMinerError> {
+		let plugin_library = PluginLibrary::new(&self.file)?;
+		let devices = plugin_library.enumerate_devices();
+		plugin_library.unload();
+		Ok(devices)
+	}
+
+	/// whether this is a CPU plugin, going by the plugin naming convention
+	pub fn is_cpu(&self) -> bool {
+		is_cpu_plugin(&self.name)
+	}
+
+	/// algorithm the plugin mines, going by the plugin naming convention,
+	/// e.g. "cuckarood" for cuckarood_cuda_29 or ocl_cuckarood
+	pub fn algorithm(&self) -> &str {
+		let name = self.name.trim_start_matches("ocl_");
+		name.split('_').next().unwrap_or(name)
+	}
+}
+
+/// Whether the named plugin, or plugin file, is a CPU plugin, going by the
+/// plugin naming convention
+pub fn is_cpu_plugin(name: &str) -> bool {
+	name.contains("cpu")
+}
+
+/// Configs for every plugin in the plugin dir, with their default params,
+/// and the build info each plugin reports ("unknown" for plugins that
+/// don't), ordered by file. Files that can't be loaded as a plugin are
+/// skipped
+pub fn get_available_plugins(
+	plugin_dir: &Path,
+) -> Result<Vec<(PluginConfig, PluginBuildInfo)>, CuckooMinerError> {
+	find_plugins(plugin_dir, PluginConfig::load)
+}
+
+/// Plugins in the plugin dir as loaded by the given loader, which is
+/// passed the dir and each plugin's name
+fn find_plugins<F>(
+	plugin_dir: &Path,
+	load: F,
+) -> Result<Vec<(PluginConfig, PluginBuildInfo)>, CuckooMinerError>
+where
+	F: Fn(PathBuf, &str) -> Result<(PluginConfig, PluginBuildInfo), CuckooMinerError>
+		+ Send
+		+ Sync
+		+ 'static,
+{
+	let pattern = plugin_dir.join(format!("*{}", SO_SUFFIX));
+	let pattern = pattern.to_str().ok_or_else(|| {
+		CuckooMinerError::PluginNotFoundError(
+			"Invalid plugin path. Paths must be valid unicode".to_owned(),
+		)
+	})?;
+	let paths = glob(pattern).map_err(|e| CuckooMinerError::ParameterError(format!("{}", e)))?;
+	let dir = plugin_dir.to_path_buf();
+	let mut configs = load_plugins(paths.filter_map(Result::ok).collect(), move |path| {
+		let name = path
+			.file_name()
+			.and_then(|f| f.to_str())
+			.map(|f| f.trim_end_matches(SO_SUFFIX))
+			.ok_or_else(|| {
+				CuckooMinerError::PluginNotFoundError(
+					"Invalid plugin path. Paths must be valid unicode".to_owned(),
+				)
+			})?;
+		load(dir.clone(), name)
+	});
+	if configs.is_empty() {
+		return Err(CuckooMinerError::NoPluginsFoundError(format!(
+			"No plugins found in {}",
+			plugin_dir.display()
+		)));
+	}
+	configs.sort_by(|a, b| a.0.file.cmp(&b.0.file));
+	Ok(configs)
+}
+
+/// Load each path on a pool of up to one thread per cpu, since opening a
+/// plugin library can be slow. Paths that fail to load, or whose loader
+/// panics, are skipped with a warning; results are in no particular order
+fn load_plugins<T, F>(paths: Vec<PathBuf>, load: F) -> Vec<T>
+where
+	T: Send + 'static,
+	F: Fn(&Path) -> Result<T, CuckooMinerError> + Send + Sync + 'static,
+{
+	let num_threads = cmp::min(num_cpus::get(), paths.len());
+	let queue = Arc::new(Mutex::new(paths.into_iter()));
+	let load = Arc::new(load);
+	let (tx, rx) = mpsc::channel();
+	let mut handles = vec![];
+	for _ in 0..num_threads {
+		let queue = queue.clone();
+		let load = load.clone();
+		let tx = tx.clone();
+		handles.push(thread::spawn(move || loop {
+			let path = match queue.lock().unwrap().next() {
+				Some(p) => p,
+				None => break,
+			};
+			match panic::catch_unwind(AssertUnwindSafe(|| load(&path))) {
+				Ok(Ok(r)) => {
+					let _ = tx.send(r);
+				}
+				Ok(Err(e)) => warn!(LOGGER, "Skipping plugin {}: {:?}", path.display(), e),
+				Err(_) => warn!(LOGGER, "Skipping plugin {}: panicked", path.display()),
+			}
+		}));
+	}
+	drop(tx);
+	for h in handles {
+		let _ = h.join();
+	}
+	rx.iter().collect()
 }
 
 /// Error type wrapping config errors.
@@ -102,3 +342,67 @@ impl From<io::Error> for ConfigError {
 		)
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::fs;
+
+	#[test]
+	fn test_skip_unloadable_plugin() {
+		let dir = std::env::temp_dir().join(format!("grin_miner_plugins_{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		for name in &["good_b", "corrupt", "good_a"] {
+			fs::write(dir.join(format!("{}{}", name, SO_SUFFIX)), b"not a library").unwrap();
+		}
+		// no plugin binaries to test with, so the good ones are faked, and
+		// the corrupt one really loaded
+		let res = find_plugins(&dir, |plugin_dir, name| {
+			if !name.starts_with("good") {
+				return PluginConfig::load(plugin_dir, name);
+			}
+			let file = plugin_dir.join(format!("{}{}", name, SO_SUFFIX));
+			let config =
+				PluginConfig::with_params(name, file.to_str().unwrap(), SolverParams::default());
+			Ok((config, PluginBuildInfo::default()))
+		});
+		let unfaked = get_available_plugins(&dir).map(|c| c.len());
+		fs::remove_dir_all(&dir).unwrap();
+		// skipped with a warning, rather than aborting enumeration
+		let names: Vec<String> = res.unwrap().into_iter().map(|(c, _)| c.name).collect();
+		assert_eq!(names, vec!["good_a", "good_b"]);
+		// and without the fakes there's nothing left
+		match unfaked {
+			Err(CuckooMinerError::NoPluginsFoundError(_)) => {}
+			r => panic!("unexpected result {:?}", r),
+		}
+	}
+
+	#[test]
+	fn test_load_plugins() {
+		let dir = std::env::temp_dir().join(format!("grin_miner_scan_{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let mut paths = vec![];
+		for i in 0..20 {
+			let path = dir.join(format!("dummy_{:02}{}", i, SO_SUFFIX));
+			fs::write(&path, format!("{}", i)).unwrap();
+			paths.push(path);
+		}
+		paths.reverse();
+		let mut found = load_plugins(paths, |path| {
+			let contents = fs::read_to_string(path).unwrap();
+			match contents.as_str() {
+				"3" => Err(CuckooMinerError::PluginNotFoundError(contents)),
+				"7" => panic!("bad plugin"),
+				_ => Ok(path.to_path_buf()),
+			}
+		});
+		fs::remove_dir_all(&dir).unwrap();
+		found.sort();
+		let expected: Vec<PathBuf> = (0..20)
+			.filter(|i| *i != 3 && *i != 7)
+			.map(|i| dir.join(format!("dummy_{:02}{}", i, SO_SUFFIX)))
+			.collect();
+		assert_eq!(found, expected);
+	}
+}
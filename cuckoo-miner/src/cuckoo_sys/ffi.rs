@@ -19,15 +19,37 @@
 //! and will not be exposed to other projects including the cuckoo-miner crate.
 
 use plugin::*;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use util::LOGGER;
 
 use libloading;
 
+use config::types::is_cpu_plugin;
 use error::CuckooMinerError;
 
-/// Struct to hold instances of loaded plugins
+/// Look up a symbol every plugin has to export
+unsafe fn required_symbol<T: Copy>(
+	lib: &libloading::Library,
+	name: &str,
+	path: &str,
+) -> Result<T, CuckooMinerError> {
+	let symbol: libloading::Symbol<T> = lib.get(format!("{}\0", name).as_bytes()).map_err(|e| {
+		CuckooMinerError::PluginSymbolNotFoundError(format!("{} in {} - {:?}", name, path, e))
+	})?;
+	Ok(*symbol.into_raw())
+}
+
+/// Look up a symbol plugins may leave out, so the plugin ABI can grow
+/// without breaking older plugins
+unsafe fn optional_symbol<T: Copy>(lib: &libloading::Library, name: &str) -> Option<T> {
+	let symbol: Option<libloading::Symbol<T>> = lib.get(format!("{}\0", name).as_bytes()).ok();
+	symbol.map(|s| *s.into_raw())
+}
 
+/// Struct to hold instances of loaded plugins. Clones share the loaded
+/// library, which stays loaded until the last of them is dropped
+#[derive(Clone)]
 pub struct PluginLibrary {
 	///The full file path to the plugin loaded by this instance
 	pub lib_full_path: String,
@@ -37,7 +59,10 @@ pub struct PluginLibrary {
 	cuckoo_destroy_solver_ctx: Arc<Mutex<CuckooDestroySolverCtx>>,
 	cuckoo_run_solver: Arc<Mutex<CuckooRunSolver>>,
 	cuckoo_stop_solver: Arc<Mutex<CuckooStopSolver>>,
-	cuckoo_fill_default_params: Arc<Mutex<CuckooFillDefaultParams>>,
+	cuckoo_fill_default_params: Option<Arc<Mutex<CuckooFillDefaultParams>>>,
+	cuckoo_plugin_build_info: Option<Arc<Mutex<CuckooPluginBuildInfo>>>,
+	cuckoo_enumerate_devices: Option<Arc<Mutex<CuckooEnumerateDevices>>>,
+	cuckoo_get_device_info: Option<Arc<Mutex<CuckooGetDeviceInfo>>>,
 }
 
 impl PluginLibrary {
@@ -64,39 +89,35 @@ impl PluginLibrary {
 		path: &str,
 	) -> Result<PluginLibrary, CuckooMinerError> {
 		unsafe {
+			// the four core symbols are mandatory
+			let lib = &loaded_library;
+			let create_solver_ctx: CuckooCreateSolverCtx =
+				required_symbol(lib, "create_solver_ctx", path)?;
+			let destroy_solver_ctx: CuckooDestroySolverCtx =
+				required_symbol(lib, "destroy_solver_ctx", path)?;
+			let run_solver: CuckooRunSolver = required_symbol(lib, "run_solver", path)?;
+			let stop_solver: CuckooStopSolver = required_symbol(lib, "stop_solver", path)?;
+			// optional, defaults are filled in crate-side if not provided
+			let fill_default_params: Option<CuckooFillDefaultParams> =
+				optional_symbol(lib, "fill_default_params");
+			// optional, older plugins don't report where they were built from
+			let plugin_build_info: Option<CuckooPluginBuildInfo> =
+				optional_symbol(lib, "plugin_build_info");
+			let enumerate_devices: Option<CuckooEnumerateDevices> =
+				optional_symbol(lib, "enumerate_devices");
+			let get_device_info: Option<CuckooGetDeviceInfo> =
+				optional_symbol(lib, "get_device_info");
+
 			let ret_val = PluginLibrary {
 				lib_full_path: String::from(path),
-
-				cuckoo_create_solver_ctx: {
-					let cuckoo_create_solver_ctx: libloading::Symbol<CuckooCreateSolverCtx> =
-						loaded_library.get(b"create_solver_ctx\0").unwrap();
-					Arc::new(Mutex::new(*cuckoo_create_solver_ctx.into_raw()))
-				},
-
-				cuckoo_destroy_solver_ctx: {
-					let cuckoo_destroy_solver_ctx: libloading::Symbol<CuckooDestroySolverCtx> =
-						loaded_library.get(b"destroy_solver_ctx\0").unwrap();
-					Arc::new(Mutex::new(*cuckoo_destroy_solver_ctx.into_raw()))
-				},
-
-				cuckoo_run_solver: {
-					let cuckoo_run_solver: libloading::Symbol<CuckooRunSolver> =
-						loaded_library.get(b"run_solver\0").unwrap();
-					Arc::new(Mutex::new(*cuckoo_run_solver.into_raw()))
-				},
-
-				cuckoo_stop_solver: {
-					let cuckoo_stop_solver: libloading::Symbol<CuckooStopSolver> =
-						loaded_library.get(b"stop_solver\0").unwrap();
-					Arc::new(Mutex::new(*cuckoo_stop_solver.into_raw()))
-				},
-
-				cuckoo_fill_default_params: {
-					let cuckoo_fill_default_params: libloading::Symbol<CuckooFillDefaultParams> =
-						loaded_library.get(b"fill_default_params\0").unwrap();
-					Arc::new(Mutex::new(*cuckoo_fill_default_params.into_raw()))
-				},
-
+				cuckoo_create_solver_ctx: Arc::new(Mutex::new(create_solver_ctx)),
+				cuckoo_destroy_solver_ctx: Arc::new(Mutex::new(destroy_solver_ctx)),
+				cuckoo_run_solver: Arc::new(Mutex::new(run_solver)),
+				cuckoo_stop_solver: Arc::new(Mutex::new(stop_solver)),
+				cuckoo_fill_default_params: fill_default_params.map(|f| Arc::new(Mutex::new(f))),
+				cuckoo_plugin_build_info: plugin_build_info.map(|f| Arc::new(Mutex::new(f))),
+				cuckoo_enumerate_devices: enumerate_devices.map(|f| Arc::new(Mutex::new(f))),
+				cuckoo_get_device_info: get_device_info.map(|f| Arc::new(Mutex::new(f))),
 				loaded_library: Arc::new(Mutex::new(loaded_library)),
 			};
 
@@ -130,8 +151,25 @@ impl PluginLibrary {
 		let cuckoo_stop_solver_ref = self.cuckoo_stop_solver.lock().unwrap();
 		drop(cuckoo_stop_solver_ref);
 
-		let cuckoo_fill_default_params_ref = self.cuckoo_fill_default_params.lock().unwrap();
-		drop(cuckoo_fill_default_params_ref);
+		if let Some(f) = &self.cuckoo_fill_default_params {
+			let cuckoo_fill_default_params_ref = f.lock().unwrap();
+			drop(cuckoo_fill_default_params_ref);
+		}
+
+		if let Some(f) = &self.cuckoo_plugin_build_info {
+			let cuckoo_plugin_build_info_ref = f.lock().unwrap();
+			drop(cuckoo_plugin_build_info_ref);
+		}
+
+		if let Some(f) = &self.cuckoo_enumerate_devices {
+			let cuckoo_enumerate_devices_ref = f.lock().unwrap();
+			drop(cuckoo_enumerate_devices_ref);
+		}
+
+		if let Some(f) = &self.cuckoo_get_device_info {
+			let cuckoo_get_device_info_ref = f.lock().unwrap();
+			drop(cuckoo_get_device_info_ref);
+		}
 
 		let loaded_library_ref = self.loaded_library.lock().unwrap();
 		drop(loaded_library_ref);
@@ -179,13 +217,73 @@ impl PluginLibrary {
 		unsafe { call_ref(ctx) }
 	}
 
-	/// Get default params
+	/// Get default params, as filled by the plugin. Falls back to sensible
+	/// crate-side defaults for any params the plugin doesn't fill in
 	pub fn get_default_params(&self) -> SolverParams {
 		let mut ret_params = SolverParams::default();
-		let call_ref = self.cuckoo_fill_default_params.lock().unwrap();
-		unsafe {
-			call_ref(&mut ret_params);
-			ret_params
+		match &self.cuckoo_fill_default_params {
+			Some(f) => {
+				let call_ref = f.lock().unwrap();
+				unsafe { call_ref(&mut ret_params) }
+			}
+			None => warn!(
+				LOGGER,
+				"Plugin {} doesn't provide default params, using built-in defaults",
+				self.lib_full_path
+			),
+		}
+		self.fill_fallback_params(&mut ret_params);
+		ret_params
+	}
+
+	/// Get the plugin's build info, left as "unknown" if the plugin doesn't
+	/// provide it
+	pub fn get_build_info(&self) -> PluginBuildInfo {
+		let mut info = PluginBuildInfo::default();
+		if let Some(f) = &self.cuckoo_plugin_build_info {
+			let call_ref = f.lock().unwrap();
+			unsafe { call_ref(&mut info) }
+		}
+		info
+	}
+
+	/// List the devices the plugin can mine on, None if the plugin can't
+	/// list them
+	pub fn enumerate_devices(&self) -> Option<Vec<DeviceInfo>> {
+		let f = self.cuckoo_enumerate_devices.as_ref()?;
+		let mut list = DeviceList::default();
+		let call_ref = f.lock().unwrap();
+		unsafe { call_ref(&mut list) }
+		Some(list.devices().to_vec())
+	}
+
+	/// Sensor readings of the solver's device, None if the plugin can't
+	/// read them
+	pub fn get_device_info(&self, ctx: *mut SolverCtx) -> Option<DeviceSensors> {
+		let f = self.cuckoo_get_device_info.as_ref()?;
+		let mut sensors = DeviceSensors::default();
+		let call_ref = f.lock().unwrap();
+		unsafe { call_ref(ctx, &mut sensors) }
+		Some(sensors)
+	}
+
+	/// Fill in params left zeroed by the plugin that would otherwise be
+	/// passed to the solver as-is
+	fn fill_fallback_params(&self, params: &mut SolverParams) {
+		// cpu solvers won't do anything with zero threads
+		let file_name = Path::new(&self.lib_full_path)
+			.file_name()
+			.and_then(|f| f.to_str())
+			.unwrap_or("");
+		if params.nthreads == 0 && is_cpu_plugin(file_name) {
+			debug!(
+				LOGGER,
+				"Plugin {} left nthreads unset, defaulting to 1", self.lib_full_path
+			);
+			params.nthreads = 1;
+		}
+		if params.edge_bits == 0 {
+			params.edge_bits = SolverParams::default().edge_bits;
 		}
 	}
 
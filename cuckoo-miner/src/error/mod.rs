@@ -53,6 +53,9 @@ pub enum CuckooMinerError {
 
 	/// Error getting stats or stats not implemented
 	StatsError(String),
+
+	/// Error from a mining pipeline's job source
+	PipelineError(String),
 }
 
 impl From<io::Error> for CuckooMinerError {
@@ -37,12 +37,14 @@ extern crate serde_json;
 
 extern crate blake2_rfc as blake2;
 extern crate byteorder;
+extern crate core_affinity;
 extern crate crypto;
 extern crate rand;
 extern crate regex;
 
 extern crate libc;
 extern crate libloading as libloading;
+extern crate num_cpus;
 
 #[macro_use]
 extern crate slog;
@@ -54,7 +56,13 @@ mod cuckoo_sys;
 mod error;
 mod miner;
 
-pub use config::types::PluginConfig;
+pub use config::types::{get_available_plugins, is_cpu_plugin, PluginConfig};
 pub use cuckoo_sys::ffi::PluginLibrary;
 pub use error::CuckooMinerError;
-pub use miner::miner::CuckooMiner;
+pub use miner::consensus::{
+	expected_secs_per_share, header_siphash_keys, is_plausible_proof, DifficultyScaling, Proof,
+};
+pub use miner::miner::{CuckooMiner, DEFAULT_SHUTDOWN_TIMEOUT_SECS};
+pub use miner::pipeline::{Job, JobSource, Miner, Share, ShareSink};
+pub use miner::types::{DeviceStats, FoundSolutions, SolverState};
+pub use plugin::DeviceInfo;
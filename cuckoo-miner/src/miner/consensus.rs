@@ -15,11 +15,69 @@
 /// Difficulty calculation as from Grin
 use blake2::blake2b::Blake2b;
 use byteorder::{BigEndian, ByteOrder};
+use miner::util::header_data;
+use miner::verify;
+use plugin::PROOFSIZE;
 use std::cmp::{max, min};
 use std::fmt;
 
 // constants from grin
-const PROOF_SIZE: usize = 42;
+const BASE_EDGE_BITS: u8 = 24;
+const WEEK_HEIGHT: u64 = 7 * 24 * 60;
+const YEAR_HEIGHT: u64 = 52 * WEEK_HEIGHT;
+
+/// How a solution's difficulty is scaled before comparing it to the target,
+/// as cuckaroo and cuckatoo shares are weighted differently in grin
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum DifficultyScaling {
+	/// No scaling, as used by grin's stratum server for shares
+	#[default]
+	Unscaled,
+	/// Cuckatoo (primary PoW), scaled by graph weight
+	Cuckatoo,
+	/// Cuckaroo (secondary PoW), scaled by the header's secondary scaling
+	Cuckaroo,
+}
+
+impl DifficultyScaling {
+	/// Factor a proof's difficulty is scaled by
+	pub fn scale(self, height: u64, edge_bits: u8, secondary_scaling: u32) -> u64 {
+		match self {
+			DifficultyScaling::Unscaled => 1,
+			DifficultyScaling::Cuckatoo => graph_weight(height, edge_bits),
+			DifficultyScaling::Cuckaroo => secondary_scaling as u64,
+		}
+	}
+}
+
+/// Graph weight of a cuckatoo graph of the given size, as in grin
+pub fn graph_weight(height: u64, edge_bits: u8) -> u64 {
+	let mut xpr_edge_bits = edge_bits as u64;
+	if edge_bits == 31 && height >= YEAR_HEIGHT {
+		xpr_edge_bits = xpr_edge_bits.saturating_sub(1 + (height - YEAR_HEIGHT) / WEEK_HEIGHT);
+	}
+	(2u64 << (edge_bits.saturating_sub(BASE_EDGE_BITS)) as u64) * xpr_edge_bits
+}
+
+/// Estimated seconds between shares when searching `gps` graphs per second
+/// against the target difficulty. A graph holds a 42-cycle about once in 42
+/// tries, and a solution meets the target with probability scale / target.
+/// None if there's no rate or target to go by
+pub fn expected_secs_per_share(
+	gps: f64,
+	edge_bits: u8,
+	height: u64,
+	target_difficulty: u64,
+	scaling: DifficultyScaling,
+	secondary_scaling: u32,
+) -> Option<f64> {
+	let scale = scaling.scale(height, edge_bits, secondary_scaling);
+	if gps <= 0.0 || !gps.is_finite() || target_difficulty == 0 || scale == 0 {
+		return None;
+	}
+	let share_chance = (scale as f64 / target_difficulty as f64).min(1.0);
+	Some(PROOFSIZE as f64 / (gps * share_chance))
+}
 
 /// The difficulty is defined as the maximum target divided by the block hash.
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
@@ -90,10 +148,10 @@ impl Proof {
 		min(diff, <u64>::max_value() as u128) as u64
 	}
 
-	/// Hash, as in Grin
-	fn hash(&self) -> Hash {
+	/// Packs the nonces at their exact bit size, as grin serializes them
+	pub fn pack_nonces(&self) -> Vec<u8> {
 		let nonce_bits = self.edge_bits as usize;
-		let mut bitvec = BitVec::new(nonce_bits * PROOF_SIZE);
+		let mut bitvec = BitVec::new(nonce_bits * PROOFSIZE);
 		for (n, nonce) in self.nonces.iter().enumerate() {
 			for bit in 0..nonce_bits {
 				if nonce & (1 << bit) != 0 {
@@ -101,8 +159,13 @@ impl Proof {
 				}
 			}
 		}
+		bitvec.bits
+	}
+
+	/// Hash, as in Grin
+	fn hash(&self) -> Hash {
 		let mut blake2b = Blake2b::new(32);
-		blake2b.update(&bitvec.bits);
+		blake2b.update(&self.pack_nonces());
 		let mut ret = [0; 32];
 		ret.copy_from_slice(blake2b.finalize().as_bytes());
 		Hash(ret)
@@ -112,6 +175,48 @@ impl Proof {
 	pub fn to_difficulty_unscaled(&self) -> Difficulty {
 		Difficulty::from_proof_unscaled(&self)
 	}
+
+	/// Difficulty of a primary (cuckatoo) proof scaled by its graph weight at
+	/// the given height, as grin weighs it against the network difficulty.
+	/// Secondary (cuckaroo) proofs are scaled by the header's secondary
+	/// scaling instead, see `scaled_difficulty_with`
+	pub fn to_difficulty(&self, height: u64) -> Difficulty {
+		self.scaled_difficulty_with(DifficultyScaling::Cuckatoo, height, 0)
+	}
+
+	/// Difficulty of the proof with the given scaling applied
+	pub fn scaled_difficulty_with(
+		&self,
+		scaling: DifficultyScaling,
+		height: u64,
+		secondary_scaling: u32,
+	) -> Difficulty {
+		let scale = scaling.scale(height, self.edge_bits, secondary_scaling);
+		Difficulty::from_num(self.scaled_difficulty(scale))
+	}
+
+	/// Check the nonces form a valid 42-cycle in the graph generated by the
+	/// given siphash keys, with the given algorithm's edge hashing.
+	/// Algorithms without a verifier here are taken as valid
+	pub fn verify(&self, siphash_keys: &[u64; 4], algorithm: &str) -> Result<(), String> {
+		match algorithm {
+			"cuckatoo" => verify::verify_cuckatoo(siphash_keys, self.edge_bits, &self.nonces),
+			"cuckaroo" => verify::verify_cuckaroo(siphash_keys, self.edge_bits, &self.nonces),
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Whether a proof looks like a real one, rather than a zeroed or cut short
+/// buffer from a misbehaving plugin
+pub fn is_plausible_proof(proof: &[u64]) -> bool {
+	proof.len() == PROOFSIZE && proof.iter().any(|n| *n != 0)
+}
+
+/// Siphash keys of the header for the given pre-pow and nonce
+pub fn header_siphash_keys(pre_pow: &str, nonce: u64) -> [u64; 4] {
+	let (header, _) = header_data(pre_pow, "", nonce);
+	verify::siphash_keys(&header)
 }
 
 struct BitVec {
@@ -166,3 +271,95 @@ impl fmt::Display for Hash {
 		fmt::Debug::fmt(self, f)
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn proof(edge_bits: u8, first_nonce: u64) -> Proof {
+		Proof {
+			edge_bits,
+			nonces: (0..PROOFSIZE as u64)
+				.map(|i| first_nonce + i * 1000)
+				.collect(),
+		}
+	}
+
+	#[test]
+	fn test_plausible_proof() {
+		let proof: Vec<u64> = (1..PROOFSIZE as u64 + 1).collect();
+		assert!(is_plausible_proof(&proof));
+		assert!(!is_plausible_proof(&proof[1..]));
+		assert!(!is_plausible_proof(&[0; PROOFSIZE]));
+		assert!(!is_plausible_proof(&[]));
+	}
+
+	#[test]
+	fn test_pack_nonces() {
+		let p = Proof {
+			edge_bits: 29,
+			nonces: (0..PROOFSIZE as u64).collect(),
+		};
+		let packed = p.pack_nonces();
+		assert_eq!(packed.len(), (29 * PROOFSIZE + 7) / 8);
+		// nonce 1 sets bit 29, nonce 2 bit 59, nonce 3 bits 87 and 88
+		assert_eq!(
+			util::to_hex(packed[..12].to_vec()),
+			"000000200000000800008001"
+		);
+	}
+
+	// expected hashes computed separately with blake2b-256 over the proof
+	// packed as grin serializes it
+	#[test]
+	fn test_proof_difficulty() {
+		let p = proof(29, 4021);
+		assert_eq!(
+			p.hash().to_hex(),
+			"0007c042556cfab5d985cf7c58f7fcc372fe8809e616a1df4395f5a97736f356"
+		);
+		assert_eq!(p.to_difficulty_unscaled().to_num(), 8455);
+
+		let p = proof(31, 6432);
+		assert_eq!(
+			p.hash().to_hex(),
+			"0009fff63f52bd07b1ca1d744a19c86a2468238a5e5b3cb0a158cb3499723622"
+		);
+		assert_eq!(p.to_difficulty_unscaled().to_num(), 6553);
+		assert_eq!(
+			p.scaled_difficulty_with(DifficultyScaling::Unscaled, 1, 100)
+				.to_num(),
+			6553
+		);
+		assert_eq!(
+			p.scaled_difficulty_with(DifficultyScaling::Cuckatoo, 1, 100)
+				.to_num(),
+			52010143
+		);
+		assert_eq!(
+			p.scaled_difficulty_with(DifficultyScaling::Cuckaroo, 1, 100)
+				.to_num(),
+			655369
+		);
+
+		// graph weight of a cuckatoo31 proof drops a week after the first year
+		assert_eq!(p.to_difficulty(1).to_num(), 52010143);
+		assert_eq!(
+			p.to_difficulty(YEAR_HEIGHT + WEEK_HEIGHT).to_num(),
+			48654650
+		);
+
+		// hash well above the minimum target still has a difficulty of 1
+		assert_eq!(proof(29, 0).to_difficulty_unscaled().to_num(), 1);
+	}
+
+	#[test]
+	fn test_graph_weight() {
+		assert_eq!(graph_weight(1, 29), 64 * 29);
+		assert_eq!(graph_weight(1, 31), 256 * 31);
+		assert_eq!(graph_weight(1, 32), 512 * 32);
+		assert_eq!(graph_weight(YEAR_HEIGHT, 31), 256 * 30);
+		assert_eq!(graph_weight(YEAR_HEIGHT + WEEK_HEIGHT, 31), 256 * 29);
+		assert_eq!(graph_weight(YEAR_HEIGHT, 32), 512 * 32);
+	}
+}
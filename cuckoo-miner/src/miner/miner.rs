@@ -16,21 +16,203 @@
 //! to load a mining plugin, send it a Cuckoo Cycle POW problem, and
 //! return any resulting solutions.
 
-use std::ptr::NonNull;
-use std::sync::{mpsc, Arc, RwLock};
+use std::ptr::{self, NonNull};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
 use std::{thread, time};
 use util::LOGGER;
 
 use config::types::PluginConfig;
-use miner::types::{JobSharedData, JobSharedDataType, SolverInstance};
+use miner::types::{
+	DeviceStats, FoundSolutions, JobSharedData, JobSharedDataType, SolverCtxCache,
+	SolverCtxCacheType, SolverInstance, SolverState, MAX_CACHED_CTXS,
+};
 
-use miner::consensus::Proof;
+use miner::consensus::{DifficultyScaling, Proof};
 use miner::util;
-use plugin::{Solution, SolverCtxWrapper, SolverSolutions, SolverStats};
+use plugin::{Solution, SolverCtx, SolverCtxWrapper, SolverSolutions};
 use {CuckooMinerError, PluginLibrary};
 
+/// Solutions meeting the target difficulty, with their computed difficulty
+fn filter_solutions(
+	solutions: &SolverSolutions,
+	scaling: DifficultyScaling,
+	height: u64,
+	sec_scaling: u32,
+	target_difficulty: u64,
+) -> Vec<(Solution, u64)> {
+	solutions
+		.sols
+		.iter()
+		.take(solutions.num_sols as usize)
+		.map(|s| {
+			let proof = Proof {
+				edge_bits: solutions.edge_bits as u8,
+				nonces: s.proof.to_vec(),
+			};
+			let difficulty = proof
+				.scaled_difficulty_with(scaling, height, sec_scaling)
+				.to_num();
+			(*s, difficulty)
+		})
+		.filter(|(_, difficulty)| *difficulty >= target_difficulty)
+		.collect()
+}
+
+/// Pin the calling thread to the first of the given core indices the OS
+/// reports, warning and leaving it unpinned if none of them can be used
+fn pin_to_cores(instance: usize, cores: &[usize]) {
+	let available = core_affinity::get_core_ids().unwrap_or_default();
+	let core = cores
+		.iter()
+		.filter_map(|c| available.iter().find(|id| id.id == *c))
+		.next();
+	match core {
+		Some(id) if core_affinity::set_for_current(*id) => {
+			info!(LOGGER, "Solver {} pinned to CPU core {}", instance, id.id);
+		}
+		_ => warn!(
+			LOGGER,
+			"Solver {} could not be pinned to CPU cores {:?}, running unpinned", instance, cores
+		),
+	}
+}
+
+/// First wait before retrying a failed solver context creation, doubled
+/// on each failure
+const CTX_RETRY_INITIAL_MS: u64 = 500;
+
+/// Longest wait between solver context creation attempts
+const CTX_RETRY_MAX_MS: u64 = 60_000;
+
+/// Times in a row a solver is restarted after its plugin reports an error,
+/// before the device is left errored
+const MAX_ERROR_RESTARTS: u32 = 5;
+
+/// Wait before restarting a solver that has errored `restarts` times in a
+/// row, doubling each time. None once it's been restarted too often
+fn error_restart_backoff(restarts: u32) -> Option<time::Duration> {
+	if restarts >= MAX_ERROR_RESTARTS {
+		return None;
+	}
+	let ms = CTX_RETRY_INITIAL_MS << restarts;
+	Some(time::Duration::from_millis(std::cmp::min(
+		ms,
+		CTX_RETRY_MAX_MS,
+	)))
+}
+
+/// Wait for `wait`, applying pause and resume messages received meanwhile
+/// to `paused`. Returns false if told to stop
+fn wait_for_control(
+	solver_loop_rx: &mpsc::Receiver<ControlMessage>,
+	paused: &mut bool,
+	wait: time::Duration,
+) -> bool {
+	let deadline = time::Instant::now() + wait;
+	loop {
+		let now = time::Instant::now();
+		if now >= deadline {
+			return true;
+		}
+		match solver_loop_rx.recv_timeout(deadline - now) {
+			Ok(ControlMessage::Stop) => return false,
+			Ok(ControlMessage::Pause) => *paused = true,
+			Ok(ControlMessage::Resume) => *paused = false,
+			Ok(_) => {}
+			Err(mpsc::RecvTimeoutError::Timeout) => return true,
+			Err(mpsc::RecvTimeoutError::Disconnected) => return false,
+		}
+	}
+}
+
+/// Create a solver context, retrying with backoff while the plugin fails to
+/// (device busy, out of memory, ...), with the device marked errored in the
+/// meantime. Pause and resume messages received while waiting are applied
+/// to `paused`. Returns None if told to stop before a context is created
+fn create_ctx_with_backoff<F>(
+	mut create: F,
+	instance: usize,
+	shared_data: &JobSharedDataType,
+	solver_loop_rx: &mpsc::Receiver<ControlMessage>,
+	paused: &mut bool,
+	initial_backoff: time::Duration,
+) -> Option<*mut SolverCtx>
+where
+	F: FnMut() -> *mut SolverCtx,
+{
+	let mut backoff = initial_backoff;
+	loop {
+		let ctx = create();
+		if !ctx.is_null() {
+			let mut s = shared_data.write().unwrap();
+			if let Some(st) = s.stats.get_mut(instance) {
+				st.has_errored = false;
+				st.set_error_reason("");
+			}
+			return Some(ctx);
+		}
+		warn!(
+			LOGGER,
+			"Solver {} failed to create a context for its device, retrying in {:?}",
+			instance,
+			backoff
+		);
+		{
+			let mut s = shared_data.write().unwrap();
+			if let Some(st) = s.stats.get_mut(instance) {
+				st.has_errored = true;
+				st.set_error_reason("Failed to create solver context");
+			}
+		}
+		if !wait_for_control(solver_loop_rx, paused, backoff) {
+			return None;
+		}
+		backoff = std::cmp::min(backoff * 2, time::Duration::from_millis(CTX_RETRY_MAX_MS));
+	}
+}
+
+/// Seconds a working solver can go without completing a graph before
+/// `solver_states` reports it as hung
+const SOLVER_HUNG_SECS: u64 = 120;
+
+/// Default seconds to wait for solvers to exit on shutdown
+pub const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 10;
+
+/// Wait until `timeout` for each solver thread to report it has stopped,
+/// joining those that have. Solvers still running, e.g. stuck in their
+/// plugin, are detached and their instances returned
+fn wait_for_stopped(
+	stopped_rxs: &[mpsc::Receiver<ControlMessage>],
+	handles: Vec<thread::JoinHandle<()>>,
+	timeout: time::Duration,
+) -> Vec<usize> {
+	let deadline = time::Instant::now() + timeout;
+	let mut stuck = vec![];
+	for (i, (r, h)) in stopped_rxs.iter().zip(handles).enumerate() {
+		let stopped = loop {
+			let wait = deadline.saturating_duration_since(time::Instant::now());
+			match r.recv_timeout(wait) {
+				Ok(ControlMessage::SolverStopped(s)) => {
+					debug!(LOGGER, "Solver stopped: {}", s);
+					break true;
+				}
+				Ok(_) => {}
+				Err(mpsc::RecvTimeoutError::Timeout) => break false,
+				// the thread has gone without reporting, e.g. on a panic
+				Err(mpsc::RecvTimeoutError::Disconnected) => break true,
+			}
+		};
+		if stopped {
+			let _ = h.join();
+		} else {
+			stuck.push(i);
+		}
+	}
+	stuck
+}
+
 /// Miner control Messages
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum ControlMessage {
 	/// Stop everything, pull down, exis
 	Stop,
@@ -46,9 +228,16 @@ enum ControlMessage {
 /// and calls its mine function according to the provided configuration
 
 pub struct CuckooMiner {
-	/// Configurations
+	/// Configurations of the running solvers, one per solver. A GPU that
+	/// failed to initialize is represented by its CPU fallback
 	configs: Vec<PluginConfig>,
 
+	/// Configurations as given, before any CPU fallback replaced them
+	plugin_configs: Vec<PluginConfig>,
+
+	/// Index of the solver running each of plugin_configs
+	solver_slots: Vec<usize>,
+
 	/// Data shared across threads
 	pub shared_data: Arc<RwLock<JobSharedData>>,
 
@@ -60,6 +249,31 @@ pub struct CuckooMiner {
 
 	/// Solver has stopped and cleanly shutdown
 	solver_stopped_rxs: Vec<mpsc::Receiver<ControlMessage>>,
+
+	/// Solver threads, joined on shutdown
+	solver_threads: Vec<thread::JoinHandle<()>>,
+
+	/// Solver contexts handed back by exited solvers, for their restarted
+	/// or reconfigured replacements to reuse
+	ctx_cache: SolverCtxCacheType,
+
+	/// Mining paused by the caller, solvers won't be resumed on new jobs
+	paused: bool,
+
+	/// Individual devices paused by the caller
+	paused_devices: Vec<bool>,
+
+	/// Where each running solver's plugin was built from
+	build_info: Vec<String>,
+
+	/// Pause between initializing each solver's device
+	start_stagger: time::Duration,
+
+	/// Longest wait for solvers to exit on shutdown
+	shutdown_timeout: time::Duration,
+
+	/// When the miner was created
+	start_time: time::Instant,
 }
 
 impl CuckooMiner {
@@ -69,11 +283,21 @@ impl CuckooMiner {
 	pub fn new(configs: Vec<PluginConfig>) -> CuckooMiner {
 		let len = configs.len();
 		CuckooMiner {
-			configs,
+			configs: configs.clone(),
+			plugin_configs: configs,
+			solver_slots: (0..len).collect(),
 			shared_data: Arc::new(RwLock::new(JobSharedData::new(len))),
 			control_txs: vec![],
 			solver_loop_txs: vec![],
 			solver_stopped_rxs: vec![],
+			solver_threads: vec![],
+			ctx_cache: Arc::new(Mutex::new(SolverCtxCache::new(MAX_CACHED_CTXS))),
+			paused: false,
+			paused_devices: vec![false; len],
+			build_info: vec![],
+			start_stagger: time::Duration::from_millis(0),
+			shutdown_timeout: time::Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT_SECS),
+			start_time: time::Instant::now(),
 		}
 	}
 
@@ -86,36 +310,80 @@ impl CuckooMiner {
 		solver_loop_rx: mpsc::Receiver<ControlMessage>,
 		solver_stopped_tx: mpsc::Sender<ControlMessage>,
 	) {
+		if let Some(ref cores) = solver.config.cpu_affinity {
+			pin_to_cores(instance, cores);
+		}
 		{
 			let mut s = shared_data.write().unwrap();
 			s.stats[instance].set_plugin_name(&solver.config.name);
+			// carry over start time and restarts if this slot ran a solver before
+			s.stats[instance].mark_started();
+			s.snapshot_iterations(instance);
 		}
-		// "Detach" a stop function from the solver, to let us keep a control thread going
-		let ctx = solver.lib.create_solver_ctx(&mut solver.config.params);
-		let control_ctx = SolverCtxWrapper(NonNull::new(ctx).unwrap());
+		let mut paused = true;
+		// the plugin may fail to create a context, e.g. if the device is busy,
+		// and must never be run without one
+		let mut ctx = match create_ctx_with_backoff(
+			|| solver.acquire_ctx(),
+			instance,
+			&shared_data,
+			&solver_loop_rx,
+			&mut paused,
+			time::Duration::from_millis(CTX_RETRY_INITIAL_MS),
+		) {
+			Some(c) => c,
+			None => {
+				solver.unload();
+				let _ = solver_stopped_tx.send(ControlMessage::SolverStopped(instance));
+				return;
+			}
+		};
+		// "Detach" a stop function from the solver, to let us keep a control
+		// thread going. The context is swapped out if the solver is restarted
+		// after an error
+		let control_ctx = Arc::new(Mutex::new(Some(SolverCtxWrapper(
+			NonNull::new(ctx).unwrap(),
+		))));
+		let stop_ctx = control_ctx.clone();
 
 		let stop_fn = solver.lib.get_stop_solver_instance();
 
 		// monitor whether to send a stop signal to the solver, which should
 		// end the current solve attempt below
 		let stop_handle = thread::spawn(move || loop {
-			let ctx_ptr = control_ctx.0.as_ptr();
 			while let Some(message) = control_rx.iter().next() {
-				match message {
-					ControlMessage::Stop => {
-						PluginLibrary::stop_solver_from_instance(stop_fn.clone(), ctx_ptr);
-						return;
-					}
-					ControlMessage::Pause => {
-						PluginLibrary::stop_solver_from_instance(stop_fn.clone(), ctx_ptr);
-					}
-					_ => {}
+				let stop = match message {
+					ControlMessage::Stop => true,
+					ControlMessage::Pause => false,
+					_ => continue,
 				};
+				if let Some(ref c) = *stop_ctx.lock().unwrap() {
+					PluginLibrary::stop_solver_from_instance(stop_fn.clone(), c.0.as_ptr());
+				}
+				if stop {
+					return;
+				}
 			}
 		});
+		let mut error_restarts = 0;
 
 		let mut iter_count = 0;
-		let mut paused = true;
+		// sequential nonces restart from zero for each job, with solvers
+		// interleaved so they don't cover the same nonces
+		let mut nonce_job = (0, 0);
+		let mut nonce_count: u64 = 0;
+		// each instance gets its own sequence from a seed
+		let seed = solver
+			.config
+			.nonce_seed
+			.map(|s| s.wrapping_add(instance as u64));
+		if let Some(s) = seed {
+			warn!(
+				LOGGER,
+				"Solver {} using seeded nonces ({}), for debugging only", instance, s
+			);
+		}
+		let mut nonces = util::NonceSource::new(seed);
 		loop {
 			if let Some(message) = solver_loop_rx.try_iter().next() {
 				debug!(
@@ -137,102 +405,422 @@ impl CuckooMiner {
 				let mut s = shared_data.write().unwrap();
 				s.stats[instance].set_plugin_name(&solver.config.name);
 			}
-			let header_pre = { shared_data.read().unwrap().pre_nonce.clone() };
-			let header_post = { shared_data.read().unwrap().post_nonce.clone() };
-			let height = { shared_data.read().unwrap().height };
-			let job_id = { shared_data.read().unwrap().job_id };
-			let target_difficulty = { shared_data.read().unwrap().difficulty };
-			let header = util::get_next_header_data(&header_pre, &header_post);
+			// both halves of the header from the same job, under one lock
+			let (header_pre, header_post, height, job_id) = {
+				let s = shared_data.read().unwrap();
+				(
+					s.pre_nonce.clone(),
+					s.post_nonce.clone(),
+					s.height,
+					s.job_id,
+				)
+			};
+			let header = if solver.config.sequential_nonce {
+				if nonce_job != (height, job_id) {
+					nonce_job = (height, job_id);
+					nonce_count = 0;
+				}
+				let num_solvers = { shared_data.read().unwrap().stats.len() } as u64;
+				let nonce = nonce_count * num_solvers + instance as u64;
+				nonce_count += 1;
+				util::get_header_data(&header_pre, &header_post, nonce)
+			} else {
+				util::get_next_header_data(&header_pre, &header_post, &mut nonces)
+			};
 			let nonce = header.0;
-			//let sec_scaling = header.2;
+			let sec_scaling = header.2;
+			let mutate_nonce = solver.config.params.mutate_nonce;
+			let plugin_nonce = util::solver_nonce(nonce, mutate_nonce);
+			let range = if mutate_nonce {
+				solver.config.solver_range(solver.stats.last_solution_time)
+			} else {
+				1
+			};
 			solver.lib.run_solver(
 				ctx,
 				header.1,
-				0,
-				1,
+				plugin_nonce,
+				range,
 				&mut solver.solutions,
 				&mut solver.stats,
 			);
 			iter_count += 1;
+			if let Some(sensors) = solver.lib.get_device_info(ctx) {
+				solver.stats.set_sensors(&sensors);
+			}
+			// solutions are submitted at the size of the device that found
+			// them, falling back to the configured size if not reported
+			if solver.solutions.edge_bits == 0 {
+				solver.solutions.edge_bits = solver.config.params.edge_bits;
+			}
 			let still_valid = { height == shared_data.read().unwrap().height };
 			if still_valid {
 				let mut s = shared_data.write().unwrap();
-				s.stats[instance] = solver.stats.clone();
+				s.stats[instance].solver = solver.stats.clone();
 				s.stats[instance].iterations = iter_count;
+				s.stats[instance].graphs_since_share += 1;
 				if solver.solutions.num_sols > 0 {
-					// Filter solutions that don't meet difficulty check
-					let mut filtered_sols: Vec<Solution> = vec![];
-					for i in 0..solver.solutions.num_sols {
-						filtered_sols.push(solver.solutions.sols[i as usize]);
-					}
-					let mut filtered_sols: Vec<Solution> = filtered_sols
-						.iter()
-						.filter(|s| {
-							let proof = Proof {
-								edge_bits: solver.solutions.edge_bits as u8,
-								nonces: s.proof.to_vec(),
-							};
-							proof.to_difficulty_unscaled().to_num() >= target_difficulty
-						})
-						.cloned()
-						.collect();
-					for mut ss in filtered_sols.iter_mut() {
-						ss.nonce = nonce;
+					// Filter solutions that don't meet difficulty check, going by
+					// the current target as it may have changed mid-solve
+					let mut filtered_sols = filter_solutions(
+						&solver.solutions,
+						solver.config.scaling,
+						height,
+						sec_scaling,
+						s.difficulty,
+					);
+					for (ss, _) in filtered_sols.iter_mut() {
+						// plugins mutating the nonce report the one they tried
+						ss.nonce = util::solution_nonce(nonce, mutate_nonce, ss.nonce);
 						ss.id = job_id as u64;
 					}
-					solver.solutions.num_sols = filtered_sols.len() as u32;
+					let mut found = FoundSolutions {
+						solutions: solver.solutions,
+						instance: instance as u32,
+						height,
+						..FoundSolutions::default()
+					};
+					found.num_sols = filtered_sols.len() as u32;
 					for (i, _) in filtered_sols
 						.iter()
 						.enumerate()
-						.take(solver.solutions.num_sols as usize)
+						.take(found.num_sols as usize)
 					{
-						solver.solutions.sols[i] = filtered_sols[i];
+						found.sols[i] = filtered_sols[i].0;
+						found.difficulties[i] = filtered_sols[i].1;
 					}
-					s.solutions.push(solver.solutions.clone());
+					s.solutions.push(found);
 				}
-				if s.stats[instance].has_errored {
-					s.stats[instance].set_plugin_name(&solver.config.name);
-					error!(
-						LOGGER,
-						"Plugin {} has errored, device: {}. Reason: {}",
-						s.stats[instance].get_plugin_name(),
-						s.stats[instance].get_device_name(),
-						s.stats[instance].get_error_reason(),
-					);
+			}
+			if solver.stats.has_errored {
+				solver.stats.set_plugin_name(&solver.config.name);
+				error!(
+					LOGGER,
+					"Plugin {} has errored, device: {}. Reason: {}",
+					solver.stats.get_plugin_name(),
+					solver.stats.get_device_name(),
+					solver.stats.get_error_reason(),
+				);
+				// the context may be broken, replace it with a new one
+				*control_ctx.lock().unwrap() = None;
+				solver.lib.destroy_solver_ctx(ctx);
+				ctx = ptr::null_mut();
+				let backoff = match error_restart_backoff(error_restarts) {
+					Some(b) => b,
+					None => {
+						error!(
+							LOGGER,
+							"Solver {} errored {} times in a row, giving up on its device",
+							instance,
+							error_restarts
+						);
+						let mut s = shared_data.write().unwrap();
+						s.stats[instance].has_errored = true;
+						s.stats[instance].set_error_reason(&solver.stats.get_error_reason());
+						break;
+					}
+				};
+				error_restarts += 1;
+				warn!(
+					LOGGER,
+					"Restarting solver {} in {:?} ({} of {})",
+					instance,
+					backoff,
+					error_restarts,
+					MAX_ERROR_RESTARTS
+				);
+				if !wait_for_control(&solver_loop_rx, &mut paused, backoff) {
 					break;
 				}
+				ctx = match create_ctx_with_backoff(
+					|| solver.acquire_ctx(),
+					instance,
+					&shared_data,
+					&solver_loop_rx,
+					&mut paused,
+					time::Duration::from_millis(CTX_RETRY_INITIAL_MS),
+				) {
+					Some(c) => c,
+					None => break,
+				};
+				*control_ctx.lock().unwrap() = Some(SolverCtxWrapper(NonNull::new(ctx).unwrap()));
+				solver.stats.has_errored = false;
+				solver.stats.set_error_reason("");
+				shared_data.write().unwrap().stats[instance].mark_started();
+			} else {
+				error_restarts = 0;
 			}
 			solver.solutions = SolverSolutions::default();
 			thread::sleep(time::Duration::from_micros(100));
 		}
 
 		let _ = stop_handle.join();
-		solver.lib.destroy_solver_ctx(ctx);
+		if !ctx.is_null() {
+			solver.release_ctx(ctx);
+		}
 		solver.unload();
 		let _ = solver_stopped_tx.send(ControlMessage::SolverStopped(instance));
 	}
 
-	/// Starts solvers, ready for jobs via job control
+	/// Load a solver and check it can create a context for its device,
+	/// reusing a cached one if there is one
+	fn init_solver(
+		config: PluginConfig,
+		ctx_cache: &SolverCtxCacheType,
+	) -> Result<SolverInstance, CuckooMinerError> {
+		let mut s = SolverInstance::new(config, ctx_cache.clone())?;
+		let ctx = s.acquire_ctx();
+		if ctx.is_null() {
+			s.unload();
+			return Err(CuckooMinerError::PluginProcessingError(format!(
+				"Plugin {} failed to initialize device {}",
+				s.config.name, s.config.params.device
+			)));
+		}
+		// keep the context for the solver thread
+		s.release_ctx(ctx);
+		Ok(s)
+	}
+
+	/// Pause for the given milliseconds between initializing each solver's
+	/// device, so large rigs don't set them all up at once
+	pub fn set_start_stagger_ms(&mut self, ms: u64) {
+		self.start_stagger = time::Duration::from_millis(ms);
+	}
+
+	/// Seconds to wait for solvers to exit on shutdown before giving up on
+	/// them
+	pub fn set_shutdown_timeout_secs(&mut self, secs: u64) {
+		self.shutdown_timeout = time::Duration::from_secs(secs);
+	}
+
+	/// Starts solvers, ready for jobs via job control. GPU solvers that fail
+	/// to initialize are replaced by their CPU fallback, if configured
 	pub fn start_solvers(&mut self) -> Result<(), CuckooMinerError> {
-		let mut solvers = Vec::new();
-		for c in self.configs.clone() {
-			solvers.push(SolverInstance::new(c)?);
-		}
-		let mut i = 0;
-		for s in solvers {
-			let sd = self.shared_data.clone();
-			let (control_tx, control_rx) = mpsc::channel::<ControlMessage>();
-			let (solver_tx, solver_rx) = mpsc::channel::<ControlMessage>();
-			let (solver_stopped_tx, solver_stopped_rx) = mpsc::channel::<ControlMessage>();
+		let mut solvers: Vec<SolverInstance> = Vec::new();
+		let mut slots = vec![];
+		for (i, c) in self.plugin_configs.clone().into_iter().enumerate() {
+			if i > 0 && self.start_stagger > time::Duration::from_millis(0) {
+				thread::sleep(self.start_stagger);
+			}
+			let fallback = c.cpu_fallback.clone();
+			match CuckooMiner::init_solver(c, &self.ctx_cache) {
+				Ok(s) => {
+					slots.push(solvers.len());
+					solvers.push(s);
+				}
+				Err(e) => {
+					let fb = match fallback {
+						Some(fb) => *fb,
+						None => return Err(e),
+					};
+					warn!(LOGGER, "{:?}, falling back to {}", e, fb.name);
+					// a single CPU solver covers all failed GPUs
+					match solvers.iter().position(|s| s.config.file == fb.file) {
+						Some(slot) => slots.push(slot),
+						None => {
+							slots.push(solvers.len());
+							solvers.push(CuckooMiner::init_solver(fb, &self.ctx_cache)?);
+						}
+					}
+				}
+			}
+		}
+		self.set_solver_configs(solvers.iter().map(|s| s.config.clone()).collect(), slots);
+		self.build_info = solvers
+			.iter()
+			.map(|s| format!("{}: {}", s.config.name, s.lib.get_build_info()))
+			.collect();
+		for b in self.build_info.iter() {
+			info!(LOGGER, "Plugin {}", b);
+		}
+		for (i, s) in solvers.into_iter().enumerate() {
+			self.start_solver_thread(s, i);
+		}
+		Ok(())
+	}
+
+	/// Use the configs of the solvers that actually started, one per slot.
+	/// A GPU that fell back leaves its slot running the CPU plugin, or no
+	/// slot at all if another GPU's fallback already runs it, so the
+	/// per-solver state is rebuilt to match. slots gives the solver running
+	/// each of the configs as given
+	fn set_solver_configs(&mut self, configs: Vec<PluginConfig>, slots: Vec<usize>) {
+		let len = configs.len();
+		self.configs = configs;
+		self.solver_slots = slots;
+		self.paused_devices = vec![false; len];
+		let mut s = self.shared_data.write().unwrap();
+		s.stats = vec![DeviceStats::default(); len];
+		s.graphs_before_restart = vec![0; len];
+	}
+
+	/// Start the thread of the solver in slot i, replacing the thread and
+	/// channels of any solver the slot had before
+	fn start_solver_thread(&mut self, s: SolverInstance, i: usize) {
+		let sd = self.shared_data.clone();
+		let (control_tx, control_rx) = mpsc::channel::<ControlMessage>();
+		let (solver_tx, solver_rx) = mpsc::channel::<ControlMessage>();
+		let (solver_stopped_tx, solver_stopped_rx) = mpsc::channel::<ControlMessage>();
+		let handle = thread::spawn(move || {
+			CuckooMiner::solver_thread(s, i, sd, control_rx, solver_rx, solver_stopped_tx);
+		});
+		if i < self.solver_threads.len() {
+			self.control_txs[i] = control_tx;
+			self.solver_loop_txs[i] = solver_tx;
+			self.solver_stopped_rxs[i] = solver_stopped_rx;
+			self.solver_threads[i] = handle;
+		} else {
 			self.control_txs.push(control_tx);
 			self.solver_loop_txs.push(solver_tx);
 			self.solver_stopped_rxs.push(solver_stopped_rx);
-			thread::spawn(move || {
-				CuckooMiner::solver_thread(s, i, sd, control_rx, solver_rx, solver_stopped_tx);
-			});
-			i += 1;
+			self.solver_threads.push(handle);
 		}
-		Ok(())
+	}
+
+	/// Apply new plugin configs, one per configured plugin, restarting only
+	/// the solvers whose config changed and leaving the others mining.
+	/// Configs are compared as given, so a device running its CPU fallback
+	/// isn't restarted just for that; a changed config for such a device
+	/// applies once the miner restarts. A solver that fails to start with
+	/// its new config goes back to its previous one, and one that can't
+	/// start with either is left stopped and marked errored. A solver that
+	/// doesn't exit within the shutdown timeout keeps its old config.
+	/// Restarted solvers start paused, reusing cached solver contexts where
+	/// they can; those of plugins no longer configured are destroyed.
+	/// Returns the indices of the solvers restarted
+	pub fn reconfigure(
+		&mut self,
+		configs: Vec<PluginConfig>,
+	) -> Result<Vec<usize>, CuckooMinerError> {
+		if configs.len() != self.plugin_configs.len() {
+			return Err(CuckooMinerError::ParameterError(format!(
+				"{} plugins configured, {} before, changing the number of plugins needs a restart",
+				configs.len(),
+				self.plugin_configs.len()
+			)));
+		}
+		let mut restarted = vec![];
+		for (p, config) in configs.into_iter().enumerate() {
+			if config == self.plugin_configs[p] {
+				continue;
+			}
+			let i = self.solver_slots[p];
+			if self.configs[i] != self.plugin_configs[p] {
+				warn!(
+					LOGGER,
+					"Plugin {} is running on its CPU fallback, its new config applies after a restart",
+					config.name
+				);
+				self.plugin_configs[p] = config;
+				continue;
+			}
+			// the old solver has to let go of the device first
+			if !self.stop_solver(i) {
+				error!(
+					LOGGER,
+					"Solver {} is still running, not applying its new config", i
+				);
+				continue;
+			}
+			let solver = match CuckooMiner::init_solver(config, &self.ctx_cache) {
+				Ok(s) => s,
+				Err(e) => {
+					warn!(
+						LOGGER,
+						"Solver {} failed to start with its new config: {:?}, going back to its previous config",
+						i,
+						e
+					);
+					match CuckooMiner::init_solver(self.configs[i].clone(), &self.ctx_cache) {
+						Ok(s) => s,
+						Err(e) => {
+							error!(
+								LOGGER,
+								"Solver {} failed to start with its previous config too: {:?}",
+								i,
+								e
+							);
+							let mut s = self.shared_data.write().unwrap();
+							s.stats[i].has_errored = true;
+							s.stats[i].set_error_reason("Failed to restart after reconfiguring");
+							continue;
+						}
+					}
+				}
+			};
+			self.build_info[i] = format!("{}: {}", solver.config.name, solver.lib.get_build_info());
+			info!(LOGGER, "Plugin {}", self.build_info[i]);
+			self.configs[i] = solver.config.clone();
+			self.plugin_configs[p] = solver.config.clone();
+			self.start_solver_thread(solver, i);
+			restarted.push(i);
+		}
+		let plugins: Vec<String> = self
+			.plugin_configs
+			.iter()
+			.chain(self.configs.iter())
+			.map(|c| c.name.clone())
+			.collect();
+		self.ctx_cache.lock().unwrap().retain_plugins(&plugins);
+		Ok(restarted)
+	}
+
+	/// Stop a single solver, waiting up to the shutdown timeout for it to
+	/// exit. Returns whether it did
+	fn stop_solver(&self, i: usize) -> bool {
+		let _ = self.control_txs[i].send(ControlMessage::Stop);
+		let _ = self.solver_loop_txs[i].send(ControlMessage::Stop);
+		let deadline = time::Instant::now() + self.shutdown_timeout;
+		loop {
+			let wait = deadline.saturating_duration_since(time::Instant::now());
+			match self.solver_stopped_rxs[i].recv_timeout(wait) {
+				Ok(ControlMessage::SolverStopped(_))
+				| Err(mpsc::RecvTimeoutError::Disconnected) => {
+					return true;
+				}
+				Ok(_) => {}
+				Err(mpsc::RecvTimeoutError::Timeout) => break,
+			}
+		}
+		warn!(
+			LOGGER,
+			"Solver {} didn't exit within {}s, leaving it running",
+			i,
+			self.shutdown_timeout.as_secs()
+		);
+		false
+	}
+
+	/// Tear down and recreate each solver in turn, e.g. if they've stalled.
+	/// Each old solver is given up to the shutdown timeout to exit and let
+	/// go of its device first; one that doesn't is left running rather than
+	/// replaced. A solver that fails to start again is marked errored and
+	/// the rest are still restarted. New solvers start paused
+	pub fn restart_solvers(&mut self) -> Result<(), CuckooMinerError> {
+		if self.solver_threads.is_empty() {
+			return self.start_solvers();
+		}
+		let mut res = Ok(());
+		for i in 0..self.solver_threads.len() {
+			if !self.stop_solver(i) {
+				continue;
+			}
+			match CuckooMiner::init_solver(self.configs[i].clone(), &self.ctx_cache) {
+				Ok(s) => {
+					self.build_info[i] = format!("{}: {}", s.config.name, s.lib.get_build_info());
+					self.start_solver_thread(s, i);
+				}
+				Err(e) => {
+					error!(LOGGER, "Solver {} failed to restart: {:?}", i, e);
+					let mut s = self.shared_data.write().unwrap();
+					s.stats[i].has_errored = true;
+					s.stats[i].set_error_reason("Failed to restart");
+					res = Err(e);
+				}
+			}
+		}
+		res
 	}
 
 	/// An asynchronous -esque version of the plugin miner, which takes
@@ -269,15 +857,22 @@ impl CuckooMiner {
 		sd.pre_nonce = pre_nonce.to_owned();
 		sd.post_nonce = post_nonce.to_owned();
 		sd.difficulty = difficulty;
+		drop(sd);
 		if paused {
 			self.resume_solvers();
 		}
 		Ok(())
 	}
 
+	/// Change the target difficulty of the current job, without restarting
+	/// the solvers. Applies to solutions found from now on
+	pub fn set_difficulty(&mut self, difficulty: u64) {
+		self.shared_data.write().unwrap().difficulty = difficulty;
+	}
+
 	/// Returns solutions if currently waiting.
 
-	pub fn get_solutions(&self) -> Option<SolverSolutions> {
+	pub fn get_solutions(&self) -> Option<FoundSolutions> {
 		// just to prevent endless needless locking of this
 		// when using fast test miners, in real cuckoo30 terms
 		// this shouldn't be an issue
@@ -297,9 +892,13 @@ impl CuckooMiner {
 	}
 
 	/// get stats for all running solvers
-	pub fn get_stats(&self) -> Result<Vec<SolverStats>, CuckooMinerError> {
+	pub fn get_stats(&self) -> Result<Vec<DeviceStats>, CuckooMinerError> {
 		let s = self.shared_data.read().unwrap();
-		Ok(s.stats.clone())
+		let mut stats = s.stats.clone();
+		for (i, st) in stats.iter_mut().enumerate() {
+			st.paused = self.paused || self.paused_devices.get(i).cloned().unwrap_or(false);
+		}
+		Ok(stats)
 	}
 
 	/// #Description
@@ -332,26 +931,443 @@ impl CuckooMiner {
 		debug!(LOGGER, "Pause message sent");
 	}
 
-	/// Tells current solvers to stop and wait
+	/// Tells current solvers to resume, unless mining or the solver's
+	/// device has been paused, or there's no job yet
 	pub fn resume_solvers(&self) {
-		for t in self.control_txs.iter() {
-			let _ = t.send(ControlMessage::Resume);
+		if self.paused || !self.has_job() {
+			return;
 		}
-		for t in self.solver_loop_txs.iter() {
-			let _ = t.send(ControlMessage::Resume);
+		for (i, t) in self.control_txs.iter().enumerate() {
+			if !self.paused_devices[i] {
+				let _ = t.send(ControlMessage::Resume);
+			}
+		}
+		for (i, t) in self.solver_loop_txs.iter().enumerate() {
+			if !self.paused_devices[i] {
+				let _ = t.send(ControlMessage::Resume);
+			}
 		}
 		debug!(LOGGER, "Resume message sent");
 	}
 
-	/// block until solvers have all exited
-	pub fn wait_for_solver_shutdown(&self) {
-		for r in self.solver_stopped_rxs.iter() {
-			while let Some(message) = r.iter().next() {
-				if let ControlMessage::SolverStopped(i) = message {
-					debug!(LOGGER, "Solver stopped: {}", i);
-					break;
-				}
+	/// Pause or unpause mining on all devices. While paused, solvers aren't
+	/// resumed when new jobs arrive. Unpausing leaves the solvers idle until
+	/// the caller resumes them, as the current job may not be worth mining
+	pub fn set_paused(&mut self, paused: bool) {
+		self.paused = paused;
+		if paused {
+			self.pause_solvers();
+		}
+	}
+
+	/// Pause or unpause mining on a single device, by solver index. Like
+	/// set_paused, unpausing leaves the device idle until the caller
+	/// resumes the solvers
+	pub fn set_device_paused(
+		&mut self,
+		device: usize,
+		paused: bool,
+	) -> Result<(), CuckooMinerError> {
+		if device >= self.paused_devices.len() {
+			return Err(CuckooMinerError::ParameterError(format!(
+				"No solver with index {}",
+				device
+			)));
+		}
+		self.paused_devices[device] = paused;
+		if paused {
+			if let Some(t) = self.control_txs.get(device) {
+				let _ = t.send(ControlMessage::Pause);
+			}
+			if let Some(t) = self.solver_loop_txs.get(device) {
+				let _ = t.send(ControlMessage::Pause);
 			}
 		}
+		Ok(())
+	}
+
+	/// Live state of each solver, by solver index
+	pub fn solver_states(&self) -> Vec<SolverState> {
+		let has_job = self.has_job();
+		let now = plugin::now_nanos();
+		let s = self.shared_data.read().unwrap();
+		s.stats
+			.iter()
+			.enumerate()
+			.map(|(i, st)| {
+				let mut st = st.clone();
+				st.paused = self.paused || self.paused_devices.get(i).cloned().unwrap_or(false);
+				SolverState::from_stats(i, &st, has_job, now, SOLVER_HUNG_SECS)
+			})
+			.collect()
+	}
+
+	/// Restart a solver's count of graphs since its last accepted share
+	pub fn reset_graphs_since_share(&self, instance: usize) {
+		let mut s = self.shared_data.write().unwrap();
+		if let Some(st) = s.stats.get_mut(instance) {
+			st.graphs_since_share = 0;
+		}
+	}
+
+	/// Expected graphs per second of each solver, as configured
+	pub fn expected_gps(&self) -> Vec<Option<f64>> {
+		self.configs.iter().map(|c| c.expected_gps).collect()
+	}
+
+	/// Algorithm each solver mines, going by its plugin name
+	pub fn algorithms(&self) -> Vec<String> {
+		self.configs
+			.iter()
+			.map(|c| c.algorithm().to_string())
+			.collect()
+	}
+
+	/// Time since the miner was created
+	pub fn uptime(&self) -> time::Duration {
+		self.start_time.elapsed()
+	}
+
+	/// Graphs attempted by all solvers this session, including by solvers
+	/// since restarted
+	pub fn total_graphs(&self) -> u64 {
+		self.shared_data.read().unwrap().total_graphs()
+	}
+
+	/// Plugin name and build info of each solver, "unknown" for plugins
+	/// that don't report it
+	pub fn plugin_build_info(&self) -> Vec<String> {
+		self.build_info.clone()
+	}
+
+	/// Whether each device is configured as critical
+	pub fn critical_devices(&self) -> Vec<bool> {
+		self.configs.iter().map(|c| c.critical).collect()
+	}
+
+	/// Whether mining is paused on all devices
+	pub fn is_paused(&self) -> bool {
+		self.paused
+	}
+
+	/// Whether a job has been received to work on
+	fn has_job(&self) -> bool {
+		!self.shared_data.read().unwrap().pre_nonce.is_empty()
+	}
+
+	/// block until solvers have all exited, or the shutdown timeout has
+	/// passed, then destroy the cached solver contexts. Solvers that haven't
+	/// exited by then are left running
+	pub fn wait_for_solver_shutdown(&mut self) {
+		let handles = self.solver_threads.drain(..).collect();
+		let stuck = wait_for_stopped(&self.solver_stopped_rxs, handles, self.shutdown_timeout);
+		for i in stuck {
+			warn!(
+				LOGGER,
+				"Solver {} didn't exit within {}s, leaving it running",
+				i,
+				self.shutdown_timeout.as_secs()
+			);
+		}
+		self.ctx_cache.lock().unwrap().clear();
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use miner::types::{CtxDestroyer, SolverCtxKey};
+
+	#[test]
+	fn test_set_difficulty_mid_job() {
+		let mut miner = CuckooMiner::new(vec![]);
+		miner
+			.notify(1, 100, "00", "00", 1)
+			.expect("notify shouldn't fail without solvers");
+
+		// a proof of difficulty 8455 at 29 edge bits, unscaled
+		let mut sols = SolverSolutions {
+			edge_bits: 29,
+			num_sols: 1,
+			..SolverSolutions::default()
+		};
+		for (i, n) in sols.sols[0].proof.iter_mut().enumerate() {
+			*n = 4021 + i as u64 * 1000;
+		}
+		let filter = |miner: &CuckooMiner| {
+			let target = miner.shared_data.read().unwrap().difficulty;
+			filter_solutions(&sols, DifficultyScaling::Unscaled, 100, 1, target)
+		};
+		let found = filter(&miner);
+		assert_eq!(found.len(), 1);
+		assert_eq!(found[0].1, 8455);
+
+		miner.set_difficulty(8456);
+		assert!(filter(&miner).is_empty());
+		miner.set_difficulty(8455);
+		assert_eq!(filter(&miner).len(), 1);
+
+		// the job itself is left alone
+		let sd = miner.shared_data.read().unwrap();
+		assert_eq!((sd.job_id, sd.height), (1, 100));
+		assert_eq!(sd.pre_nonce, "00");
+	}
+
+	#[test]
+	fn test_unpause_leaves_solvers_idle() {
+		let mut miner = CuckooMiner::new(vec![test_config("cuckatoo_ocl_31")]);
+		let (tx, rx) = mpsc::channel();
+		miner.control_txs.push(tx.clone());
+		miner.solver_loop_txs.push(tx);
+		let messages = || {
+			rx.try_iter()
+				.map(|m| format!("{:?}", m))
+				.collect::<Vec<_>>()
+		};
+
+		// no job to resume on yet
+		miner.resume_solvers();
+		assert!(messages().is_empty());
+		miner.notify(1, 100, "00", "00", 1).unwrap();
+		assert_eq!(messages(), vec!["Pause", "Pause", "Resume", "Resume"]);
+
+		miner.set_paused(true);
+		assert_eq!(messages(), vec!["Pause", "Pause"]);
+		miner.resume_solvers();
+		assert!(messages().is_empty());
+		// the caller resumes once the job is worth mining
+		miner.set_paused(false);
+		assert!(messages().is_empty());
+		miner.resume_solvers();
+		assert_eq!(messages(), vec!["Resume", "Resume"]);
+	}
+
+	#[test]
+	fn test_unpause_device_leaves_it_idle() {
+		let mut miner = CuckooMiner::new(vec![test_config("cuckatoo_ocl_31")]);
+		let (tx, rx) = mpsc::channel();
+		miner.control_txs.push(tx.clone());
+		miner.solver_loop_txs.push(tx);
+		miner.notify(1, 100, "00", "00", 1).unwrap();
+		let _ = rx.try_iter().count();
+
+		miner.set_device_paused(0, true).unwrap();
+		assert_eq!(rx.try_iter().count(), 2);
+		miner.set_device_paused(0, false).unwrap();
+		assert_eq!(rx.try_iter().count(), 0);
+		miner.resume_solvers();
+		assert_eq!(rx.try_iter().count(), 2);
+		assert!(miner.set_device_paused(1, false).is_err());
+	}
+
+	#[test]
+	fn test_ctx_creation_backoff() {
+		let shared_data = Arc::new(RwLock::new(JobSharedData::new(1)));
+		let (tx, rx) = mpsc::channel();
+		let backoff = time::Duration::from_millis(1);
+		let mut paused = true;
+
+		// a stub plugin failing twice before creating a context
+		let mut attempts = 0;
+		let ctx = create_ctx_with_backoff(
+			|| {
+				attempts += 1;
+				if attempts < 3 {
+					return std::ptr::null_mut();
+				}
+				assert!(shared_data.read().unwrap().stats[0].has_errored);
+				NonNull::dangling().as_ptr()
+			},
+			0,
+			&shared_data,
+			&rx,
+			&mut paused,
+			backoff,
+		);
+		assert!(ctx.is_some());
+		assert_eq!(attempts, 3);
+		assert!(!shared_data.read().unwrap().stats[0].has_errored);
+
+		// a stub plugin that never manages it, until told to stop
+		tx.send(ControlMessage::Resume).unwrap();
+		tx.send(ControlMessage::Stop).unwrap();
+		let ctx = create_ctx_with_backoff(
+			std::ptr::null_mut,
+			0,
+			&shared_data,
+			&rx,
+			&mut paused,
+			backoff,
+		);
+		assert!(ctx.is_none());
+		assert!(!paused);
+		let sd = shared_data.read().unwrap();
+		assert!(sd.stats[0].has_errored);
+		assert_eq!(
+			sd.stats[0].get_error_reason(),
+			"Failed to create solver context"
+		);
+	}
+
+	#[test]
+	fn test_error_restart_backoff() {
+		assert_eq!(
+			error_restart_backoff(0),
+			Some(time::Duration::from_millis(CTX_RETRY_INITIAL_MS))
+		);
+		assert_eq!(
+			error_restart_backoff(2),
+			Some(time::Duration::from_millis(CTX_RETRY_INITIAL_MS * 4))
+		);
+		assert_eq!(error_restart_backoff(MAX_ERROR_RESTARTS), None);
+	}
+
+	#[test]
+	fn test_shutdown_timeout() {
+		// a stub solver exiting when told to stop, and one stuck in its plugin
+		let mut rxs = vec![];
+		let mut handles = vec![];
+		let (stop_tx, stop_rx) = mpsc::channel::<ControlMessage>();
+		let (tx, rx) = mpsc::channel();
+		rxs.push(rx);
+		handles.push(thread::spawn(move || {
+			let _ = stop_rx.recv();
+			let _ = tx.send(ControlMessage::SolverStopped(0));
+		}));
+		let (tx, rx) = mpsc::channel();
+		rxs.push(rx);
+		handles.push(thread::spawn(move || {
+			thread::sleep(time::Duration::from_secs(5));
+			let _ = tx.send(ControlMessage::SolverStopped(1));
+		}));
+		stop_tx.send(ControlMessage::Stop).unwrap();
+
+		let start = time::Instant::now();
+		let stuck = wait_for_stopped(&rxs, handles, time::Duration::from_millis(200));
+		assert!(start.elapsed() < time::Duration::from_secs(2));
+		assert_eq!(stuck, vec![1]);
+	}
+
+	fn test_config(name: &str) -> PluginConfig {
+		PluginConfig {
+			name: name.to_string(),
+			file: format!("{}.cuckooplugin", name),
+			params: Default::default(),
+			scaling: DifficultyScaling::default(),
+			expected_gps: None,
+			cpu_fallback: None,
+			sequential_nonce: false,
+			critical: false,
+			nonce_seed: None,
+			range_target_secs: None,
+			nonce_range: 1,
+			min_range: 1,
+			max_range: 16,
+			cpu_affinity: None,
+		}
+	}
+
+	#[test]
+	fn test_ctx_cache_across_reconfigure() {
+		// a stub plugin, creating and destroying contexts
+		let created = Arc::new(Mutex::new(vec![]));
+		let destroyed = Arc::new(Mutex::new(vec![]));
+		let miner = CuckooMiner::new(vec![]);
+		let cache = miner.ctx_cache.clone();
+		// what each solver does with its config: take a context on start,
+		// creating one if none is cached, and hand it back on exit
+		let run_solver = |config: &PluginConfig| {
+			let key = SolverCtxKey::from_config(config);
+			let cached = cache.lock().unwrap().take(&key);
+			let ctx = cached.unwrap_or_else(|| {
+				let c = Box::into_raw(Box::new(0u8)) as *mut SolverCtx;
+				created.lock().unwrap().push(c as usize);
+				c
+			});
+			let d = destroyed.clone();
+			let destroy: CtxDestroyer = Box::new(move |c| {
+				d.lock().unwrap().push(c as usize);
+				unsafe { drop(Box::from_raw(c as *mut u8)) };
+			});
+			cache.lock().unwrap().put(key, ctx, destroy);
+			ctx as usize
+		};
+
+		let mut a = test_config("cuckatoo_ocl_31");
+		a.params.edge_bits = 31;
+		let mut b = a.clone();
+		b.params.edge_bits = 29;
+		let first_a = run_solver(&a);
+		let first_b = run_solver(&b);
+		assert_ne!(first_a, first_b);
+		// switching back to A reuses its context
+		assert_eq!(run_solver(&a), first_a);
+		assert_eq!(created.lock().unwrap().len(), 2);
+		assert!(destroyed.lock().unwrap().is_empty());
+
+		// reloading configs without the plugin destroys its contexts
+		let mut miner = miner;
+		assert_eq!(miner.reconfigure(vec![]).unwrap(), Vec::<usize>::new());
+		for config in [a, b].iter() {
+			let key = SolverCtxKey::from_config(config);
+			assert!(cache.lock().unwrap().take(&key).is_none());
+		}
+		let mut gone = destroyed.lock().unwrap().clone();
+		gone.sort();
+		let mut all = created.lock().unwrap().clone();
+		all.sort();
+		assert_eq!(gone, all);
+	}
+
+	#[test]
+	fn test_reconfigure_solver_count() {
+		let mut miner = CuckooMiner::new(vec![]);
+		assert_eq!(miner.reconfigure(vec![]).unwrap(), Vec::<usize>::new());
+
+		// adding a solver needs a restart
+		let config = test_config("cuckatoo_lean_cpu_compat_31");
+		assert!(miner.reconfigure(vec![config]).is_err());
+	}
+
+	#[test]
+	fn test_fallback_solver_configs() {
+		let mut gpu0 = test_config("cuckaroo_cuda_29");
+		gpu0.critical = true;
+		gpu0.expected_gps = Some(4.0);
+		let gpu1 = test_config("cuckatoo_cuda_31");
+		let cpu = test_config("cuckatoo_lean_cpu_compat_31");
+		let mut miner = CuckooMiner::new(vec![gpu0, gpu1.clone()]);
+
+		// the first GPU fell back to the CPU plugin, the second started
+		miner.set_solver_configs(vec![cpu, gpu1], vec![0, 1]);
+		assert_eq!(miner.algorithms(), vec!["cuckatoo", "cuckatoo"]);
+		assert_eq!(miner.expected_gps(), vec![None, None]);
+		assert_eq!(miner.critical_devices(), vec![false, false]);
+		assert_eq!(miner.get_stats().unwrap().len(), 2);
+	}
+
+	#[test]
+	fn test_reconfigure_after_fallback() {
+		let gpu0 = test_config("cuckatoo_cuda_31");
+		let gpu1 = test_config("cuckatoo_ocl_31");
+		let mut cpu = test_config("cuckatoo_lean_cpu_compat_31");
+		cpu.params.nthreads = 4;
+		let mut miner = CuckooMiner::new(vec![gpu0.clone(), gpu1.clone()]);
+
+		// both GPUs fell back to the same CPU solver
+		miner.set_solver_configs(vec![cpu], vec![0, 0]);
+		let reloaded = vec![gpu0.clone(), gpu1.clone()];
+		assert_eq!(miner.reconfigure(reloaded).unwrap(), Vec::<usize>::new());
+
+		// a new config for a device on its fallback waits for a restart
+		let mut gpu0_new = gpu0;
+		gpu0_new.params.device = 1;
+		let reloaded = vec![gpu0_new.clone(), gpu1];
+		assert_eq!(
+			miner.reconfigure(reloaded.clone()).unwrap(),
+			Vec::<usize>::new()
+		);
+		assert_eq!(miner.plugin_configs[0], gpu0_new);
+		assert_eq!(miner.reconfigure(reloaded).unwrap(), Vec::<usize>::new());
 	}
 }
@@ -23,5 +23,7 @@
 
 pub mod consensus;
 pub mod miner;
+pub mod pipeline;
 pub mod types;
 pub mod util;
+pub mod verify;
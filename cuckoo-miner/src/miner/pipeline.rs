@@ -0,0 +1,256 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Higher level mining pipeline, feeding jobs from a source to the solvers
+//! and handing solutions that meet the job's difficulty to a sink, for
+//! driving a miner from library code rather than the grin-miner binary
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::{thread, time};
+use util::LOGGER;
+
+use config::types::PluginConfig;
+use miner::consensus::{header_siphash_keys, is_plausible_proof, Proof};
+use miner::miner::CuckooMiner;
+use miner::types::{DeviceStats, FoundSolutions};
+use CuckooMinerError;
+
+/// A job to mine
+#[derive(Clone, Debug)]
+pub struct Job {
+	/// Job id, as given by the pool
+	pub job_id: u64,
+	/// Block height
+	pub height: u64,
+	/// Target share difficulty
+	pub difficulty: u64,
+	/// Hex encoded pre-nonce portion of the header
+	pub pre_pow: String,
+}
+
+/// A solution meeting the target difficulty of the job it was found for
+#[derive(Clone, Debug)]
+pub struct Share {
+	/// Id of the job the share was found for
+	pub job_id: u64,
+	/// Block height
+	pub height: u64,
+	/// Graph size
+	pub edge_bits: u32,
+	/// Nonce
+	pub nonce: u64,
+	/// Proof nonces
+	pub proof: Vec<u64>,
+}
+
+/// Where jobs come from, e.g. a connection to a pool
+pub trait JobSource: Send {
+	/// Return a new job if one has arrived since the last call. An error
+	/// stops the pipeline
+	fn poll_job(&mut self) -> Result<Option<Job>, String>;
+}
+
+/// Where found shares go, e.g. submitted to a pool
+pub trait ShareSink: Send {
+	/// Handle a share. Errors are logged, but don't stop the pipeline
+	fn submit(&mut self, share: Share) -> Result<(), String>;
+}
+
+/// Runs solvers on jobs from a `JobSource`, handing shares to a `ShareSink`
+pub struct Miner {
+	miner: CuckooMiner,
+	source: Option<Box<dyn JobSource>>,
+	sink: Option<Box<dyn ShareSink>>,
+	job: Option<Job>,
+	algorithms: Vec<String>,
+	stop: Arc<AtomicBool>,
+}
+
+impl Miner {
+	/// Create a pipeline with one PluginConfig per device
+	pub fn new(configs: Vec<PluginConfig>) -> Miner {
+		Miner {
+			miner: CuckooMiner::new(configs),
+			source: None,
+			sink: None,
+			job: None,
+			algorithms: vec![],
+			stop: Arc::new(AtomicBool::new(false)),
+		}
+	}
+
+	/// Set where jobs come from and where shares go
+	pub fn connect<S, K>(&mut self, source: S, sink: K)
+	where
+		S: JobSource + 'static,
+		K: ShareSink + 'static,
+	{
+		self.source = Some(Box::new(source));
+		self.sink = Some(Box::new(sink));
+	}
+
+	/// Flag that stops `run` when set, e.g. from another thread
+	pub fn stop_handle(&self) -> Arc<AtomicBool> {
+		self.stop.clone()
+	}
+
+	/// Stats for all running solvers
+	pub fn stats(&self) -> Result<Vec<DeviceStats>, CuckooMinerError> {
+		self.miner.get_stats()
+	}
+
+	/// Start the solvers and mine until stopped, or until the job source
+	/// fails
+	pub fn run(&mut self) -> Result<(), CuckooMinerError> {
+		if self.source.is_none() || self.sink.is_none() {
+			return Err(CuckooMinerError::PipelineError(
+				"No job source or share sink connected".to_string(),
+			));
+		}
+		self.miner.start_solvers()?;
+		self.algorithms = self.miner.algorithms();
+		let res = self.mine();
+		self.miner.stop_solvers();
+		self.miner.wait_for_solver_shutdown();
+		res
+	}
+
+	fn mine(&mut self) -> Result<(), CuckooMinerError> {
+		while !self.stop.load(Ordering::Relaxed) {
+			let job = self
+				.source
+				.as_mut()
+				.unwrap()
+				.poll_job()
+				.map_err(CuckooMinerError::PipelineError)?;
+			if let Some(job) = job {
+				debug!(
+					LOGGER,
+					"Pipeline job {} at height {}, difficulty {}",
+					job.job_id,
+					job.height,
+					job.difficulty
+				);
+				self.miner.notify(
+					job.job_id as u32,
+					job.height,
+					&job.pre_pow,
+					"",
+					job.difficulty,
+				)?;
+				self.job = Some(job);
+			}
+			// solvers only report solutions meeting the job's difficulty
+			while let Some(ss) = self.miner.get_solutions() {
+				for share in self.shares(&ss) {
+					if let Err(e) = self.sink.as_mut().unwrap().submit(share) {
+						warn!(LOGGER, "Failed to submit share: {}", e);
+					}
+				}
+			}
+			thread::sleep(time::Duration::from_millis(100));
+		}
+		Ok(())
+	}
+
+	/// Shares for a solver's solutions, leaving out malformed proofs and
+	/// proofs for the current job that don't verify. Solutions for an
+	/// earlier job are left alone, as its header is no longer at hand
+	fn shares(&self, ss: &FoundSolutions) -> Vec<Share> {
+		let mut shares = vec![];
+		for sol in ss.sols.iter().take(ss.num_sols as usize) {
+			if !is_plausible_proof(&sol.proof) {
+				warn!(
+					LOGGER,
+					"Dropping malformed solution from solver {}: {:?}",
+					ss.instance,
+					sol.proof.to_vec()
+				);
+				continue;
+			}
+			let proof = Proof {
+				edge_bits: ss.edge_bits as u8,
+				nonces: sol.proof.to_vec(),
+			};
+			let algorithm = self.algorithms.get(ss.instance as usize);
+			if let (Some(job), Some(algorithm)) = (self.job.as_ref(), algorithm) {
+				if sol.id == job.job_id {
+					let keys = header_siphash_keys(&job.pre_pow, sol.nonce);
+					if let Err(e) = proof.verify(&keys, algorithm) {
+						warn!(
+							LOGGER,
+							"Dropping invalid solution from solver {}: {}, {:?}",
+							ss.instance,
+							e,
+							proof
+						);
+						continue;
+					}
+				}
+			}
+			shares.push(Share {
+				job_id: sol.id,
+				height: ss.height,
+				edge_bits: ss.edge_bits,
+				nonce: sol.nonce,
+				proof: proof.nonces,
+			});
+		}
+		shares
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use plugin::{SolverSolutions, PROOFSIZE};
+
+	#[test]
+	fn test_shares() {
+		let mut miner = Miner::new(vec![]);
+		miner.algorithms = vec!["cuckatoo".to_string()];
+		miner.job = Some(Job {
+			job_id: 2,
+			height: 100,
+			difficulty: 1,
+			pre_pow: "00".repeat(40),
+		});
+		let mut ss = FoundSolutions {
+			solutions: SolverSolutions {
+				edge_bits: 29,
+				num_sols: 3,
+				..SolverSolutions::default()
+			},
+			height: 99,
+			..FoundSolutions::default()
+		};
+		for (i, sol) in ss.sols.iter_mut().enumerate() {
+			sol.id = 1;
+			for (j, n) in sol.proof.iter_mut().enumerate() {
+				*n = (j + i * PROOFSIZE) as u64 + 1;
+			}
+		}
+		// malformed
+		ss.sols[1].proof = [0; PROOFSIZE];
+		// for the current job, and not a cycle
+		ss.sols[2].id = 2;
+		let shares = miner.shares(&ss);
+		assert_eq!(shares.len(), 1);
+		// the height of the job it was found for, not the current one
+		assert_eq!(shares[0].height, 99);
+		assert_eq!(shares[0].job_id, 1);
+		assert_eq!(shares[0].proof, ss.sols[0].proof.to_vec());
+	}
+}
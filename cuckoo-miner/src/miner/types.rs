@@ -13,14 +13,111 @@
 // limitations under the License.
 
 //! Miner types
-use std::sync::{Arc, RwLock};
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::sync::{Arc, Mutex, RwLock};
 
 use error::CuckooMinerError;
-use plugin::{SolverSolutions, SolverStats};
+use plugin::{now_nanos, SolverCtx, SolverCtxWrapper, SolverSolutions, SolverStats, MAX_SOLS};
 use {PluginConfig, PluginLibrary};
 
 pub type JobSharedDataType = Arc<RwLock<JobSharedData>>;
 
+/// Solver context cache shared by all solvers of a miner
+pub type SolverCtxCacheType = Arc<Mutex<SolverCtxCache>>;
+
+/// Destroys a cached solver context, keeping the plugin that created it
+/// loaded until then
+pub type CtxDestroyer = Box<dyn FnOnce(*mut SolverCtx) + Send>;
+
+/// Maximum number of idle solver contexts kept around by a miner
+pub const MAX_CACHED_CTXS: usize = 4;
+
+/// Identifies the configuration a solver context was created with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SolverCtxKey {
+	/// Plugin name
+	pub plugin: String,
+	/// Device
+	pub device: u32,
+	/// Graph size
+	pub edge_bits: u32,
+}
+
+impl SolverCtxKey {
+	/// Key for the given plugin config
+	pub fn from_config(config: &PluginConfig) -> SolverCtxKey {
+		SolverCtxKey {
+			plugin: config.name.clone(),
+			device: config.params.device,
+			edge_bits: config.params.edge_bits,
+		}
+	}
+}
+
+/// Bounded cache of previously created solver contexts, so switching back
+/// to a configuration doesn't pay the context creation cost again.
+/// Least recently used contexts are evicted, and destroyed, first
+pub struct SolverCtxCache {
+	capacity: usize,
+	/// most recently used first
+	entries: Vec<(SolverCtxKey, SolverCtxWrapper, CtxDestroyer)>,
+}
+
+impl SolverCtxCache {
+	/// Create a cache holding up to capacity contexts
+	pub fn new(capacity: usize) -> SolverCtxCache {
+		SolverCtxCache {
+			capacity,
+			entries: vec![],
+		}
+	}
+
+	/// Take the context for the given key out of the cache, if there is one
+	pub fn take(&mut self, key: &SolverCtxKey) -> Option<*mut SolverCtx> {
+		let pos = self.entries.iter().position(|(k, _, _)| k == key)?;
+		Some(self.entries.remove(pos).1 .0.as_ptr())
+	}
+
+	/// Put a context back in the cache, to be destroyed with `destroy` once
+	/// evicted. A context already cached for the key is destroyed
+	pub fn put(&mut self, key: SolverCtxKey, ctx: *mut SolverCtx, destroy: CtxDestroyer) {
+		let pos = self.entries.iter().position(|(k, _, _)| *k == key);
+		if let Some((_, c, d)) = pos.map(|p| self.entries.remove(p)) {
+			if c.0.as_ptr() != ctx {
+				d(c.0.as_ptr());
+			}
+		}
+		let wrapper = match NonNull::new(ctx) {
+			Some(c) => SolverCtxWrapper(c),
+			None => return,
+		};
+		self.entries.insert(0, (key, wrapper, destroy));
+		while self.entries.len() > self.capacity {
+			if let Some((_, c, d)) = self.entries.pop() {
+				d(c.0.as_ptr());
+			}
+		}
+	}
+
+	/// Destroy the cached contexts of plugins not in `plugins`
+	pub fn retain_plugins(&mut self, plugins: &[String]) {
+		let (keep, drop): (Vec<_>, Vec<_>) = self
+			.entries
+			.drain(..)
+			.partition(|(k, _, _)| plugins.contains(&k.plugin));
+		self.entries = keep;
+		for (_, c, d) in drop {
+			d(c.0.as_ptr());
+		}
+	}
+
+	/// Destroy all cached contexts
+	pub fn clear(&mut self) {
+		self.retain_plugins(&[]);
+	}
+}
+
 /// Holds a loaded lib + config + stats
 /// 1 instance = 1 device on 1 controlling thread
 pub struct SolverInstance {
@@ -32,26 +129,210 @@ pub struct SolverInstance {
 	pub stats: SolverStats,
 	/// Last solution output
 	pub solutions: SolverSolutions,
+	/// Previously created solver contexts, shared with the other solvers
+	/// so they outlive this instance
+	pub ctx_cache: SolverCtxCacheType,
+}
+
+/// A solver's stats as its plugin reports them, along with what the miner
+/// keeps track of itself. Only the plugin's part is passed across the
+/// plugin ABI
+#[derive(Clone, Default)]
+pub struct DeviceStats {
+	/// stats filled in by the plugin
+	pub solver: SolverStats,
+	/// when the solver was last (re)started
+	pub start_time: u64,
+	/// number of times the solver has been restarted
+	pub restarts: u32,
+	/// whether the device is falling behind its expected rate, set by
+	/// grin-miner
+	pub underperforming: bool,
+	/// whether mining is paused on the device
+	pub paused: bool,
+	/// graphs attempted since the device's last accepted share
+	pub graphs_since_share: u64,
+	/// graphs per second averaged over recent stat intervals, set by
+	/// grin-miner (0 until the device completes a graph)
+	pub rolling_gps: f64,
+}
+
+impl Deref for DeviceStats {
+	type Target = SolverStats;
+
+	fn deref(&self) -> &SolverStats {
+		&self.solver
+	}
+}
+
+impl DerefMut for DeviceStats {
+	fn deref_mut(&mut self) -> &mut SolverStats {
+		&mut self.solver
+	}
+}
+
+impl DeviceStats {
+	/// seconds since the solver was last (re)started
+	pub fn uptime_secs(&self) -> u64 {
+		if self.start_time == 0 {
+			return 0;
+		}
+		now_nanos().saturating_sub(self.start_time) / 1_000_000_000
+	}
+
+	/// record the solver being (re)started now, counting it as a restart if
+	/// it had been started before
+	pub fn mark_started(&mut self) {
+		if self.start_time != 0 {
+			self.restarts += 1;
+		}
+		self.start_time = now_nanos();
+	}
+
+	/// rolling average graphs per second if it's been set, otherwise the
+	/// rate of the last completed graph
+	pub fn average_graphs_per_second(&self) -> Option<f64> {
+		if self.rolling_gps > 0.0 {
+			Some(self.rolling_gps)
+		} else {
+			self.graphs_per_second()
+		}
+	}
+
+	/// combined graphs per second of all devices that have completed a graph
+	/// and haven't errored
+	pub fn combined_graphs_per_second(stats: &[DeviceStats]) -> f64 {
+		stats
+			.iter()
+			.filter(|s| !s.has_errored)
+			.filter_map(|s| s.graphs_per_second())
+			.sum()
+	}
+}
+
+/// Solutions as a plugin reported them, along with the solver that found
+/// them
+#[derive(Clone, Copy, Default)]
+pub struct FoundSolutions {
+	/// solutions filled in by the plugin
+	pub solutions: SolverSolutions,
+	/// index of the solver that found the solutions
+	pub instance: u32,
+	/// block height of the job the solutions were found for
+	pub height: u64,
+	/// computed difficulty of each solution
+	pub difficulties: [u64; MAX_SOLS],
+}
+
+impl Deref for FoundSolutions {
+	type Target = SolverSolutions;
+
+	fn deref(&self) -> &SolverSolutions {
+		&self.solutions
+	}
+}
+
+impl DerefMut for FoundSolutions {
+	fn deref_mut(&mut self) -> &mut SolverSolutions {
+		&mut self.solutions
+	}
 }
 
 impl SolverInstance {
-	/// Create a new solver instance with the given config
-	pub fn new(config: PluginConfig) -> Result<SolverInstance, CuckooMinerError> {
+	/// Create a new solver instance with the given config, reusing solver
+	/// contexts from the given cache
+	pub fn new(
+		config: PluginConfig,
+		ctx_cache: SolverCtxCacheType,
+	) -> Result<SolverInstance, CuckooMinerError> {
 		let l = PluginLibrary::new(&config.file)?;
 		Ok(SolverInstance {
 			lib: l,
 			config,
 			stats: SolverStats::default(),
 			solutions: SolverSolutions::default(),
+			ctx_cache,
 		})
 	}
 
-	/// Release the lib
+	/// Get a solver context for the current config, reusing a cached one
+	/// if available
+	pub fn acquire_ctx(&mut self) -> *mut SolverCtx {
+		let key = SolverCtxKey::from_config(&self.config);
+		let cached = self.ctx_cache.lock().unwrap().take(&key);
+		match cached {
+			Some(ctx) => ctx,
+			None => self.lib.create_solver_ctx(&mut self.config.params),
+		}
+	}
+
+	/// Return a solver context created with the current config to the
+	/// cache. The cache keeps the plugin loaded until it destroys the context
+	pub fn release_ctx(&mut self, ctx: *mut SolverCtx) {
+		let key = SolverCtxKey::from_config(&self.config);
+		let lib = self.lib.clone();
+		let destroy = Box::new(move |c| lib.destroy_solver_ctx(c));
+		self.ctx_cache.lock().unwrap().put(key, ctx, destroy);
+	}
+
+	/// Release the lib. Contexts handed back to the cache stay valid
 	pub fn unload(&mut self) {
 		self.lib.unload();
 	}
 }
 
+/// Live state of a running solver, for dashboards and monitoring
+#[derive(Clone, Debug, PartialEq)]
+pub struct SolverState {
+	/// Index of the solver
+	pub instance: usize,
+	/// Plugin the solver runs
+	pub plugin_name: String,
+	/// Device id
+	pub device_id: u32,
+	/// Device name, as reported by the plugin
+	pub device_name: String,
+	/// Whether mining is paused on the device
+	pub paused: bool,
+	/// Whether the device has reported an error
+	pub errored: bool,
+	/// Whether the device has gone without completing a graph for too
+	/// long, while there's a job for it
+	pub hung: bool,
+	/// Graphs completed since the solver started
+	pub iterations: u32,
+	/// Graphs per second from the last completed graph, if any
+	pub graphs_per_second: Option<f64>,
+}
+
+impl SolverState {
+	/// State of a solver given its stats, at `now` nanoseconds since the
+	/// epoch. A working solver counts as hung once it's gone `hung_secs`
+	/// since it started or last completed a graph
+	pub fn from_stats(
+		instance: usize,
+		stats: &DeviceStats,
+		has_job: bool,
+		now: u64,
+		hung_secs: u64,
+	) -> SolverState {
+		let last_progress = stats.last_end_time.max(stats.start_time);
+		let idle_secs = now.saturating_sub(last_progress) / 1_000_000_000;
+		let working = has_job && !stats.paused && !stats.has_errored;
+		SolverState {
+			instance,
+			plugin_name: stats.get_plugin_name(),
+			device_id: stats.device_id,
+			device_name: stats.get_device_name(),
+			paused: stats.paused,
+			errored: stats.has_errored,
+			hung: working && last_progress > 0 && idle_secs >= hung_secs,
+			iterations: stats.iterations,
+			graphs_per_second: stats.graphs_per_second(),
+		}
+	}
+}
+
 /// Data intended to be shared across threads
 pub struct JobSharedData {
 	/// ID of the current running job (not currently used)
@@ -72,10 +353,14 @@ pub struct JobSharedData {
 	pub difficulty: u64,
 
 	/// Output solutions
-	pub solutions: Vec<SolverSolutions>,
+	pub solutions: Vec<FoundSolutions>,
 
 	/// Current stats
-	pub stats: Vec<SolverStats>,
+	pub stats: Vec<DeviceStats>,
+
+	/// Graphs attempted by each solver before it was last restarted, as
+	/// its iterations count from zero again
+	pub graphs_before_restart: Vec<u64>,
 }
 
 impl Default for JobSharedData {
@@ -88,6 +373,7 @@ impl Default for JobSharedData {
 			difficulty: 0,
 			solutions: Vec::new(),
 			stats: vec![],
+			graphs_before_restart: vec![],
 		}
 	}
 }
@@ -101,7 +387,98 @@ impl JobSharedData {
 			post_nonce: String::from(""),
 			difficulty: 1,
 			solutions: Vec::new(),
-			stats: vec![SolverStats::default(); num_solvers],
+			stats: vec![DeviceStats::default(); num_solvers],
+			graphs_before_restart: vec![0; num_solvers],
 		}
 	}
+
+	/// Set aside the iterations of the solver in the given slot before a
+	/// new solver starts there, so they're only counted once
+	pub fn snapshot_iterations(&mut self, instance: usize) {
+		let iterations = self.stats[instance].iterations as u64;
+		self.graphs_before_restart[instance] += iterations;
+		self.stats[instance].iterations = 0;
+	}
+
+	/// Graphs attempted by all solvers since the miner was created
+	pub fn total_graphs(&self) -> u64 {
+		self.graphs_before_restart.iter().sum::<u64>()
+			+ self.stats.iter().map(|s| s.iterations as u64).sum::<u64>()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_solver_state_hung() {
+		let secs = 1_000_000_000;
+		let mut stats = DeviceStats {
+			start_time: 100 * secs,
+			..DeviceStats::default()
+		};
+		stats.device_id = 2;
+		stats.set_device_name("GTX 1080");
+		let state = SolverState::from_stats(1, &stats, true, 150 * secs, 120);
+		assert_eq!(state.device_name, "GTX 1080");
+		assert_eq!((state.instance, state.device_id), (1, 2));
+		assert!(!state.hung);
+		assert_eq!(state.graphs_per_second, None);
+		assert!(SolverState::from_stats(1, &stats, true, 220 * secs, 120).hung);
+		// nothing to work on, or not meant to be working
+		assert!(!SolverState::from_stats(1, &stats, false, 220 * secs, 120).hung);
+		stats.paused = true;
+		assert!(!SolverState::from_stats(1, &stats, true, 220 * secs, 120).hung);
+		stats.paused = false;
+		// a graph completed recently
+		stats.last_end_time = 210 * secs;
+		stats.last_solution_time = secs / 2;
+		let state = SolverState::from_stats(1, &stats, true, 220 * secs, 120);
+		assert!(!state.hung);
+		assert_eq!(state.graphs_per_second, Some(2.0));
+	}
+
+	#[test]
+	fn test_total_graphs() {
+		let mut sd = JobSharedData::new(2);
+		sd.stats[0].iterations = 10;
+		sd.stats[1].iterations = 5;
+		assert_eq!(sd.total_graphs(), 15);
+		// the restarted solver counts from zero again
+		sd.snapshot_iterations(0);
+		assert_eq!(sd.total_graphs(), 15);
+		sd.stats[0].iterations = 3;
+		assert_eq!(sd.total_graphs(), 18);
+		sd.snapshot_iterations(0);
+		sd.snapshot_iterations(0);
+		assert_eq!(sd.total_graphs(), 18);
+	}
+
+	#[test]
+	fn test_combined_gps() {
+		// no device has completed a graph yet
+		let mut stats = vec![DeviceStats::default(); 3];
+		assert_eq!(stats[0].graphs_per_second(), None);
+		assert_eq!(DeviceStats::combined_graphs_per_second(&stats), 0.0);
+
+		stats[0].last_solution_time = 500_000_000;
+		stats[1].last_solution_time = 250_000_000;
+		assert_eq!(DeviceStats::combined_graphs_per_second(&stats), 6.0);
+
+		stats[1].has_errored = true;
+		assert_eq!(DeviceStats::combined_graphs_per_second(&stats), 2.0);
+	}
+
+	#[test]
+	fn test_average_gps() {
+		let mut stats = vec![DeviceStats::default(); 3];
+		stats[0].last_solution_time = 500_000_000;
+
+		// smoothed rate once grin-miner has set it
+		assert_eq!(stats[0].average_graphs_per_second(), Some(2.0));
+		stats[0].rolling_gps = 2.5;
+		assert_eq!(stats[0].average_graphs_per_second(), Some(2.5));
+		assert_eq!(stats[2].average_graphs_per_second(), None);
+	}
 }
@@ -15,7 +15,8 @@
 //! header manipulation utility functions
 
 use byteorder::{BigEndian, ByteOrder};
-use rand::{self, Rng};
+use rand::{Isaac64Rng, OsRng, Rng, SeedableRng};
+use util::LOGGER;
 
 pub fn header_data(pre_nonce: &str, post_nonce: &str, nonce: u64) -> (Vec<u8>, u32) {
 	// Turn input strings into vectors
@@ -36,12 +37,78 @@ pub fn header_data(pre_nonce: &str, post_nonce: &str, nonce: u64) -> (Vec<u8>, u
 	(pre_vec, sec_scaling)
 }
 
-pub fn get_next_header_data(pre_nonce: &str, post_nonce: &str) -> (u64, Vec<u8>, u32) {
-	let nonce: u64 = rand::OsRng::new().unwrap().gen();
+/// Source of the nonces a solver tries, OS random unless seeded, so a
+/// specific solve can be reproduced when debugging
+pub enum NonceSource {
+	Random(OsRng),
+	Seeded(Box<Isaac64Rng>),
+}
+
+impl NonceSource {
+	pub fn new(seed: Option<u64>) -> NonceSource {
+		match seed {
+			Some(s) => NonceSource::Seeded(Box::new(Isaac64Rng::from_seed(&[s][..]))),
+			None => NonceSource::Random(OsRng::new().unwrap()),
+		}
+	}
+
+	pub fn next_nonce(&mut self) -> u64 {
+		match *self {
+			NonceSource::Random(ref mut r) => r.gen(),
+			NonceSource::Seeded(ref mut r) => r.gen(),
+		}
+	}
+}
+
+pub fn get_next_header_data(
+	pre_nonce: &str,
+	post_nonce: &str,
+	nonces: &mut NonceSource,
+) -> (u64, Vec<u8>, u32) {
+	get_header_data(pre_nonce, post_nonce, nonces.next_nonce())
+}
+
+/// As `get_next_header_data`, but for a nonce chosen by the caller
+pub fn get_header_data(pre_nonce: &str, post_nonce: &str, nonce: u64) -> (u64, Vec<u8>, u32) {
 	let (hd, sec_scaling) = header_data(pre_nonce, post_nonce, nonce);
 	(nonce, hd, sec_scaling)
 }
 
+/// Nonce to pass to a plugin mutating the nonce itself, so the little
+/// endian u32 it writes over the end of the header leaves the low bytes of
+/// the big endian header nonce as they are
+pub fn plugin_nonce(nonce: u64) -> u64 {
+	(nonce as u32).swap_bytes() as u64
+}
+
+/// Header nonce of a solution found by a plugin mutating the nonce itself,
+/// at plugin nonce `found` of a run started from header nonce `nonce`
+pub fn header_nonce(nonce: u64, found: u64) -> u64 {
+	(nonce & !0xFFFF_FFFF) | (found as u32).swap_bytes() as u64
+}
+
+/// Nonce argument to `run_solver` for a header built with header nonce
+/// `nonce`. Plugins mutating the nonce write it over the end of the header,
+/// others hash the header as given, where the nonce is already set, and
+/// are passed it as is so any nonce they report is the one submitted
+pub fn solver_nonce(nonce: u64, mutate_nonce: bool) -> u64 {
+	if mutate_nonce {
+		plugin_nonce(nonce)
+	} else {
+		nonce
+	}
+}
+
+/// Header nonce to submit for a solution reporting nonce `found`, from a
+/// run on a header built with header nonce `nonce`
+pub fn solution_nonce(nonce: u64, mutate_nonce: bool, found: u64) -> u64 {
+	if mutate_nonce {
+		header_nonce(nonce, found)
+	} else {
+		nonce
+	}
+}
+
 /// Helper to convert a hex string
 pub fn from_hex_string(in_str: &str) -> Vec<u8> {
 	let mut bytes = Vec::new();
@@ -49,8 +116,142 @@ pub fn from_hex_string(in_str: &str) -> Vec<u8> {
 		let res = u8::from_str_radix(&in_str[2 * i..2 * i + 2], 16);
 		match res {
 			Ok(v) => bytes.push(v),
-			Err(e) => println!("Problem with hex: {}", e),
+			Err(e) => error!(LOGGER, "Problem with hex: {}", e),
 		}
 	}
 	bytes
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use plugin::SiphashKeyCache;
+
+	const PRE: &str = "0001020304050607000000aa";
+	const POST: &str = "f0f1f2";
+
+	#[test]
+	fn test_header_data() {
+		let nonce = 0x0102_0304_0506_0708;
+		let (header, sec_scaling) = header_data(PRE, POST, nonce);
+		let mut expected = from_hex_string(PRE);
+		expected.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+		expected.extend_from_slice(&from_hex_string(POST));
+		assert_eq!(header, expected);
+		// secondary scaling is the last 4 bytes of the pre-nonce part
+		assert_eq!(sec_scaling, 0xaa);
+	}
+
+	#[test]
+	fn test_header_data_nonce_position() {
+		let pre_len = PRE.len() / 2;
+		let (h1, _) = header_data(PRE, POST, 0);
+		let (h2, _) = header_data(PRE, POST, u64::MAX);
+		assert_eq!(h1.len(), h2.len());
+		for i in 0..h1.len() {
+			let in_nonce = i >= pre_len && i < pre_len + 8;
+			assert_eq!(h1[i] != h2[i], in_nonce, "byte {}", i);
+		}
+		let (nonce, h3, _) = get_header_data(PRE, POST, 42);
+		assert_eq!(nonce, 42);
+		assert_eq!(h3, header_data(PRE, POST, 42).0);
+	}
+
+	#[test]
+	fn test_seeded_nonces() {
+		let mut a = NonceSource::new(Some(42));
+		let mut b = NonceSource::new(Some(42));
+		let seq_a: Vec<u64> = (0..16).map(|_| a.next_nonce()).collect();
+		let seq_b: Vec<u64> = (0..16).map(|_| b.next_nonce()).collect();
+		assert_eq!(seq_a, seq_b);
+		let mut c = NonceSource::new(Some(43));
+		let seq_c: Vec<u64> = (0..16).map(|_| c.next_nonce()).collect();
+		assert_ne!(seq_a, seq_c);
+		let (nonce, header, _) = get_next_header_data(PRE, POST, &mut NonceSource::new(Some(42)));
+		assert_eq!(nonce, seq_a[0]);
+		assert_eq!(header, header_data(PRE, POST, nonce).0);
+	}
+
+	#[test]
+	fn test_plugin_nonce() {
+		let nonce = 0x0102_0304_0506_0708;
+		// what a plugin writes over the last 4 bytes of the header
+		let (mut header, _) = header_data(PRE, "", nonce);
+		let len = header.len();
+		let written = (plugin_nonce(nonce) as u32).to_le_bytes();
+		header[len - 4..].copy_from_slice(&written);
+		assert_eq!(header, header_data(PRE, "", nonce).0);
+		assert_eq!(header_nonce(nonce, plugin_nonce(nonce)), nonce);
+		// next nonce tried by the plugin within a range
+		let (next, _) = header_data(PRE, "", header_nonce(nonce, plugin_nonce(nonce) + 1));
+		let mut header = header_data(PRE, "", nonce).0;
+		header[len - 4..].copy_from_slice(&(plugin_nonce(nonce) as u32 + 1).to_le_bytes());
+		assert_eq!(header, next);
+	}
+
+	#[test]
+	fn test_distinct_nonces_across_iterations() {
+		// every header hashed over consecutive solver calls, each sweeping a
+		// range of nonces from a fresh random start
+		let range = 64;
+		let mut nonces = NonceSource::new(Some(7));
+		let mut hashed = vec![];
+		for _ in 0..32 {
+			let (nonce, _, _) = get_next_header_data(PRE, "", &mut nonces);
+			let start = solver_nonce(nonce, true);
+			for found in start..start + range {
+				hashed.push(solution_nonce(nonce, true, found));
+			}
+		}
+		let count = hashed.len();
+		hashed.sort();
+		hashed.dedup();
+		assert_eq!(hashed.len(), count);
+	}
+
+	#[test]
+	fn test_hashed_header_matches_submitted() {
+		for &mutate_nonce in &[false, true] {
+			let (nonce, header, _) = get_header_data(PRE, "", 0x0102_0304_0506_0708);
+			let run_nonce = solver_nonce(nonce, mutate_nonce);
+			for offset in 0..3 {
+				// what the plugin hashes, and the nonce it reports, for each
+				// nonce of its range
+				let found = run_nonce + offset;
+				let mut hashed = header.clone();
+				if mutate_nonce {
+					let len = hashed.len();
+					hashed[len - 4..].copy_from_slice(&(found as u32).to_le_bytes());
+				} else if offset > 0 {
+					// plugins left to the caller are only run over one nonce
+					continue;
+				}
+				let submitted = solution_nonce(nonce, mutate_nonce, found);
+				assert_eq!(hashed, header_data(PRE, "", submitted).0);
+				if !mutate_nonce {
+					assert_eq!(found, submitted);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn test_siphash_key_cache_across_iterations() {
+		for &mutate_nonce in &[false, true] {
+			let mut nonces = NonceSource::new(Some(7));
+			let mut keys = SiphashKeyCache::default();
+			let mut last = None;
+			for _ in 0..2 {
+				// the header and nonce a solver thread hands the plugin
+				let (nonce, header, _) = get_next_header_data(PRE, "", &mut nonces);
+				let run_nonce = solver_nonce(nonce, mutate_nonce) as u32;
+				let k = keys.keys(&header, run_nonce, mutate_nonce);
+				let fresh = SiphashKeyCache::default().keys(&header, run_nonce, mutate_nonce);
+				assert_eq!(k, fresh);
+				assert_ne!(Some(k), last);
+				last = Some(k);
+			}
+			assert_eq!(keys.hits, 1);
+		}
+	}
+}
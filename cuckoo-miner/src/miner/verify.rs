@@ -0,0 +1,315 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cuckoo cycle verification of found solutions, as grin does it, so
+//! invalid solutions can be dropped before they reach the pool
+
+use blake2::blake2b::blake2b;
+use byteorder::{ByteOrder, LittleEndian};
+use plugin::PROOFSIZE;
+
+/// Edges hashed together in a cuckaroo siphash block
+const SIPHASH_BLOCK_SIZE: u64 = 64;
+const SIPHASH_BLOCK_MASK: u64 = SIPHASH_BLOCK_SIZE - 1;
+
+/// How the endpoints shared by consecutive edges of a cycle match up
+#[derive(Clone, Copy)]
+enum CycleRule {
+	/// cuckaroo, where consecutive edges meet at the same node
+	SameNode,
+	/// cuckatoo, where consecutive edges end at nodes differing only in
+	/// their lowest bit
+	PairedNode,
+}
+
+/// Siphash keys of a header, from its blake2b hash
+pub fn siphash_keys(header: &[u8]) -> [u64; 4] {
+	let hash = blake2b(32, &[], header);
+	let h = hash.as_bytes();
+	[
+		LittleEndian::read_u64(&h[0..8]),
+		LittleEndian::read_u64(&h[8..16]),
+		LittleEndian::read_u64(&h[16..24]),
+		LittleEndian::read_u64(&h[24..32]),
+	]
+}
+
+struct SipHash24 {
+	v: [u64; 4],
+}
+
+impl SipHash24 {
+	fn new(keys: &[u64; 4]) -> SipHash24 {
+		SipHash24 { v: *keys }
+	}
+
+	fn hash(&mut self, nonce: u64) -> u64 {
+		self.v[3] ^= nonce;
+		self.round();
+		self.round();
+		self.v[0] ^= nonce;
+		self.v[2] ^= 0xff;
+		for _ in 0..4 {
+			self.round();
+		}
+		self.v[0] ^ self.v[1] ^ self.v[2] ^ self.v[3]
+	}
+
+	fn round(&mut self) {
+		let v = &mut self.v;
+		v[0] = v[0].wrapping_add(v[1]);
+		v[2] = v[2].wrapping_add(v[3]);
+		v[1] = v[1].rotate_left(13);
+		v[3] = v[3].rotate_left(16);
+		v[1] ^= v[0];
+		v[3] ^= v[2];
+		v[0] = v[0].rotate_left(32);
+		v[2] = v[2].wrapping_add(v[1]);
+		v[0] = v[0].wrapping_add(v[3]);
+		v[1] = v[1].rotate_left(17);
+		v[3] = v[3].rotate_left(21);
+		v[1] ^= v[2];
+		v[3] ^= v[0];
+		v[2] = v[2].rotate_left(32);
+	}
+}
+
+/// Siphash-2-4 of a single nonce, as cuckatoo hashes edge endpoints
+pub fn siphash24(keys: &[u64; 4], nonce: u64) -> u64 {
+	SipHash24::new(keys).hash(nonce)
+}
+
+/// Cuckaroo edge hash, siphashing the whole block of 64 edges the nonce is
+/// in and folding the block's last hash into it
+fn siphash_block(keys: &[u64; 4], nonce: u64) -> u64 {
+	let nonce0 = nonce & !SIPHASH_BLOCK_MASK;
+	let mut sip = SipHash24::new(keys);
+	let hashes: Vec<u64> = (0..SIPHASH_BLOCK_SIZE)
+		.map(|i| sip.hash(nonce0 + i))
+		.collect();
+	let i = (nonce & SIPHASH_BLOCK_MASK) as usize;
+	if i == SIPHASH_BLOCK_MASK as usize {
+		hashes[i]
+	} else {
+		hashes[i] ^ hashes[SIPHASH_BLOCK_MASK as usize]
+	}
+}
+
+/// Check a cuckatoo proof, each nonce's endpoints being the siphashes of
+/// 2 * nonce and 2 * nonce + 1
+pub fn verify_cuckatoo(keys: &[u64; 4], edge_bits: u8, nonces: &[u64]) -> Result<(), String> {
+	let mask = edge_mask(edge_bits);
+	verify_edges(nonces, mask, CycleRule::PairedNode, |n| {
+		(
+			siphash24(keys, 2 * n) & mask,
+			siphash24(keys, 2 * n + 1) & mask,
+		)
+	})
+}
+
+/// Check a cuckaroo proof, each nonce's endpoints being the low and high
+/// halves of its siphash block
+pub fn verify_cuckaroo(keys: &[u64; 4], edge_bits: u8, nonces: &[u64]) -> Result<(), String> {
+	let mask = edge_mask(edge_bits);
+	verify_edges(nonces, mask, CycleRule::SameNode, |n| {
+		let edge = siphash_block(keys, n);
+		(edge & mask, (edge >> 32) & mask)
+	})
+}
+
+fn edge_mask(edge_bits: u8) -> u64 {
+	(1u64 << edge_bits) - 1
+}
+
+/// Check the nonces are in range and ascending, and that the edges they
+/// generate form a single cycle through all of them
+fn verify_edges<F>(nonces: &[u64], mask: u64, rule: CycleRule, endpoints: F) -> Result<(), String>
+where
+	F: Fn(u64) -> (u64, u64),
+{
+	if nonces.len() != PROOFSIZE {
+		return Err(format!("proof has {} nonces", nonces.len()));
+	}
+	let mut uvs = vec![0u64; 2 * PROOFSIZE];
+	for (n, nonce) in nonces.iter().enumerate() {
+		if *nonce > mask {
+			return Err(format!("nonce {} is too big", nonce));
+		}
+		if n > 0 && *nonce <= nonces[n - 1] {
+			return Err(format!("nonce {} is out of order", nonce));
+		}
+		let (u, v) = endpoints(*nonce);
+		uvs[2 * n] = u;
+		uvs[2 * n + 1] = v;
+	}
+	verify_cycle(&uvs, rule)
+}
+
+/// Check the edges, given as consecutive u and v endpoints, form a single
+/// cycle of proof size
+fn verify_cycle(uvs: &[u64], rule: CycleRule) -> Result<(), String> {
+	// every node of a cycle is an endpoint of exactly two of its edges. With
+	// cuckatoo each of the proof size / 2 pairs of endpoints on a side
+	// differs in the lowest bit instead
+	let xor_start = match rule {
+		CycleRule::SameNode => 0,
+		CycleRule::PairedNode => (PROOFSIZE as u64 / 2) & 1,
+	};
+	let xor0 = uvs.iter().step_by(2).fold(xor_start, |x, u| x ^ u);
+	let xor1 = uvs.iter().skip(1).step_by(2).fold(xor_start, |x, v| x ^ v);
+	if xor0 | xor1 != 0 {
+		return Err("endpoints don't match up".to_string());
+	}
+	let joined = |a: u64, b: u64| match rule {
+		CycleRule::SameNode => a == b,
+		CycleRule::PairedNode => a >> 1 == b >> 1,
+	};
+	let len = uvs.len();
+	let mut n = 0;
+	let mut i = 0;
+	loop {
+		// find the other edge sharing endpoint i, on the same side
+		let mut j = i;
+		let mut k = (i + 2) % len;
+		while k != i {
+			if joined(uvs[k], uvs[i]) {
+				if j != i {
+					return Err("cycle branches".to_string());
+				}
+				j = k;
+			}
+			k = (k + 2) % len;
+		}
+		// a cuckatoo edge can't continue from the very node it ended at
+		if j == i || (matches!(rule, CycleRule::PairedNode) && uvs[j] == uvs[i]) {
+			return Err("cycle dead ends".to_string());
+		}
+		i = j ^ 1;
+		n += 1;
+		if i == 0 {
+			break;
+		}
+	}
+	if n == len / 2 {
+		Ok(())
+	} else {
+		Err(format!("cycle of length {} is too short", n))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	// endpoints of a cycle of the given number of edges, with nodes
+	// numbered from first
+	fn ring(first: u64, edges: u64) -> Vec<u64> {
+		let mut uvs = vec![];
+		for n in 0..edges {
+			let m = n / 2;
+			uvs.push(first + m);
+			uvs.push(1000 + first + if n % 2 == 0 { m } else { (m + 1) % (edges / 2) });
+		}
+		uvs
+	}
+
+	#[test]
+	fn test_siphash24() {
+		assert_eq!(siphash24(&[1, 2, 3, 4], 10), 928382149599306901);
+		assert_eq!(siphash24(&[1, 2, 3, 4], 111), 10524991083049122233);
+		assert_eq!(siphash24(&[9, 7, 6, 7], 12), 1305683875471634734);
+	}
+
+	#[test]
+	fn test_verify_cycle() {
+		assert_eq!(
+			verify_cycle(&ring(0, PROOFSIZE as u64), CycleRule::SameNode),
+			Ok(())
+		);
+
+		let mut two_cycles = ring(0, 20);
+		two_cycles.extend(ring(100, PROOFSIZE as u64 - 20));
+		assert_eq!(
+			verify_cycle(&two_cycles, CycleRule::SameNode),
+			Err("cycle of length 20 is too short".to_string())
+		);
+
+		let mut unmatched = ring(0, PROOFSIZE as u64);
+		unmatched[1] = 999;
+		assert_eq!(
+			verify_cycle(&unmatched, CycleRule::SameNode),
+			Err("endpoints don't match up".to_string())
+		);
+	}
+
+	// cuckatoo 29 solution for an empty header with nonce 20, from grin
+	const CUCKATOO29_SOL: [u64; PROOFSIZE] = [
+		0x48a9e2, 0x9cf043, 0x155ca30, 0x18f4783, 0x248f86c, 0x2629a64, 0x5bad752, 0x72e3569,
+		0x93db760, 0x97d3b37, 0x9e05670, 0xa315d5a, 0xa3571a1, 0xa48db46, 0xa7796b6, 0xac43611,
+		0xb64912f, 0xbb6c71e, 0xbcc8be1, 0xc38a43a, 0xd4faa99, 0xe018a66, 0xe37e49c, 0xfa975fa,
+		0x11786035, 0x1243b60a, 0x12892da0, 0x141b5453, 0x1483c3a0, 0x1505525e, 0x1607352c,
+		0x16181fe3, 0x17e3a1da, 0x180b651e, 0x1899d678, 0x1931b0bb, 0x19606448, 0x1b041655,
+		0x1b2c20ad, 0x1bd7a83c, 0x1c05d5b0, 0x1c0b9caa,
+	];
+
+	// cuckaroo 19 siphash keys and solution for an empty header with
+	// nonce 71, from grin
+	const CUCKAROO19_KEYS: [u64; 4] = [
+		0x23796193872092ea,
+		0xf1017d8a68c4b745,
+		0xd312bd53d2cd307b,
+		0x840acce5833ddc52,
+	];
+	const CUCKAROO19_SOL: [u64; PROOFSIZE] = [
+		0x45e9, 0x6a59, 0xf1ad, 0x10ef7, 0x129e8, 0x13e58, 0x17936, 0x19f7f, 0x208df, 0x23704,
+		0x24564, 0x27e64, 0x2b828, 0x2bb41, 0x2ffc0, 0x304c5, 0x31f2a, 0x347de, 0x39686, 0x3ab6c,
+		0x429ad, 0x45254, 0x49200, 0x4f8f8, 0x5697f, 0x57ad1, 0x5dd47, 0x607f8, 0x66199, 0x686c7,
+		0x6d5f3, 0x6da7a, 0x6dbdf, 0x6f6bf, 0x6ffbb, 0x7580e, 0x78594, 0x785ac, 0x78b1d, 0x7b80d,
+		0x7c11c, 0x7da35,
+	];
+
+	#[test]
+	fn test_verify_nonces() {
+		let mut header = [0; 80];
+		header[76] = 20;
+		let keys = siphash_keys(&header);
+		assert_eq!(verify_cuckatoo(&keys, 29, &CUCKATOO29_SOL), Ok(()));
+		assert_eq!(
+			verify_cuckaroo(&CUCKAROO19_KEYS, 19, &CUCKAROO19_SOL),
+			Ok(())
+		);
+		// the same proofs checked under the other algorithm's rules
+		assert!(verify_cuckaroo(&keys, 29, &CUCKATOO29_SOL).is_err());
+		assert!(verify_cuckatoo(&CUCKAROO19_KEYS, 19, &CUCKAROO19_SOL).is_err());
+
+		let keys = siphash_keys(&[0; 80]);
+		assert!(verify_cuckatoo(&keys, 29, &CUCKATOO29_SOL).is_err());
+		let nonces: Vec<u64> = (0..PROOFSIZE as u64).collect();
+		assert!(verify_cuckatoo(&keys, 29, &nonces).is_err());
+		assert!(verify_cuckaroo(&keys, 29, &nonces).is_err());
+
+		let mut too_big = nonces.clone();
+		too_big[PROOFSIZE - 1] = 1 << 29;
+		assert_eq!(
+			verify_cuckatoo(&keys, 29, &too_big),
+			Err(format!("nonce {} is too big", 1 << 29))
+		);
+		let mut unordered = nonces;
+		unordered.swap(0, 1);
+		assert_eq!(
+			verify_cuckaroo(&keys, 29, &unordered),
+			Err("nonce 0 is out of order".to_string())
+		);
+	}
+}
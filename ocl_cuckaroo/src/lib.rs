@@ -16,16 +16,21 @@ use std::ptr;
 use std::time::{Duration, SystemTime};
 
 pub use self::finder::Graph;
-pub use self::trimmer::Trimmer;
+pub use self::trimmer::{Trimmer, DEFAULT_EDGE_BITS, DEFAULT_TRIM_ROUNDS};
 
 mod finder;
 mod trimmer;
 
+mod built_info {
+	include!(concat!(env!("OUT_DIR"), "/built.rs"));
+}
+
 #[repr(C)]
 struct Solver {
 	trimmer: Trimmer,
 	graph: Option<Graph>,
 	mutate_nonce: bool,
+	keys: SiphashKeyCache,
 }
 
 #[no_mangle]
@@ -36,12 +41,29 @@ pub unsafe extern "C" fn create_solver_ctx(params: *mut SolverParams) -> *mut So
 		_ => None,
 	};
 	let device_id = Some((*params).device as usize);
+	let edge_bits = match (*params).edge_bits {
+		0 => DEFAULT_EDGE_BITS,
+		e => e.min(u8::MAX as u32) as u8,
+	};
 
-	let trimmer = Trimmer::build(platform, device_id).expect("can't build trimmer");
+	let mut trimmer = match Trimmer::build(platform, device_id, edge_bits) {
+		Ok(t) => t,
+		Err(e) => {
+			eprintln!("Can't build trimmer: {}", e);
+			return ptr::null_mut();
+		}
+	};
+	if (*params).ntrims != 0 {
+		if let Err(e) = trimmer.set_trim_rounds((*params).ntrims as usize) {
+			eprintln!("Invalid ntrims: {}", e);
+			return ptr::null_mut();
+		}
+	}
 	let solver = Solver {
 		trimmer: trimmer,
 		graph: None,
 		mutate_nonce: (*params).mutate_nonce,
+		keys: SiphashKeyCache::default(),
 	};
 	let solver_box = Box::new(solver);
 	let solver_ref = Box::leak(solver_box);
@@ -62,7 +84,34 @@ pub unsafe extern "C" fn stop_solver(_solver_ctx_ptr: *mut SolverCtx) {}
 pub unsafe extern "C" fn fill_default_params(params: *mut SolverParams) {
 	(*params).device = 0;
 	(*params).platform = 0;
-	(*params).edge_bits = 29;
+	(*params).edge_bits = DEFAULT_EDGE_BITS as u32;
+	(*params).ntrims = DEFAULT_TRIM_ROUNDS as u32;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn plugin_build_info(info: *mut PluginBuildInfo) {
+	(*info).set_git_commit(built_info::GIT_VERSION.unwrap_or("unknown"));
+	(*info).set_build_date(built_info::BUILT_TIME_UTC);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn enumerate_devices(list: *mut DeviceList) {
+	// same platform numbering as the platform param, and the same
+	// platform and device lookup as create_solver_ctx
+	for &(platform_id, vendor) in [(1, "AMD"), (2, "NVIDIA")].iter() {
+		let platform = match trimmer::find_platform(Some(vendor)) {
+			Some(p) => p,
+			None => continue,
+		};
+		let devices = match trimmer::gpu_devices(&platform) {
+			Ok(d) => d,
+			Err(_) => continue,
+		};
+		for (i, device) in devices.iter().enumerate() {
+			let name = device.name().unwrap_or_default();
+			(*list).add_device(platform_id, i as u32, &name);
+		}
+	}
 }
 
 #[no_mangle]
@@ -71,50 +120,56 @@ pub unsafe extern "C" fn run_solver(
 	header_ptr: *const c_uchar,
 	header_length: u32,
 	nonce: u64,
-	_range: u32,
+	range: u32,
 	solutions: *mut SolverSolutions,
 	stats: *mut SolverStats,
 ) -> u32 {
 	let start = SystemTime::now();
 	let solver_ptr = mem::transmute::<*mut SolverCtx, *mut Solver>(ctx);
-	let solver = &*solver_ptr;
+	let solver = &mut *solver_ptr;
 	let mut header = Vec::with_capacity(header_length as usize + 32);
 	let r_ptr = header.as_mut_ptr();
 	ptr::copy_nonoverlapping(header_ptr, r_ptr, header_length as usize);
 	header.set_len(header_length as usize);
-	let n = nonce as u64;
-	let k = match set_header_nonce(&header, Some(n), solver.mutate_nonce) {
-		Err(_e) => {
-			return 2;
-		}
-		Ok(v) => v,
-	};
-	let res = solver.trimmer.run(&k).unwrap();
-
-	let sols = Graph::search(&res).unwrap();
+	// the nonce is only stepped over the range when written to the header
+	let range = if solver.mutate_nonce { range.max(1) } else { 1 };
 	let mut i = 0;
-	(*solutions).edge_bits = 29;
-	for sol in sols {
-		let (nonces_cand, valid) = solver.trimmer.recover(sol.nodes, &k).unwrap();
-		if valid {
-			let nonces = nonces_cand
-				.into_iter()
-				.map(|v| v as u64)
-				.collect::<Vec<u64>>();
-			(*solutions).sols[i].nonce = nonce;
-			(*solutions).sols[i].proof.copy_from_slice(&nonces[..]);
-			i += 1;
+	let mut edges_remaining = 0;
+	(*solutions).edge_bits = solver.trimmer.edge_bits as u32;
+	for n in 0..range {
+		let nonce = (nonce as u32).wrapping_add(n);
+		let k = solver.keys.keys(&header, nonce, solver.mutate_nonce);
+		let res = solver.trimmer.run(&k).unwrap();
+		// trimmer returns a flat list of edge endpoints
+		edges_remaining = (res.len() / 2) as u64;
+
+		let sols = Graph::search(&res).unwrap();
+		for sol in sols {
+			if i == MAX_SOLS {
+				break;
+			}
+			let (nonces_cand, valid) = solver.trimmer.recover(sol.nodes, &k).unwrap();
+			if valid {
+				let nonces = nonces_cand
+					.into_iter()
+					.map(|v| v as u64)
+					.collect::<Vec<u64>>();
+				(*solutions).sols[i].nonce = nonce as u64;
+				(*solutions).sols[i].proof.copy_from_slice(&nonces[..]);
+				i += 1;
+			}
 		}
 	}
 	(*solutions).num_sols = i as u32;
 	let end = SystemTime::now();
 	let elapsed = end.duration_since(start).unwrap();
-	(*stats).edge_bits = 29;
+	(*stats).edge_bits = solver.trimmer.edge_bits as u32;
 	(*stats).device_id = solver.trimmer.device_id as u32;
-	let name_bytes = solver.trimmer.device_name.as_bytes();
-	let n = std::cmp::min((*stats).device_name.len(), name_bytes.len());
-	(*stats).device_name[..n].copy_from_slice(&solver.trimmer.device_name.as_bytes()[..n]);
-	(*stats).last_solution_time = duration_to_u64(elapsed);
+	(*stats).set_device_name(&solver.trimmer.device_name);
+	// time per graph, rather than per call
+	(*stats).last_solution_time = duration_to_u64(elapsed / range);
+	(*stats).edges_remaining = edges_remaining;
+	(*stats).siphash_key_cache_hits = solver.keys.hits;
 	(*stats).last_start_time =
 		duration_to_u64(start.duration_since(SystemTime::UNIX_EPOCH).unwrap());
 	(*stats).last_end_time = duration_to_u64(end.duration_since(SystemTime::UNIX_EPOCH).unwrap());
@@ -158,13 +213,27 @@ pub fn create_siphash_keys(header: &[u8]) -> Result<[u64; 4], Error> {
 #[cfg(test)]
 mod tests {
 	use super::*;
+
+	#[test]
+	fn test_edge_bits_bounds() {
+		assert!(Trimmer::build(None, None, 23).is_err());
+		assert!(Trimmer::build(None, None, 32).is_err());
+	}
+
+	#[test]
+	fn test_invalid_edge_bits_no_ctx() {
+		let mut params = SolverParams::default();
+		params.edge_bits = 32;
+		assert!(unsafe { create_solver_ctx(&mut params) }.is_null());
+	}
+
 	#[ignore]
 	// results in Error executing function: clEnqueueNDRangeKernel("LeanRound")
 	//            Status error code: CL_INVALID_WORK_GROUP_SIZE (-54)
 	// on MacOSX
 	#[test]
 	fn test_solve() {
-		let trimmer = Trimmer::build(None, None).expect("can't build trimmer");
+		let trimmer = Trimmer::build(None, None, DEFAULT_EDGE_BITS).expect("can't build trimmer");
 		let k = [
 			0x27580576fe290177,
 			0xf9ea9b2031f4e76e,
@@ -1,10 +1,11 @@
 extern crate ocl_cuckaroo;
 
-use ocl_cuckaroo::{Graph, Trimmer};
+use ocl_cuckaroo::{Graph, Trimmer, DEFAULT_EDGE_BITS};
+use std::env;
 use std::time::SystemTime;
 
 fn main() -> Result<(), String> {
-	let trimmer = Trimmer::build(None, None).expect("can't build trimmer");
+	let trimmer = Trimmer::build(None, None, DEFAULT_EDGE_BITS).expect("can't build trimmer");
 	let k = [
 		0xf4956dc403730b01,
 		0xe6d45de39c2a5a3e,
@@ -39,6 +40,18 @@ fn main() -> Result<(), String> {
 				println!("Not valid");
 			}
 		}
+
+		// optional micro-benchmark of the per-run overhead, with the
+		// buffers already allocated
+		let runs: u32 = env::args().nth(1).and_then(|a| a.parse().ok()).unwrap_or(0);
+		if runs > 0 {
+			start = SystemTime::now();
+			for _ in 0..runs {
+				trimmer.run(&k).unwrap();
+			}
+			let elapsed = SystemTime::now().duration_since(start).unwrap();
+			println!("Average over {} runs: {:?}", runs, elapsed / runs);
+		}
 	}
 	Ok(())
 }
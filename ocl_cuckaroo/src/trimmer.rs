@@ -3,17 +3,24 @@ use ocl::enums::{ArgVal, DeviceInfo, DeviceInfoResult};
 use ocl::flags::{CommandQueueProperties, MemFlags};
 use ocl::prm::{Uint2, Ulong4};
 use ocl::{
-	Buffer, Context, Device, Event, EventList, Kernel, Platform, Program, Queue, SpatialDims,
+	Buffer, Context, Device, DeviceType, Event, EventList, Kernel, Platform, Program, Queue,
+	SpatialDims,
 };
+use plugin::PROOFSIZE;
 use std::collections::HashMap;
 use std::env;
 
+// bucket sizes, in units of 1024 edges, at 29 edge bits
 const DUCK_SIZE_A: usize = 129; // AMD 126 + 3
 const DUCK_SIZE_B: usize = 83;
-const BUFFER_SIZE_A1: usize = DUCK_SIZE_A * 1024 * (4096 - 128) * 2;
-const BUFFER_SIZE_A2: usize = DUCK_SIZE_A * 1024 * 256 * 2;
-const BUFFER_SIZE_B: usize = DUCK_SIZE_B * 1024 * 4096 * 2;
 const INDEX_SIZE: usize = 256 * 256 * 4;
+pub const DEFAULT_EDGE_BITS: u8 = 29;
+const MIN_EDGE_BITS: u8 = 24;
+// the kernel's nodes are u32, so 1 << EDGEBITS has to fit one
+const MAX_EDGE_BITS: u8 = 31;
+pub const DEFAULT_TRIM_ROUNDS: usize = 120;
+const MIN_TRIM_ROUNDS: usize = 8;
+const MAX_TRIM_ROUNDS: usize = 512;
 
 pub struct Trimmer {
 	q: Queue,
@@ -27,7 +34,37 @@ pub struct Trimmer {
 	buffer_nonces: Buffer<u32>,
 	pub device_name: String,
 	pub device_id: usize,
+	pub edge_bits: u8,
+	duck_size_a: usize,
+	duck_size_b: usize,
 	is_nvidia: bool,
+	trim_rounds: usize,
+}
+
+/// Scale a size tuned for 29 edge bits to the given graph size, rounding up
+fn scale_to_edge_bits(size: usize, edge_bits: u8) -> usize {
+	if edge_bits >= 29 {
+		size << (edge_bits - 29)
+	} else {
+		let shift = 29 - edge_bits;
+		(size + (1 << shift) - 1) >> shift
+	}
+}
+
+/// Kernel source for the given graph size
+fn kernel_src(edge_bits: u8, duck_size_a: usize, duck_size_b: usize) -> String {
+	SRC.replace(
+		"#define EDGEBITS 29",
+		&format!("#define EDGEBITS {}", edge_bits),
+	)
+	.replace(
+		"#define DUCK_SIZE_A 129L",
+		&format!("#define DUCK_SIZE_A {}L", duck_size_a),
+	)
+	.replace(
+		"#define DUCK_SIZE_B 83L",
+		&format!("#define DUCK_SIZE_B {}L", duck_size_b),
+	)
 }
 
 struct ClBufferParams {
@@ -82,7 +119,20 @@ macro_rules! kernel_builder(
 ));
 
 impl Trimmer {
-	pub fn build(platform_name: Option<&str>, device_id: Option<usize>) -> ocl::Result<Trimmer> {
+	pub fn build(
+		platform_name: Option<&str>,
+		device_id: Option<usize>,
+		edge_bits: u8,
+	) -> ocl::Result<Trimmer> {
+		if edge_bits < MIN_EDGE_BITS || edge_bits > MAX_EDGE_BITS {
+			return Err(format!(
+				"Edge bits must be between {} and {}, got {}",
+				MIN_EDGE_BITS, MAX_EDGE_BITS, edge_bits
+			)
+			.into());
+		}
+		let duck_size_a = scale_to_edge_bits(DUCK_SIZE_A, edge_bits);
+		let duck_size_b = scale_to_edge_bits(DUCK_SIZE_B, edge_bits);
 		env::set_var("GPU_MAX_HEAP_SIZE", "100");
 		env::set_var("GPU_USE_SYNC_OBJECTS", "1");
 		env::set_var("GPU_MAX_ALLOC_PERCENT", "100");
@@ -97,21 +147,21 @@ impl Trimmer {
 		buffers.insert(
 			"A1".to_string(),
 			ClBufferParams {
-				size: BUFFER_SIZE_A1,
+				size: duck_size_a * 1024 * (4096 - 128) * 2,
 				flags: MemFlags::empty(),
 			},
 		);
 		buffers.insert(
 			"A2".to_string(),
 			ClBufferParams {
-				size: BUFFER_SIZE_A2,
+				size: duck_size_a * 1024 * 256 * 2,
 				flags: MemFlags::empty(),
 			},
 		);
 		buffers.insert(
 			"B".to_string(),
 			ClBufferParams {
-				size: BUFFER_SIZE_B,
+				size: duck_size_b * 1024 * 4096 * 2,
 				flags: MemFlags::empty(),
 			},
 		);
@@ -132,7 +182,7 @@ impl Trimmer {
 		buffers.insert(
 			"R".to_string(),
 			ClBufferParams {
-				size: 42 * 2,
+				size: PROOFSIZE * 2,
 				flags: MemFlags::READ_ONLY,
 			},
 		);
@@ -145,6 +195,15 @@ impl Trimmer {
 		);
 
 		check_device_compatibility(&device, &buffers)?;
+		// the round kernels keep their edge counters in local memory
+		let local_mem_size: u64 = get_device_info!(device, LocalMemSize);
+		let counters_size = (8 << (edge_bits - 17)) as u64;
+		if counters_size > local_mem_size {
+			return Err(ocl::Error::from(format!(
+				"{} edge bits needs {} bytes of local memory, device has {}",
+				edge_bits, counters_size, local_mem_size
+			)));
+		}
 
 		let context = Context::builder()
 			.platform(platform)
@@ -155,7 +214,7 @@ impl Trimmer {
 
 		let program = Program::builder()
 			.devices(device)
-			.src(SRC)
+			.src(kernel_src(edge_bits, duck_size_a, duck_size_b))
 			.build(&context)?;
 
 		let buffer_a1 = build_buffer(buffers.get("A1"), &q)?;
@@ -178,10 +237,27 @@ impl Trimmer {
 			buffer_nonces,
 			device_name: device.name()?,
 			device_id: device_id.unwrap_or(0),
+			edge_bits,
+			duck_size_a,
+			duck_size_b,
 			is_nvidia: p_name.to_lowercase().contains("nvidia"),
+			trim_rounds: DEFAULT_TRIM_ROUNDS,
 		})
 	}
 
+	/// Set the number of trimming rounds, fewer is faster but finds fewer solutions
+	pub fn set_trim_rounds(&mut self, rounds: usize) -> ocl::Result<()> {
+		if rounds < MIN_TRIM_ROUNDS || rounds > MAX_TRIM_ROUNDS {
+			return Err(format!(
+				"Trim rounds must be between {} and {}, got {}",
+				MIN_TRIM_ROUNDS, MAX_TRIM_ROUNDS, rounds
+			)
+			.into());
+		}
+		self.trim_rounds = rounds;
+		Ok(())
+	}
+
 	pub unsafe fn recover(
 		&self,
 		mut nodes: Vec<u32>,
@@ -190,7 +266,8 @@ impl Trimmer {
 		let event_list = EventList::new();
 		let names = vec![];
 
-		let mut kernel_recovery = kernel_builder!(self, "FluffyRecovery", 2048 * 256)
+		let recovery_size = scale_to_edge_bits(2048 * 256, self.edge_bits);
+		let mut kernel_recovery = kernel_builder!(self, "FluffyRecovery", recovery_size)
 			.arg(k[0])
 			.arg(k[1])
 			.arg(k[2])
@@ -212,7 +289,7 @@ impl Trimmer {
 		self.buffer_r.cmd().write(edges.as_slice()).enq()?;
 		self.buffer_nonces.cmd().fill(0, None).enq()?;
 		kernel_enq!(kernel_recovery, event_list, names, "recovery");
-		let mut nonces: Vec<u32> = vec![0; 42];
+		let mut nonces: Vec<u32> = vec![0; PROOFSIZE];
 
 		self.buffer_nonces.cmd().read(&mut nonces).enq()?;
 		self.q.finish()?;
@@ -228,7 +305,8 @@ impl Trimmer {
 	}
 
 	pub unsafe fn run(&self, k: &[u64; 4]) -> ocl::Result<Vec<u32>> {
-		let mut kernel_seed_a = kernel_builder!(self, "FluffySeed2A", 2048 * 128)
+		let seed_size = scale_to_edge_bits(2048 * 128, self.edge_bits);
+		let mut kernel_seed_a = kernel_builder!(self, "FluffySeed2A", seed_size)
 			.arg(k[0])
 			.arg(k[1])
 			.arg(k[2])
@@ -285,8 +363,8 @@ impl Trimmer {
 			.arg(None::<&Buffer<Uint2>>)
 			.arg(None::<&Buffer<i32>>)
 			.arg(None::<&Buffer<i32>>)
-			.arg((DUCK_SIZE_A * 1024) as i32)
-			.arg((DUCK_SIZE_B * 1024) as i32)
+			.arg((self.duck_size_a * 1024) as i32)
+			.arg((self.duck_size_b * 1024) as i32)
 			.build()?;
 		if self.is_nvidia {
 			kernel_round1.set_default_local_work_size(SpatialDims::One(1024));
@@ -358,6 +436,8 @@ impl Trimmer {
 		let names = vec![];
 
 		let mut edges_count: Vec<u32> = vec![0; 1];
+		// only the small index buffers need clearing, the edge buffers are
+		// fully rewritten by the seed kernels on every run
 		clear_buffer!(self.buffer_i1);
 		clear_buffer!(self.buffer_i2);
 		kernel_enq!(kernel_seed_a, event_list, names, "seedA");
@@ -369,7 +449,7 @@ impl Trimmer {
 		kernel_enq!(kernel_round0, event_list, names, "roundN0");
 		clear_buffer!(self.buffer_i1);
 		kernel_enq!(kernel_round_nb, event_list, names, "roundNB");
-		for _ in 0..120 {
+		for _ in 0..self.trim_rounds {
 			clear_buffer!(self.buffer_i2);
 			kernel_enq!(kernel_round_na, event_list, names, "roundNA");
 			clear_buffer!(self.buffer_i1);
@@ -429,7 +509,7 @@ fn print_event(name: &str, ev: &Event) {
 #[cfg(not(feature = "profile"))]
 fn print_event(_name: &str, _ev: &Event) {}
 
-fn find_platform(selector: Option<&str>) -> Option<Platform> {
+pub(crate) fn find_platform(selector: Option<&str>) -> Option<Platform> {
 	match selector {
 		None => Some(Platform::default()),
 		Some(sel) => Platform::list().into_iter().find(|p| {
@@ -442,11 +522,17 @@ fn find_platform(selector: Option<&str>) -> Option<Platform> {
 	}
 }
 
+/// GPU devices of the platform, in the order the device param indexes them
+pub(crate) fn gpu_devices(platform: &Platform) -> ocl::Result<Vec<Device>> {
+	Device::list(platform, Some(DeviceType::GPU))
+}
+
 fn find_device(platform: &Platform, selector: Option<usize>) -> ocl::Result<Device> {
-	match selector {
-		None => Device::first(platform),
-		Some(index) => Device::by_idx_wrap(platform, index),
+	let devices = gpu_devices(platform)?;
+	if devices.is_empty() {
+		return Err("No GPU devices found".into());
 	}
+	Ok(devices[selector.unwrap_or(0) % devices.len()])
 }
 
 fn check_device_compatibility(
@@ -525,6 +611,8 @@ typedef u64 nonce_t;
 #define NEDGES ((node_t)1 << EDGEBITS)
 // used to mask siphash output
 #define EDGEMASK (NEDGES - 1)
+// two bit counters for the EDGEBITS - 12 node bits left in a bucket
+#define ECOUNTER_WORDS (2 << (EDGEBITS - 17))
 
 #define CTHREADS 1024
 #define BKTMASK4K (4096-1)
@@ -549,7 +637,7 @@ void Increase2bCounter(__local u32 * ecounters, const int bucket)
 	u32 old = atomic_or(ecounters + word, mask) & mask;
 
 	if (old > 0)
-		atomic_or(ecounters + word + 4096, mask);
+		atomic_or(ecounters + word + ECOUNTER_WORDS / 2, mask);
 }
 
 bool Read2bCounter(__local u32 * ecounters, const int bucket)
@@ -558,7 +646,7 @@ bool Read2bCounter(__local u32 * ecounters, const int bucket)
 	unsigned char bit = bucket & 0x1F;
 	u32 mask = 1 << bit;
 
-	return (ecounters[word + 4096] & mask) > 0;
+	return (ecounters[word + ECOUNTER_WORDS / 2] & mask) > 0;
 }
 
 __attribute__((reqd_work_group_size(128, 1, 1)))
@@ -758,13 +846,13 @@ __kernel   void FluffyRound1(const __global uint2 * source1, const __global uint
 	const __global uint2 * source = group < (62 * 64) ? source1 : source2;
 	int groupRead                 = group < (62 * 64) ? group : group - (62 * 64);
 
-	__local u32 ecounters[8192];
+	__local u32 ecounters[ECOUNTER_WORDS];
 
 	const int edgesInBucket = min(sourceIndexes[group], bktInSize);
 	const int loops = (edgesInBucket + CTHREADS) / CTHREADS;
 
-	for (int i = 0; i < 8; i++)
-		ecounters[lid + (1024 * i)] = 0;
+	for (int i = lid; i < ECOUNTER_WORDS; i += 1024)
+		ecounters[i] = 0;
 
 	barrier(CLK_LOCAL_MEM_FENCE);
 
@@ -819,13 +907,13 @@ __kernel   void FluffyRoundN(const __global uint2 * source, __global uint2 * des
 	const int bktInSize = DUCK_B_EDGES;
 	const int bktOutSize = DUCK_B_EDGES;
 
-	__local u32 ecounters[8192];
+	__local u32 ecounters[ECOUNTER_WORDS];
 
 	const int edgesInBucket = min(sourceIndexes[group], bktInSize);
 	const int loops = (edgesInBucket + CTHREADS) / CTHREADS;
 
-	for (int i = 0; i < 8; i++)
-		ecounters[lid + (1024 * i)] = 0;
+	for (int i = lid; i < ECOUNTER_WORDS; i += 1024)
+		ecounters[i] = 0;
 
 	barrier(CLK_LOCAL_MEM_FENCE);
 
@@ -880,13 +968,13 @@ __kernel   void FluffyRoundN_64(const __global uint2 * source, __global uint2 *
 	const int bktInSize = DUCK_B_EDGES;
 	const int bktOutSize = DUCK_B_EDGES;
 
-	__local u32 ecounters[8192];
+	__local u32 ecounters[ECOUNTER_WORDS];
 
 	const int edgesInBucket = min(sourceIndexes[group], bktInSize);
 	const int loops = (edgesInBucket + 64) / 64;
 
-	for (int i = 0; i < 8*16; i++)
-		ecounters[lid + (64 * i)] = 0;
+	for (int i = lid; i < ECOUNTER_WORDS; i += 64)
+		ecounters[i] = 0;
 
 	barrier(CLK_LOCAL_MEM_FENCE);
 
@@ -1032,13 +1120,13 @@ __kernel   void FluffyRoundNO1(const __global uint2 * source, __global uint2 * d
 	const int bktInSize = DUCK_B_EDGES;
 	const int bktOutSize = DUCK_B_EDGES;
 
-	__local u32 ecounters[8192];
+	__local u32 ecounters[ECOUNTER_WORDS];
 
 	const int edgesInBucket = min(sourceIndexes[group], bktInSize);
 	const int loops = (edgesInBucket + CTHREADS) / CTHREADS;
 
-	for (int i = 0; i < 8; i++)
-		ecounters[lid + (1024 * i)] = 0;
+	for (int i = lid; i < ECOUNTER_WORDS; i += 1024)
+		ecounters[i] = 0;
 
 	barrier(CLK_LOCAL_MEM_FENCE);
 
@@ -1093,13 +1181,13 @@ __kernel   void FluffyRoundNON(const __global uint2 * source, __global uint2 * d
 	const int bktInSize = DUCK_B_EDGES;
 	const int bktOutSize = DUCK_B_EDGES;
 
-	__local u32 ecounters[8192];
+	__local u32 ecounters[ECOUNTER_WORDS];
 
 	const int edgesInBucket = min(sourceIndexes[group], bktInSize);
 	const int loops = (edgesInBucket + CTHREADS) / CTHREADS;
 
-	for (int i = 0; i < 8; i++)
-		ecounters[lid + (1024 * i)] = 0;
+	for (int i = lid; i < ECOUNTER_WORDS; i += 1024)
+		ecounters[i] = 0;
 
 	barrier(CLK_LOCAL_MEM_FENCE);
 
@@ -1,4 +1,5 @@
 use hashbrown::HashMap;
+use plugin::PROOFSIZE;
 
 #[derive(Clone)]
 pub struct Solution {
@@ -160,7 +161,7 @@ impl Graph {
 			nonces: HashMap::with_capacity_and_hasher(edge_count, Default::default()),
 			adj_store: Vec::with_capacity(edge_count * 2),
 		};
-		let mut search = Search::new(edge_count * 2, 42);
+		let mut search = Search::new(edge_count * 2, PROOFSIZE);
 		const STEP: usize = 4;
 		for i in 1..=edge_count {
 			let n1 = edges[i * STEP];
@@ -21,11 +21,16 @@ pub use self::trimmer::Trimmer;
 mod finder;
 mod trimmer;
 
+mod built_info {
+	include!(concat!(env!("OUT_DIR"), "/built.rs"));
+}
+
 #[repr(C)]
 struct Solver {
 	trimmer: Trimmer,
 	graph: Option<Graph>,
 	mutate_nonce: bool,
+	keys: SiphashKeyCache,
 }
 
 #[no_mangle]
@@ -40,11 +45,18 @@ pub unsafe extern "C" fn create_solver_ctx(params: *mut SolverParams) -> *mut So
 	if edge_bits < 31 || edge_bits > 64 {
 		edge_bits = 31;
 	}
-	let trimmer = Trimmer::build(platform, device_id, edge_bits).expect("can't build trimmer");
+	let trimmer = match Trimmer::build(platform, device_id, edge_bits) {
+		Ok(t) => t,
+		Err(e) => {
+			eprintln!("Can't build trimmer: {}", e);
+			return ptr::null_mut();
+		}
+	};
 	let solver = Solver {
 		trimmer: trimmer,
 		graph: None,
 		mutate_nonce: (*params).mutate_nonce,
+		keys: SiphashKeyCache::default(),
 	};
 	let solver_box = Box::new(solver);
 	let solver_ref = Box::leak(solver_box);
@@ -68,51 +80,80 @@ pub unsafe extern "C" fn fill_default_params(params: *mut SolverParams) {
 	(*params).edge_bits = 31;
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn plugin_build_info(info: *mut PluginBuildInfo) {
+	(*info).set_git_commit(built_info::GIT_VERSION.unwrap_or("unknown"));
+	(*info).set_build_date(built_info::BUILT_TIME_UTC);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn enumerate_devices(list: *mut DeviceList) {
+	// same platform numbering as the platform param, and the same
+	// platform and device lookup as create_solver_ctx
+	for &(platform_id, vendor) in [(1, "AMD"), (2, "NVIDIA")].iter() {
+		let platform = match trimmer::find_platform(Some(vendor)) {
+			Some(p) => p,
+			None => continue,
+		};
+		let devices = match trimmer::gpu_devices(&platform) {
+			Ok(d) => d,
+			Err(_) => continue,
+		};
+		for (i, device) in devices.iter().enumerate() {
+			let name = device.name().unwrap_or_default();
+			(*list).add_device(platform_id, i as u32, &name);
+		}
+	}
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn run_solver(
 	ctx: *mut SolverCtx,
 	header_ptr: *const c_uchar,
 	header_length: u32,
 	nonce: u64,
-	_range: u32,
+	range: u32,
 	solutions: *mut SolverSolutions,
 	stats: *mut SolverStats,
 ) -> u32 {
 	let start = SystemTime::now();
 	let solver_ptr = mem::transmute::<*mut SolverCtx, *mut Solver>(ctx);
-	let solver = &*solver_ptr;
+	let solver = &mut *solver_ptr;
 	let mut header = Vec::with_capacity(header_length as usize);
 	let r_ptr = header.as_mut_ptr();
 	ptr::copy_nonoverlapping(header_ptr, r_ptr, header_length as usize);
 	header.set_len(header_length as usize);
-	let n = nonce as u32;
-	let k = match set_header_nonce(&header, Some(n), solver.mutate_nonce) {
-		Err(_e) => {
-			return 2;
-		}
-		Ok(v) => v,
-	};
-	let res = solver.trimmer.run(&k).unwrap();
+	// the nonce is only stepped over the range when written to the header
+	let range = if solver.mutate_nonce { range.max(1) } else { 1 };
+	let mut i = 0;
+	let mut edges_remaining = 0;
+	for n in 0..range {
+		let nonce = (nonce as u32).wrapping_add(n);
+		let k = solver.keys.keys(&header, nonce, solver.mutate_nonce);
+		let res = solver.trimmer.run(&k).unwrap();
+		// second word of the trimmer result holds the edge count
+		edges_remaining = res.get(1).cloned().unwrap_or(0) as u64;
 
-	let sols = Graph::search(&res).unwrap();
+		let sols = Graph::search(&res).unwrap();
+		for sol in sols.into_iter().take(MAX_SOLS - i) {
+			(*solutions).sols[i].nonce = nonce as u64;
+			(*solutions).sols[i]
+				.proof
+				.copy_from_slice(&sol.nonces[..sol.nonces.len()]);
+			i += 1;
+		}
+	}
 	let end = SystemTime::now();
 	let elapsed = end.duration_since(start).unwrap();
-	let mut i = 0;
-	(*solutions).edge_bits = 31;
-	(*solutions).num_sols = sols.len() as u32;
-	for sol in sols {
-		(*solutions).sols[i].nonce = nonce;
-		(*solutions).sols[i]
-			.proof
-			.copy_from_slice(&sol.nonces[..sol.nonces.len()]);
-		i += 1;
-	}
-	(*stats).edge_bits = 31;
+	(*solutions).edge_bits = solver.trimmer.edge_bits as u32;
+	(*solutions).num_sols = i as u32;
+	(*stats).edge_bits = solver.trimmer.edge_bits as u32;
 	(*stats).device_id = solver.trimmer.device_id as u32;
-	let name_bytes = solver.trimmer.device_name.as_bytes();
-	let n = std::cmp::min((*stats).device_name.len(), name_bytes.len());
-	(*stats).device_name[..n].copy_from_slice(&solver.trimmer.device_name.as_bytes()[..n]);
-	(*stats).last_solution_time = duration_to_u64(elapsed);
+	(*stats).set_device_name(&solver.trimmer.device_name);
+	// time per graph, rather than per call
+	(*stats).last_solution_time = duration_to_u64(elapsed / range);
+	(*stats).edges_remaining = edges_remaining;
+	(*stats).siphash_key_cache_hits = solver.keys.hits;
 	(*stats).last_start_time =
 		duration_to_u64(start.duration_since(SystemTime::UNIX_EPOCH).unwrap());
 	(*stats).last_end_time = duration_to_u64(end.duration_since(SystemTime::UNIX_EPOCH).unwrap());
@@ -0,0 +1,41 @@
+extern crate ocl_cuckatoo;
+
+use ocl_cuckatoo::{Graph, Trimmer};
+use std::env;
+use std::time::SystemTime;
+
+fn main() -> Result<(), String> {
+	let trimmer = Trimmer::build(None, None, 31).expect("can't build trimmer");
+	let k = [
+		0xf4956dc403730b01,
+		0xe6d45de39c2a5a3e,
+		0xcbf626a8afee35f6,
+		0x4307b94b1a0c9980,
+	];
+
+	let mut start = SystemTime::now();
+	let res = trimmer.run(&k).unwrap();
+	let elapsed = SystemTime::now().duration_since(start).unwrap();
+	println!("Time: {:?}", elapsed);
+
+	start = SystemTime::now();
+	let sols = Graph::search(&res).unwrap();
+	let elapsed = SystemTime::now().duration_since(start).unwrap();
+	println!("Finder: {:?}", elapsed);
+	for sol in sols {
+		println!("Solution: {:?}", sol.nonces);
+	}
+
+	// optional micro-benchmark of the per-run overhead, with the buffers
+	// already allocated
+	let runs: u32 = env::args().nth(1).and_then(|a| a.parse().ok()).unwrap_or(0);
+	if runs > 0 {
+		start = SystemTime::now();
+		for _ in 0..runs {
+			trimmer.run(&k).unwrap();
+		}
+		let elapsed = SystemTime::now().duration_since(start).unwrap();
+		println!("Average over {} runs: {:?}", runs, elapsed / runs);
+	}
+	Ok(())
+}
@@ -1,5 +1,5 @@
 use ocl;
-use ocl::{Buffer, Context, Device, Kernel, Platform, Program, Queue, SpatialDims};
+use ocl::{Buffer, Context, Device, DeviceType, Kernel, Platform, Program, Queue, SpatialDims};
 
 const RES_BUFFER_SIZE: usize = 4_000_000;
 const LOCAL_WORK_SIZE: usize = 256;
@@ -12,7 +12,7 @@ enum Mode {
 }
 
 pub struct Trimmer {
-	edge_bits: u8,
+	pub edge_bits: u8,
 	q: Queue,
 	program: Program,
 	edges: Buffer<u32>,
@@ -140,6 +140,8 @@ impl Trimmer {
 		}
 		self.q.finish()?;
 		let ret = self.res_buf.clone();
+		// the edge bitmap holds the trimming state, so it has to be reset
+		// for the next run
 		self.edges.cmd().fill(0xFFFFFFFF, None).enq()?;
 		self.result.cmd().fill(0, None).enq()?;
 		self.q.finish()?;
@@ -147,7 +149,7 @@ impl Trimmer {
 	}
 }
 
-fn find_platform(selector: Option<&str>) -> Option<Platform> {
+pub(crate) fn find_platform(selector: Option<&str>) -> Option<Platform> {
 	match selector {
 		None => Some(Platform::default()),
 		Some(sel) => Platform::list().into_iter().find(|p| {
@@ -160,11 +162,17 @@ fn find_platform(selector: Option<&str>) -> Option<Platform> {
 	}
 }
 
+/// GPU devices of the platform, in the order the device param indexes them
+pub(crate) fn gpu_devices(platform: &Platform) -> ocl::Result<Vec<Device>> {
+	Device::list(platform, Some(DeviceType::GPU))
+}
+
 fn find_device(platform: &Platform, selector: Option<usize>) -> ocl::Result<Device> {
-	match selector {
-		None => Device::first(platform),
-		Some(index) => Device::by_idx_wrap(platform, index),
+	let devices = gpu_devices(platform)?;
+	if devices.is_empty() {
+		return Err("No GPU devices found".into());
 	}
+	Ok(devices[selector.unwrap_or(0) % devices.len()])
 }
 
 const SRC: &str = r#"
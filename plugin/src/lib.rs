@@ -29,19 +29,23 @@ extern crate libc;
 extern crate serde_json;
 
 use libc::*;
-use std::ffi::CString;
 use std::ptr::NonNull;
-use std::{cmp, fmt, marker};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{cmp, fmt, marker, mem};
 
 use blake2::blake2b::Blake2b;
-use byteorder::{BigEndian, ByteOrder};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
 
 /// Size of proof
 pub const PROOFSIZE: usize = 42;
-/// Maximin length of plugin name w
+/// Size of the name buffers shared with plugins, including the trailing null
 pub const MAX_NAME_LEN: usize = 256;
 /// Maximum number of solutions
 pub const MAX_SOLS: usize = 4;
+/// Maximum number of devices a plugin can list
+pub const MAX_DEVICES: usize = 32;
+/// Size of the nonce the host appends to the pre-nonce part of the header
+pub const HEADER_NONCE_SIZE: usize = 8;
 
 // Type definitions corresponding to each function that the plugin/solver implements
 /// Create solver function
@@ -62,6 +66,12 @@ pub type CuckooRunSolver = unsafe extern "C" fn(
 pub type CuckooStopSolver = unsafe extern "C" fn(*mut SolverCtx);
 /// Fill default params of solver
 pub type CuckooFillDefaultParams = unsafe extern "C" fn(*mut SolverParams);
+/// Fill in the plugin's build info
+pub type CuckooPluginBuildInfo = unsafe extern "C" fn(*mut PluginBuildInfo);
+/// List the devices the plugin can mine on
+pub type CuckooEnumerateDevices = unsafe extern "C" fn(*mut DeviceList);
+/// Read the sensors of a solver's device
+pub type CuckooGetDeviceInfo = unsafe extern "C" fn(*mut SolverCtx, *mut DeviceSensors);
 
 /// A solver context, opaque reference to C++ type underneath
 #[derive(Copy, Clone, Debug)]
@@ -71,7 +81,7 @@ pub struct SolverCtxWrapper(pub NonNull<SolverCtx>);
 unsafe impl marker::Send for SolverCtxWrapper {}
 
 /// Common parameters for a solver
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[repr(C)]
 pub struct SolverParams {
 	/// threads
@@ -83,7 +93,10 @@ pub struct SolverParams {
 	/// allrounds
 	pub allrounds: bool,
 	/// whether to apply the nonce to the header, or leave as is,
-	/// letting caller mutate nonce
+	/// letting caller mutate nonce. When set, the nonce passed to
+	/// run_solver is written little endian over the last 4 bytes of the
+	/// header, and incremented over the range. When not, the header already
+	/// holds the nonce passed and is hashed as given
 	pub mutate_nonce: bool,
 	/// reduce cpuload
 	pub cpuload: bool,
@@ -144,6 +157,14 @@ impl Default for SolverParams {
 	}
 }
 
+/// Current time in nanoseconds since the epoch, as solvers report times
+pub fn now_nanos() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64)
+		.unwrap_or(0)
+}
+
 /// Common stats collected by solvers
 #[derive(Clone)]
 #[repr(C)]
@@ -168,6 +189,23 @@ pub struct SolverStats {
 	pub last_end_time: u64,
 	/// last solution elapsed time
 	pub last_solution_time: u64,
+	/// edges left in the graph after trimming on the last run, for solvers
+	/// that report it (0 otherwise)
+	pub edges_remaining: u64,
+	/// runs that reused the previous run's hash of the pre-nonce part of
+	/// the header to derive siphash keys, for solvers that cache it (0
+	/// otherwise)
+	pub siphash_key_cache_hits: u64,
+	/// device temperature in degrees Celsius, for plugins reporting it
+	/// (0 otherwise)
+	pub temperature_c: f32,
+	/// device power draw in watts, for plugins reporting it (0 otherwise)
+	pub power_w: f32,
+	/// device fan speed as a percentage, for plugins reporting it
+	/// (0 otherwise)
+	pub fan_percent: u32,
+	/// device memory in use in MB, for plugins reporting it (0 otherwise)
+	pub memory_used_mb: u64,
 }
 
 impl Default for SolverStats {
@@ -183,48 +221,241 @@ impl Default for SolverStats {
 			last_start_time: 0,
 			last_end_time: 0,
 			last_solution_time: 0,
+			edges_remaining: 0,
+			siphash_key_cache_hits: 0,
+			temperature_c: 0.0,
+			power_w: 0.0,
+			fan_percent: 0,
+			memory_used_mb: 0,
 		}
 	}
 }
 
-impl SolverStats {
-	fn get_name(&self, c_str: &[u8; MAX_NAME_LEN]) -> String {
-		// remove all null zeroes
-		let v = c_str.clone().to_vec();
-		let mut i = 0;
-		for j in 0..v.len() {
-			if v.get(j) == Some(&0) {
-				i = j;
-				break;
-			}
+/// Length of `bytes` without a multi-byte utf-8 char cut short at the end
+fn trim_partial_char(bytes: &[u8]) -> usize {
+	// look back for the lead byte of the last char
+	for back in 1..cmp::min(4, bytes.len()) + 1 {
+		let b = bytes[bytes.len() - back];
+		if b & 0xc0 == 0x80 {
+			continue;
 		}
-		let v = v.split_at(i).0;
-		match CString::new(v) {
-			Ok(s) => s.to_str().unwrap().to_owned(),
-			Err(_) => String::from("Unknown Device Name"),
+		let char_len = match b {
+			b if b >= 0xf0 => 4,
+			b if b >= 0xe0 => 3,
+			b if b >= 0xc0 => 2,
+			_ => 1,
+		};
+		if char_len > back {
+			return bytes.len() - back;
 		}
+		break;
 	}
+	bytes.len()
+}
+
+fn get_name(c_str: &[u8; MAX_NAME_LEN]) -> String {
+	// trim at the first null, and don't trust drivers to hand back valid utf-8.
+	// a name filling the whole buffer may have been cut mid-char
+	let len = c_str.iter().position(|c| *c == 0).unwrap_or(c_str.len());
+	let len = trim_partial_char(&c_str[..len]);
+	String::from_utf8_lossy(&c_str[..len]).into_owned()
+}
+
+fn set_name(c_str: &mut [u8; MAX_NAME_LEN], name: &str) {
+	// leave room for the null, and only cut on a char boundary
+	let mut len = cmp::min(name.len(), MAX_NAME_LEN - 1);
+	while !name.is_char_boundary(len) {
+		len -= 1;
+	}
+	*c_str = [0; MAX_NAME_LEN];
+	c_str[..len].copy_from_slice(&name.as_bytes()[..len]);
+}
+
+impl SolverStats {
 	/// return device name as rust string
 	pub fn get_device_name(&self) -> String {
-		self.get_name(&self.device_name)
+		get_name(&self.device_name)
 	}
 	/// return plugin name as rust string
 	pub fn get_plugin_name(&self) -> String {
-		self.get_name(&self.plugin_name)
+		get_name(&self.plugin_name)
 	}
 	/// return plugin name as rust string
 	pub fn get_error_reason(&self) -> String {
-		self.get_name(&self.error_reason)
+		get_name(&self.error_reason)
+	}
+	/// graphs per second from the last completed graph, or None if the
+	/// device hasn't completed one yet
+	pub fn graphs_per_second(&self) -> Option<f64> {
+		if self.last_solution_time == 0 {
+			return None;
+		}
+		let gps = 1_000_000_000.0 / self.last_solution_time as f64;
+		if gps.is_finite() {
+			Some(gps)
+		} else {
+			None
+		}
 	}
-	/// set plugin name
+	/// set plugin name, truncated to fit
 	pub fn set_plugin_name(&mut self, name: &str) {
-		let c_vec = CString::new(name).unwrap().into_bytes();
-		for (i, _) in c_vec.iter().enumerate() {
-			self.plugin_name[i] = c_vec[i];
+		set_name(&mut self.plugin_name, name);
+	}
+	/// set device name, truncated to fit
+	pub fn set_device_name(&mut self, name: &str) {
+		set_name(&mut self.device_name, name);
+	}
+	/// take the device's sensor readings
+	pub fn set_sensors(&mut self, sensors: &DeviceSensors) {
+		self.temperature_c = sensors.temperature_c;
+		self.power_w = sensors.power_w;
+		self.fan_percent = sensors.fan_percent;
+		self.memory_used_mb = sensors.memory_used_mb;
+	}
+	/// set error reason, truncated to fit
+	pub fn set_error_reason(&mut self, reason: &str) {
+		set_name(&mut self.error_reason, reason);
+	}
+}
+
+/// Where a plugin was built from, filled in by plugins exporting
+/// `plugin_build_info`
+#[repr(C)]
+pub struct PluginBuildInfo {
+	/// git commit the plugin was built from
+	pub git_commit: [c_uchar; MAX_NAME_LEN],
+	/// when the plugin was built
+	pub build_date: [c_uchar; MAX_NAME_LEN],
+}
+
+impl Default for PluginBuildInfo {
+	fn default() -> PluginBuildInfo {
+		PluginBuildInfo {
+			git_commit: [0; MAX_NAME_LEN],
+			build_date: [0; MAX_NAME_LEN],
+		}
+	}
+}
+
+impl PluginBuildInfo {
+	/// return git commit as rust string, "unknown" if not filled in
+	pub fn get_git_commit(&self) -> String {
+		PluginBuildInfo::or_unknown(get_name(&self.git_commit))
+	}
+	/// return build date as rust string, "unknown" if not filled in
+	pub fn get_build_date(&self) -> String {
+		PluginBuildInfo::or_unknown(get_name(&self.build_date))
+	}
+	/// set git commit, truncated to fit
+	pub fn set_git_commit(&mut self, commit: &str) {
+		set_name(&mut self.git_commit, commit);
+	}
+	/// set build date, truncated to fit
+	pub fn set_build_date(&mut self, date: &str) {
+		set_name(&mut self.build_date, date);
+	}
+	fn or_unknown(s: String) -> String {
+		if s.is_empty() {
+			String::from("unknown")
+		} else {
+			s
+		}
+	}
+}
+
+/// A device a plugin can mine on, as selected by the platform and device
+/// solver params
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct DeviceInfo {
+	/// platform param selecting the device's platform
+	pub platform: u32,
+	/// device param selecting the device on its platform
+	pub device: u32,
+	/// device name
+	pub name: [c_uchar; MAX_NAME_LEN],
+}
+
+impl Default for DeviceInfo {
+	fn default() -> DeviceInfo {
+		DeviceInfo {
+			platform: 0,
+			device: 0,
+			name: [0; MAX_NAME_LEN],
 		}
 	}
 }
 
+impl DeviceInfo {
+	/// return device name as rust string
+	pub fn get_name(&self) -> String {
+		get_name(&self.name)
+	}
+}
+
+/// Sensor readings of a solver's device, filled in by plugins exporting
+/// `get_device_info`. Readings a plugin can't take are left at 0
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct DeviceSensors {
+	/// temperature in degrees Celsius
+	pub temperature_c: f32,
+	/// power draw in watts
+	pub power_w: f32,
+	/// fan speed as a percentage
+	pub fan_percent: u32,
+	/// memory in use in MB
+	pub memory_used_mb: u64,
+}
+
+/// Devices listed by a plugin's `enumerate_devices`
+#[repr(C)]
+pub struct DeviceList {
+	/// number of devices listed
+	pub num_devices: u32,
+	/// devices
+	pub devices: [DeviceInfo; MAX_DEVICES],
+}
+
+impl Default for DeviceList {
+	fn default() -> DeviceList {
+		DeviceList {
+			num_devices: 0,
+			devices: [DeviceInfo::default(); MAX_DEVICES],
+		}
+	}
+}
+
+impl DeviceList {
+	/// Add a device, ignored if the list is already full
+	pub fn add_device(&mut self, platform: u32, device: u32, name: &str) {
+		let i = self.num_devices as usize;
+		if i >= MAX_DEVICES {
+			return;
+		}
+		self.devices[i].platform = platform;
+		self.devices[i].device = device;
+		set_name(&mut self.devices[i].name, name);
+		self.num_devices += 1;
+	}
+
+	/// The devices listed
+	pub fn devices(&self) -> &[DeviceInfo] {
+		&self.devices[..cmp::min(self.num_devices as usize, MAX_DEVICES)]
+	}
+}
+
+impl fmt::Display for PluginBuildInfo {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"git {}, built {}",
+			self.get_git_commit(),
+			self.get_build_date()
+		)
+	}
+}
+
 /// A single solution
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -327,3 +558,157 @@ impl Default for SolverSolutions {
 		}
 	}
 }
+
+/// Derives the siphash keys of headers, keeping the blake2b state over the
+/// pre-nonce part of the last header so runs on the same job only hash
+/// the nonce
+#[derive(Default)]
+pub struct SiphashKeyCache {
+	pre_nonce: Vec<u8>,
+	state: Option<Blake2b>,
+	/// number of derivations that reused the pre-nonce state
+	pub hits: u64,
+}
+
+impl SiphashKeyCache {
+	/// Siphash keys of the header, with `nonce` written little endian over
+	/// its last 4 bytes first if `mutate_nonce`
+	pub fn keys(&mut self, header: &[u8], nonce: u32, mutate_nonce: bool) -> [u64; 4] {
+		let (pre, post) = header.split_at(header.len().saturating_sub(HEADER_NONCE_SIZE));
+		let mut blake2b = match self.state {
+			Some(ref s) if self.pre_nonce[..] == pre[..] => {
+				self.hits += 1;
+				s.clone()
+			}
+			_ => {
+				let mut s = Blake2b::new(32);
+				s.update(pre);
+				self.pre_nonce = pre.to_vec();
+				self.state = Some(s.clone());
+				s
+			}
+		};
+		if mutate_nonce {
+			let mut bytes = [0; 4];
+			LittleEndian::write_u32(&mut bytes, nonce);
+			blake2b.update(&post[..post.len().saturating_sub(4)]);
+			blake2b.update(&bytes);
+		} else {
+			blake2b.update(post);
+		}
+		let h = blake2b.finalize();
+		let hb = h.as_bytes();
+		[
+			LittleEndian::read_u64(&hb[0..8]),
+			LittleEndian::read_u64(&hb[8..16]),
+			LittleEndian::read_u64(&hb[16..24]),
+			LittleEndian::read_u64(&hb[24..32]),
+		]
+	}
+}
+
+// Plugins are built against these layouts, make sure they don't drift.
+// SolverStats field offsets are checked in the tests below
+const _SOLVER_PARAMS_SIZE: [(); 64] = [(); mem::size_of::<SolverParams>()];
+const _SOLUTION_SIZE: [(); 8 * (PROOFSIZE + 2)] = [(); mem::size_of::<Solution>()];
+const _SOLVER_SOLUTIONS_SIZE: [(); 8 + MAX_SOLS * 8 * (PROOFSIZE + 2)] =
+	[(); mem::size_of::<SolverSolutions>()];
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_device_name() {
+		let mut stats = SolverStats::default();
+		assert_eq!(stats.get_device_name(), "");
+
+		stats.device_name[..6].copy_from_slice(b"GTX\xff\xfe1");
+		assert_eq!(stats.get_device_name(), "GTX\u{fffd}\u{fffd}1");
+
+		stats.device_name = [b'a'; MAX_NAME_LEN];
+		assert_eq!(stats.get_device_name().len(), MAX_NAME_LEN);
+
+		// unterminated buffer cut in the middle of a char
+		stats.device_name = [b'a'; MAX_NAME_LEN];
+		stats.device_name[MAX_NAME_LEN - 3..MAX_NAME_LEN - 1].copy_from_slice("é".as_bytes());
+		stats.device_name[MAX_NAME_LEN - 1] = "€".as_bytes()[0];
+		let name = stats.get_device_name();
+		assert!(name.ends_with('é'));
+		assert_eq!(name.len(), MAX_NAME_LEN - 1);
+	}
+
+	#[test]
+	fn test_long_device_name() {
+		let mut stats = SolverStats::default();
+		stats.set_device_name(&"x".repeat(300));
+		assert_eq!(stats.get_device_name(), "x".repeat(MAX_NAME_LEN - 1));
+		assert_eq!(stats.device_name[MAX_NAME_LEN - 1], 0);
+
+		// 2-byte chars never get split
+		stats.set_device_name(&"é".repeat(300));
+		let name = stats.get_device_name();
+		assert_eq!(name, "é".repeat((MAX_NAME_LEN - 1) / 2));
+
+		// shorter names clear what was there before
+		stats.set_device_name("GTX 1080");
+		assert_eq!(stats.get_device_name(), "GTX 1080");
+	}
+
+	#[test]
+	fn test_build_info() {
+		let mut info = PluginBuildInfo::default();
+		assert_eq!(info.to_string(), "git unknown, built unknown");
+		info.set_git_commit("1a17cc6");
+		assert_eq!(info.get_git_commit(), "1a17cc6");
+		assert_eq!(info.get_build_date(), "unknown");
+	}
+
+	#[test]
+	fn test_siphash_key_cache() {
+		let header: Vec<u8> = (0..40).collect();
+		let mut cache = SiphashKeyCache::default();
+		for &mutate_nonce in [false, true].iter() {
+			let mut hashed = header.clone();
+			if mutate_nonce {
+				hashed[36..].copy_from_slice(&[7, 0, 0, 0]);
+			}
+			let h = blake2::blake2b::blake2b(32, &[], &hashed);
+			let expected = [
+				LittleEndian::read_u64(&h.as_bytes()[0..8]),
+				LittleEndian::read_u64(&h.as_bytes()[8..16]),
+				LittleEndian::read_u64(&h.as_bytes()[16..24]),
+				LittleEndian::read_u64(&h.as_bytes()[24..32]),
+			];
+			assert_eq!(cache.keys(&header, 7, mutate_nonce), expected);
+		}
+		assert_eq!(cache.hits, 1);
+
+		// a new pre-nonce part is hashed in full
+		let mut other = header.clone();
+		other[0] = 0xff;
+		cache.keys(&other, 7, false);
+		assert_eq!(cache.hits, 1);
+		assert_eq!(cache.pre_nonce[..], other[..32]);
+	}
+
+	fn offset<T, F>(base: &T, field: &F) -> usize {
+		field as *const F as usize - base as *const T as usize
+	}
+
+	#[test]
+	fn test_plugin_abi() {
+		let s = SolverStats::default();
+		assert_eq!(offset(&s, &s.plugin_name), 8);
+		assert_eq!(offset(&s, &s.device_name), 8 + MAX_NAME_LEN);
+		assert_eq!(offset(&s, &s.has_errored), 8 + 2 * MAX_NAME_LEN);
+		assert_eq!(offset(&s, &s.error_reason), 9 + 2 * MAX_NAME_LEN);
+		assert_eq!(offset(&s, &s.iterations), 780);
+		assert_eq!(offset(&s, &s.last_start_time), 784);
+		assert_eq!(offset(&s, &s.last_solution_time), 800);
+
+		let sols = SolverSolutions::default();
+		assert_eq!(offset(&sols, &sols.num_sols), 4);
+		assert_eq!(offset(&sols, &sols.sols), 8);
+	}
+}
@@ -16,17 +16,61 @@
 //! stratum server
 
 use bufstream::BufStream;
-use native_tls::{TlsConnector, TlsStream};
+use config::StratumProtocol;
+use cuckoo::Proof;
+use http::{self, HttpTransport};
+use native_tls::TlsStream;
+use plugin::PROOFSIZE;
 use serde_json;
+use socket2::{Domain, Protocol, Socket, Type};
 use stats;
 use std;
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
 use std::io::{self, BufRead, ErrorKind, Read, Write};
-use std::net::TcpStream;
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
 use std::sync::{mpsc, Arc, RwLock};
 use std::thread;
 use time;
 use types;
-use util::LOGGER;
+use util::{self, LogSampler, LOGGER};
+
+/// Longest line accepted from the server, anything longer is treated as a
+/// protocol error rather than buffered without bound
+const MAX_LINE_LEN: usize = 256 * 1024;
+
+/// Prefix of a stratum server address given as a unix socket path
+const UNIX_ADDR_PREFIX: &str = "unix:";
+
+/// Stream to a stratum server's unix socket
+#[cfg(unix)]
+type LocalStream = UnixStream;
+// never connected, unix sockets aren't supported
+#[cfg(not(unix))]
+type LocalStream = TcpStream;
+
+/// How often a new job template is polled for over HTTP, which has no way
+/// for the server to push jobs
+const HTTP_POLL_INTERVAL_SECS: i64 = 2;
+
+/// Delay between retries of a share that failed to send
+const SUBMIT_RETRY_DELAY_MS: u64 = 500;
+
+/// Wait before the first reconnect attempt after a failed connect, doubled
+/// on each consecutive failure
+const MIN_RETRY_SECS: i64 = 1;
+
+/// Longest wait between reconnect attempts
+const MAX_RETRY_SECS: i64 = 60;
+
+/// Consecutive failed connects before failing over to the next server
+const FAILOVER_AFTER_FAILURES: u32 = 3;
+
+/// Methods a pool may push a new share difficulty with, between jobs
+const SET_DIFFICULTY_METHODS: [&str; 2] = ["set_difficulty", "mining.set_difficulty"];
 
 #[derive(Debug)]
 pub enum Error {
@@ -35,6 +79,7 @@ pub enum Error {
 	ResponseError(String),
 	JsonError(String),
 	GeneralError(String),
+	LoginError(String),
 }
 
 impl From<serde_json::error::Error> for Error {
@@ -58,6 +103,8 @@ impl<T> From<std::sync::mpsc::SendError<T>> for Error {
 struct Stream {
 	stream: Option<BufStream<TcpStream>>,
 	tls_stream: Option<BufStream<TlsStream<TcpStream>>>,
+	unix_stream: Option<BufStream<LocalStream>>,
+	http: Option<HttpTransport>,
 }
 
 impl Stream {
@@ -65,25 +112,66 @@ impl Stream {
 		Stream {
 			stream: None,
 			tls_stream: None,
+			unix_stream: None,
+			http: None,
 		}
 	}
-	fn try_connect(&mut self, server_url: &str, tls: Option<bool>) -> Result<(), Error> {
-		match TcpStream::connect(server_url) {
+	fn try_connect_http(
+		&mut self,
+		server_url: &str,
+		tls: Option<bool>,
+		tls_ca: Option<&str>,
+	) -> Result<(), Error> {
+		let http = HttpTransport::new(server_url, tls, tls_ca).map_err(Error::ConnectionError)?;
+		http.check_connection()
+			.map_err(|e| Error::ConnectionError(format!("{}", e)))?;
+		self.http = Some(http);
+		Ok(())
+	}
+	/// Connect to a local stratum server's unix socket. There's no TLS over
+	/// a unix socket
+	#[cfg(unix)]
+	fn try_connect_unix(&mut self, path: &str) -> Result<(), Error> {
+		let conn = UnixStream::connect(path).map_err(|e| {
+			Error::ConnectionError(format!("Can't connect to unix socket {}: {}", path, e))
+		})?;
+		conn.set_nonblocking(true).map_err(|e| {
+			Error::ConnectionError(format!("Can't switch to nonblocking mode: {:?}", e))
+		})?;
+		self.unix_stream = Some(BufStream::new(conn));
+		Ok(())
+	}
+	#[cfg(not(unix))]
+	fn try_connect_unix(&mut self, path: &str) -> Result<(), Error> {
+		Err(Error::ConnectionError(format!(
+			"Can't connect to {}, unix sockets aren't supported on this platform",
+			path
+		)))
+	}
+	fn try_connect(
+		&mut self,
+		server_url: &str,
+		tls: Option<bool>,
+		tls_ca: Option<&str>,
+		bind_addr: Option<&str>,
+	) -> Result<(), Error> {
+		if let Some(path) = server_url.strip_prefix(UNIX_ADDR_PREFIX) {
+			if tls.unwrap_or(false) {
+				warn!(LOGGER, "TLS isn't used for unix socket stratum connections");
+			}
+			return self.try_connect_unix(path);
+		}
+		match connect_tcp(server_url, bind_addr) {
 			Ok(conn) => {
 				if tls.is_some() && tls.unwrap() {
-					let connector = TlsConnector::new().map_err(|e| {
-						Error::ConnectionError(format!("Can't create TLS connector: {:?}", e))
-					})?;
-					let url_port: Vec<&str> = server_url.split(':').collect();
-					let splitted_url: Vec<&str> = url_port[0].split('.').collect();
-					let base_host = format!(
-						"{}.{}",
-						splitted_url[splitted_url.len() - 2],
-						splitted_url[splitted_url.len() - 1]
-					);
-					let mut stream = connector.connect(&base_host, conn).map_err(|e| {
-						Error::ConnectionError(format!("Can't establish TLS connection: {:?}", e))
-					})?;
+					let connector = http::tls_connector(tls_ca).map_err(Error::ConnectionError)?;
+					let mut stream =
+						connector.connect(tls_host(server_url), conn).map_err(|e| {
+							Error::ConnectionError(format!(
+								"Can't establish TLS connection: {:?}",
+								e
+							))
+						})?;
 					stream.get_mut().set_nonblocking(true).map_err(|e| {
 						Error::ConnectionError(format!("Can't switch to nonblocking mode: {:?}", e))
 					})?;
@@ -101,17 +189,78 @@ impl Stream {
 	}
 }
 
+/// Host of a `host:port` server address, to verify the server's TLS
+/// certificate against
+fn tls_host(server_url: &str) -> &str {
+	let host = match server_url.rfind(':') {
+		Some(i) if server_url[i + 1..].chars().all(|c| c.is_ascii_digit()) => &server_url[..i],
+		_ => server_url,
+	};
+	host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// Connect to the server, from the given local address if any. The local
+/// address can be given with or without a port
+fn connect_tcp(server_url: &str, bind_addr: Option<&str>) -> io::Result<TcpStream> {
+	let bind_addr = match bind_addr {
+		Some(a) => a,
+		None => return TcpStream::connect(server_url),
+	};
+	let local = match bind_addr.parse::<SocketAddr>() {
+		Ok(a) => a,
+		Err(_) => {
+			let ip = bind_addr.parse::<IpAddr>().map_err(|e| {
+				io::Error::new(
+					ErrorKind::InvalidInput,
+					format!("Invalid bind address {}: {}", bind_addr, e),
+				)
+			})?;
+			SocketAddr::new(ip, 0)
+		}
+	};
+	let mut last_err = io::Error::new(
+		ErrorKind::NotFound,
+		format!("No {} address found for {}", local.ip(), server_url),
+	);
+	// only addresses of the same family as the local one can be reached
+	for addr in server_url.to_socket_addrs()? {
+		if addr.is_ipv4() != local.is_ipv4() {
+			continue;
+		}
+		let domain = if addr.is_ipv4() {
+			Domain::ipv4()
+		} else {
+			Domain::ipv6()
+		};
+		let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+		socket.bind(&local.into())?;
+		match socket.connect(&addr.into()) {
+			Ok(()) => return Ok(socket.into_tcp_stream()),
+			Err(e) => last_err = e,
+		}
+	}
+	Err(last_err)
+}
+
 impl Write for Stream {
 	fn write(&mut self, b: &[u8]) -> Result<usize, std::io::Error> {
-		if self.tls_stream.is_some() {
+		if self.http.is_some() {
+			self.http.as_mut().unwrap().write(b)
+		} else if self.tls_stream.is_some() {
 			self.tls_stream.as_mut().unwrap().write(b)
+		} else if let Some(s) = self.unix_stream.as_mut() {
+			s.write(b)
 		} else {
 			self.stream.as_mut().unwrap().write(b)
 		}
 	}
 	fn flush(&mut self) -> Result<(), std::io::Error> {
-		if self.tls_stream.is_some() {
+		if self.http.is_some() {
+			self.http.as_mut().unwrap().flush()
+		} else if self.tls_stream.is_some() {
 			self.tls_stream.as_mut().unwrap().flush()
+		} else if let Some(s) = self.unix_stream.as_mut() {
+			s.flush()
 		} else {
 			self.stream.as_mut().unwrap().flush()
 		}
@@ -119,8 +268,12 @@ impl Write for Stream {
 }
 impl Read for Stream {
 	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-		if self.tls_stream.is_some() {
+		if self.http.is_some() {
+			self.http.as_mut().unwrap().read(buf)
+		} else if self.tls_stream.is_some() {
 			self.tls_stream.as_mut().unwrap().read(buf)
+		} else if let Some(s) = self.unix_stream.as_mut() {
+			s.read(buf)
 		} else {
 			self.stream.as_mut().unwrap().read(buf)
 		}
@@ -129,49 +282,143 @@ impl Read for Stream {
 
 impl BufRead for Stream {
 	fn fill_buf(&mut self) -> io::Result<&[u8]> {
-		if self.tls_stream.is_some() {
+		if self.http.is_some() {
+			self.http.as_mut().unwrap().fill_buf()
+		} else if self.tls_stream.is_some() {
 			self.tls_stream.as_mut().unwrap().fill_buf()
+		} else if let Some(s) = self.unix_stream.as_mut() {
+			s.fill_buf()
 		} else {
 			self.stream.as_mut().unwrap().fill_buf()
 		}
 	}
 	fn consume(&mut self, amt: usize) {
-		if self.tls_stream.is_some() {
+		if self.http.is_some() {
+			self.http.as_mut().unwrap().consume(amt)
+		} else if self.tls_stream.is_some() {
 			self.tls_stream.as_mut().unwrap().consume(amt)
+		} else if let Some(s) = self.unix_stream.as_mut() {
+			s.consume(amt)
 		} else {
 			self.stream.as_mut().unwrap().consume(amt)
 		}
 	}
 	fn read_until(&mut self, byte: u8, buf: &mut Vec<u8>) -> io::Result<usize> {
-		if self.tls_stream.is_some() {
+		if self.http.is_some() {
+			self.http.as_mut().unwrap().read_until(byte, buf)
+		} else if self.tls_stream.is_some() {
 			self.tls_stream.as_mut().unwrap().read_until(byte, buf)
+		} else if let Some(s) = self.unix_stream.as_mut() {
+			s.read_until(byte, buf)
 		} else {
 			self.stream.as_mut().unwrap().read_until(byte, buf)
 		}
 	}
 	fn read_line(&mut self, string: &mut String) -> io::Result<usize> {
-		if self.tls_stream.is_some() {
+		if self.http.is_some() {
+			self.http.as_mut().unwrap().read_line(string)
+		} else if self.tls_stream.is_some() {
 			self.tls_stream.as_mut().unwrap().read_line(string)
+		} else if let Some(s) = self.unix_stream.as_mut() {
+			s.read_line(string)
 		} else {
 			self.stream.as_mut().unwrap().read_line(string)
 		}
 	}
 }
 
+/// Connect to a stratum server over the given protocol
+fn connect(
+	protocol: StratumProtocol,
+	server_url: &str,
+	tls_enabled: Option<bool>,
+	tls_ca: Option<&str>,
+	bind_addr: Option<&str>,
+) -> Result<Stream, Error> {
+	let mut stream = Stream::new();
+	match protocol {
+		StratumProtocol::Tcp => stream.try_connect(server_url, tls_enabled, tls_ca, bind_addr)?,
+		StratumProtocol::Http => stream.try_connect_http(server_url, tls_enabled, tls_ca)?,
+	}
+	Ok(stream)
+}
+
 pub struct Controller {
 	_id: u32,
+	// the server currently used, from server_urls
 	server_url: String,
+	// primary server first, then the failover servers in order
+	server_urls: Vec<String>,
+	server_index: usize,
+	// connects failed in a row to the current server
+	connect_failures: u32,
 	server_login: Option<String>,
 	server_password: Option<String>,
 	server_tls_enabled: Option<bool>,
+	// CA certificate to trust for the server's TLS certificate
+	server_tls_ca: Option<String>,
+	server_compact_pow: bool,
+	protocol: StratumProtocol,
+	suggested_difficulty: Option<u64>,
+	logout_on_shutdown: bool,
+	// times a share that failed to send is retried before the connection
+	// is given up on
+	submit_retries: u32,
+	// methods sent on connect, before asking for a job
+	handshake: Vec<String>,
+	// local address to connect from
+	bind_addr: Option<String>,
+	// seconds between keepalive requests, 0 for none
+	keepalive_secs: i64,
+	// seconds to wait after the next failed connect
+	retry_backoff: i64,
+	// whether to go back to a higher priority server while failed over,
+	// checking them this often
+	failback: bool,
+	failback_probe_secs: i64,
+	// check of the higher priority servers in progress
+	failback_probe: Option<mpsc::Receiver<Option<(usize, Stream)>>>,
+	// connection to a higher priority server found reachable again, kept
+	// until the shares pending on the current server are answered
+	failback_stream: Option<(usize, Stream)>,
+	login_error: Option<String>,
+	// login sent, but not acknowledged yet
+	awaiting_login: bool,
+	// shares left pending by a dropped connection, to be resubmitted once
+	// the server has sent a job after the login
+	resubmit_on_job: bool,
 	stream: Option<Stream>,
+	// partial line read from the server so far
+	line_buf: Vec<u8>,
 	rx: mpsc::Receiver<types::ClientMessage>,
 	pub tx: mpsc::Sender<types::ClientMessage>,
 	miner_tx: mpsc::Sender<types::MinerMessage>,
 	last_request_id: u32,
+	current_height: u64,
+	current_job_id: Option<u64>,
+	// job id and pre-pow of the last job received
+	last_job_pre_pow: (u64, String),
+	// file to append found blocks to
+	blocks_found_file: Option<String>,
+	pending_shares: HashMap<String, PendingShare>,
+	log_sampler: LogSampler,
 	stats: Arc<RwLock<stats::Stats>>,
 }
 
+/// A submitted share that hasn't been acknowledged by the server yet
+#[derive(Clone, Debug)]
+struct PendingShare {
+	height: u64,
+	job_id: u64,
+	edge_bits: u32,
+	nonce: u64,
+	pow: Vec<u64>,
+	// solver that found the share
+	device: usize,
+	// computed difficulty of the solution
+	difficulty: u64,
+}
+
 fn invalid_error_response() -> types::RpcError {
 	types::RpcError {
 		code: 0,
@@ -179,73 +426,392 @@ fn invalid_error_response() -> types::RpcError {
 	}
 }
 
+/// Make sure a response has exactly one of a result or an error, so a
+/// malformed one is never taken for a success. An error wins over a
+/// result sent alongside it
+fn sanitize_response(mut res: types::RpcResponse) -> types::RpcResponse {
+	match (res.result.is_some(), res.error.is_some()) {
+		(true, true) => {
+			warn!(
+				LOGGER,
+				"Response {} to {} has both a result and an error, treating it as an error",
+				res.id,
+				res.method
+			);
+			res.result = None;
+		}
+		(false, false) => {
+			error!(
+				LOGGER,
+				"Protocol error: response {} to {} has neither a result nor an error",
+				res.id,
+				res.method
+			);
+			res.error = Some(invalid_error_response());
+		}
+		_ => {}
+	}
+	res
+}
+
+fn log_new_job(job: &types::JobTemplate) {
+	let cleanjob = match job.cleanjob {
+		Some(c) => c.to_string(),
+		None => "n/a".to_string(),
+	};
+	let xn = if job.xn.is_some() {
+		"present"
+	} else {
+		"absent"
+	};
+	info!(
+		LOGGER,
+		"NewJob - height: {}, job_id: {}, difficulty: {}, cleanjob: {}, xn: {}",
+		job.height,
+		job.job_id,
+		job.difficulty,
+		cleanjob,
+		xn
+	);
+}
+
 impl Controller {
+	/// Controller for the given stratum servers, the primary server first
+	/// followed by any to fail over to
 	pub fn new(
-		server_url: &str,
+		server_urls: Vec<String>,
 		server_login: Option<String>,
 		server_password: Option<String>,
 		server_tls_enabled: Option<bool>,
+		server_compact_pow: Option<bool>,
 		miner_tx: mpsc::Sender<types::MinerMessage>,
 		stats: Arc<RwLock<stats::Stats>>,
 	) -> Result<Controller, Error> {
+		let server_url = server_urls
+			.first()
+			.cloned()
+			.ok_or_else(|| Error::ConnectionError("No stratum server address".to_string()))?;
 		let (tx, rx) = mpsc::channel::<types::ClientMessage>();
 		Ok(Controller {
 			_id: 0,
-			server_url: server_url.to_string(),
+			server_url,
+			server_urls,
+			server_index: 0,
+			connect_failures: 0,
 			server_login,
 			server_password,
 			server_tls_enabled,
+			server_tls_ca: None,
+			server_compact_pow: server_compact_pow.unwrap_or(false),
+			protocol: StratumProtocol::Tcp,
+			suggested_difficulty: None,
+			logout_on_shutdown: false,
+			submit_retries: 0,
+			handshake: vec!["login".to_string()],
+			bind_addr: None,
+			keepalive_secs: 0,
+			retry_backoff: MIN_RETRY_SECS,
+			failback: false,
+			failback_probe_secs: 0,
+			failback_probe: None,
+			failback_stream: None,
+			login_error: None,
+			awaiting_login: false,
+			resubmit_on_job: false,
 			stream: None,
+			line_buf: Vec::new(),
 			tx,
 			rx,
 			miner_tx,
 			last_request_id: 0,
+			current_height: 0,
+			current_job_id: None,
+			last_job_pre_pow: (0, String::new()),
+			blocks_found_file: None,
+			pending_shares: HashMap::new(),
+			log_sampler: LogSampler::new(),
 			stats,
 		})
 	}
 
+	/// Suggest a starting share difficulty to the pool on login
+	pub fn set_suggested_difficulty(&mut self, difficulty: Option<u64>) {
+		self.suggested_difficulty = difficulty;
+	}
+
+	/// Tell the pool we're going away on shutdown, for pools that track
+	/// worker disconnects
+	pub fn set_logout_on_shutdown(&mut self, logout: bool) {
+		self.logout_on_shutdown = logout;
+	}
+
+	/// Methods to send on connect before asking for a job, for proxies
+	/// expecting e.g. a subscribe before login. "login" sends the usual
+	/// login request, anything else is sent without params
+	pub fn set_handshake(&mut self, handshake: Vec<String>) {
+		self.handshake = handshake;
+	}
+
+	/// Retry shares that fail to send this many times before reconnecting
+	pub fn set_submit_retries(&mut self, retries: u32) {
+		self.submit_retries = retries;
+	}
+
+	/// Connect to the stratum server from this local address, rather than
+	/// leaving it to the OS
+	pub fn set_bind_addr(&mut self, addr: Option<String>) {
+		self.bind_addr = addr;
+	}
+
+	/// Trust the CA certificate in this file for the server's TLS
+	/// certificate, e.g. for a pool with a self-signed one
+	pub fn set_tls_ca(&mut self, ca_file: Option<String>) {
+		self.server_tls_ca = ca_file;
+	}
+
+	/// Send the server a keepalive request this often, so pools with a read
+	/// timeout don't drop an idle connection. 0 disables them
+	pub fn set_keepalive_secs(&mut self, secs: u64) {
+		self.keepalive_secs = secs as i64;
+	}
+
+	/// While failed over, check the higher priority servers this often and
+	/// go back to the first one that's reachable again
+	pub fn set_failback(&mut self, enabled: bool, probe_secs: u64) {
+		self.failback = enabled;
+		self.failback_probe_secs = probe_secs as i64;
+	}
+
+	/// Append a record of each block found to this file
+	pub fn set_blocks_found_file(&mut self, file: Option<String>) {
+		self.blocks_found_file = file;
+	}
+
+	/// Talk to the server over raw TCP stratum or polled HTTP/JSON-RPC
+	pub fn set_protocol(&mut self, protocol: StratumProtocol) {
+		self.protocol = protocol;
+	}
+
+	/// Seconds to wait before reconnecting after a failed connect, backing
+	/// off further with each consecutive failure. Fails over to the next
+	/// server after repeated failures
+	fn connect_failed(&mut self) -> i64 {
+		self.connect_failures += 1;
+		if self.connect_failures >= FAILOVER_AFTER_FAILURES && self.server_urls.len() > 1 {
+			let next = (self.server_index + 1) % self.server_urls.len();
+			warn!(
+				LOGGER,
+				"Can't reach stratum server {} after {} attempts, failing over to {}",
+				self.server_url,
+				self.connect_failures,
+				self.server_urls[next]
+			);
+			self.switch_server(next);
+			return self.retry_backoff;
+		}
+		let wait = self.retry_backoff;
+		self.retry_backoff = cmp::min(self.retry_backoff * 2, MAX_RETRY_SECS);
+		wait
+	}
+
+	/// Start backing off from scratch the next time a connect fails
+	fn connect_succeeded(&mut self) {
+		self.connect_failures = 0;
+		self.retry_backoff = MIN_RETRY_SECS;
+	}
+
+	/// Use the server at the given index from now on. Shares pending on the
+	/// old server are kept, the ones still current for the new server's
+	/// first job are resubmitted to it
+	fn switch_server(&mut self, index: usize) {
+		self.server_index = index;
+		self.server_url = self.server_urls[index].clone();
+		self.failback_stream = None;
+		self.connect_succeeded();
+		if let Ok(mut stats) = self.stats.write() {
+			stats.client_stats.server_url = self.server_url.clone();
+		}
+	}
+
+	fn set_connection_state(&self, state: stats::ConnectionState) {
+		let mut stats = self.stats.write().unwrap();
+		if stats.client_stats.connection_state != state {
+			debug!(LOGGER, "Connection state: {:?}", state);
+			stats.client_stats.set_connection_state(state);
+		}
+	}
+
 	pub fn try_connect(&mut self) -> Result<(), Error> {
-		self.stream = Some(Stream::new());
-		self.stream
-			.as_mut()
-			.unwrap()
-			.try_connect(&self.server_url, self.server_tls_enabled)?;
+		self.line_buf.clear();
+		self.current_job_id = None;
+		self.stream = Some(self.connect_to(&self.server_url)?);
 		Ok(())
 	}
 
+	fn connect_to(&self, server_url: &str) -> Result<Stream, Error> {
+		connect(
+			self.protocol,
+			server_url,
+			self.server_tls_enabled,
+			self.server_tls_ca.as_deref(),
+			self.bind_addr.as_deref(),
+		)
+	}
+
+	/// Check the servers above the one in use in the background, keeping a
+	/// connection to the first that's reachable
+	fn start_failback_probe(&mut self) {
+		let urls = self.server_urls[..self.server_index].to_vec();
+		let protocol = self.protocol;
+		let tls_enabled = self.server_tls_enabled;
+		let tls_ca = self.server_tls_ca.clone();
+		let bind_addr = self.bind_addr.clone();
+		let (tx, rx) = mpsc::channel();
+		let res = thread::Builder::new()
+			.name("failback_probe".to_string())
+			.spawn(move || {
+				let found = urls.iter().enumerate().find_map(|(i, url)| {
+					connect(
+						protocol,
+						url,
+						tls_enabled,
+						tls_ca.as_deref(),
+						bind_addr.as_deref(),
+					)
+					.ok()
+					.map(|s| (i, s))
+				});
+				let _ = tx.send(found);
+			});
+		match res {
+			Ok(_) => self.failback_probe = Some(rx),
+			Err(e) => error!(LOGGER, "Failed to start failback probe: {:?}", e),
+		}
+	}
+
+	/// Go back to a higher priority server found reachable by the probe,
+	/// using the probe's connection, once the current server has answered
+	/// the shares pending on it. Returns whether the server was switched
+	fn check_failback(&mut self) -> bool {
+		if let Some(res) = self.failback_probe.as_ref().map(|rx| rx.try_recv()) {
+			match res {
+				Ok(found) => {
+					self.failback_probe = None;
+					// may have failed over further since the probe started
+					self.failback_stream = found.filter(|(i, _)| *i < self.server_index);
+				}
+				Err(mpsc::TryRecvError::Disconnected) => self.failback_probe = None,
+				Err(mpsc::TryRecvError::Empty) => {}
+			}
+		}
+		if !self.pending_shares.is_empty() {
+			return false;
+		}
+		let (index, stream) = match self.failback_stream.take() {
+			Some(f) => f,
+			None => return false,
+		};
+		warn!(
+			LOGGER,
+			"Stratum server {} is reachable again, failing back from {}",
+			self.server_urls[index],
+			self.server_url
+		);
+		self.switch_server(index);
+		self.line_buf.clear();
+		self.current_job_id = None;
+		self.stream = Some(stream);
+		let status = format!(
+			"Connection Status: Connected to Grin server at {}.",
+			self.server_url
+		);
+		if let Ok(mut stats) = self.stats.write() {
+			stats.client_stats.connection_status = status;
+			stats
+				.client_stats
+				.set_connection_state(stats::ConnectionState::Connected);
+		}
+		true
+	}
+
 	fn read_message(&mut self) -> Result<Option<String>, Error> {
 		if self.stream.is_none() {
 			return Err(Error::ConnectionError("broken pipe".to_string()));
 		}
-		let mut line = String::new();
-		match self.stream.as_mut().unwrap().read_line(&mut line) {
-			Ok(_) => {
+		let stream = self.stream.as_mut().unwrap();
+		// accumulate until a full line is available, keeping partial lines
+		// across calls
+		loop {
+			let (found_newline, used) = {
+				let available = match stream.fill_buf() {
+					Ok(b) => b,
+					Err(ref e) if e.kind() == ErrorKind::BrokenPipe => {
+						return Err(Error::ConnectionError("broken pipe".to_string()));
+					}
+					Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+					Err(e) => {
+						error!(LOGGER, "Communication error with stratum server: {}", e);
+						return Err(Error::ConnectionError("broken pipe".to_string()));
+					}
+				};
 				// stream is not returning a proper error on disconnect
-				if line == "" {
+				if available.is_empty() {
 					return Err(Error::ConnectionError("broken pipe".to_string()));
 				}
-				Ok(Some(line))
-			}
-			Err(ref e) if e.kind() == ErrorKind::BrokenPipe => {
-				Err(Error::ConnectionError("broken pipe".to_string()))
+				match available.iter().position(|b| *b == b'\n') {
+					Some(i) => {
+						self.line_buf.extend_from_slice(&available[..=i]);
+						(true, i + 1)
+					}
+					None => {
+						self.line_buf.extend_from_slice(available);
+						(false, available.len())
+					}
+				}
+			};
+			stream.consume(used);
+			if self.line_buf.len() > MAX_LINE_LEN {
+				self.line_buf.clear();
+				error!(
+					LOGGER,
+					"Message from stratum server exceeds {} bytes, dropping connection",
+					MAX_LINE_LEN
+				);
+				return Err(Error::ResponseError("Message too long".to_string()));
 			}
-			Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
-			Err(e) => {
-				error!(LOGGER, "Communication error with stratum server: {}", e);
-				Err(Error::ConnectionError("broken pipe".to_string()))
+			if found_newline {
+				let line = String::from_utf8_lossy(&self.line_buf).into_owned();
+				self.line_buf.clear();
+				if let Ok(mut stats) = self.stats.write() {
+					stats.client_stats.set_raw_message_received(&line);
+				}
+				return Ok(Some(line));
 			}
 		}
 	}
 
 	fn send_message(&mut self, message: &str) -> Result<(), Error> {
+		self.send_message_as(message, message)
+	}
+
+	/// Send a message, logging and showing it as display instead, e.g. to
+	/// keep a password out of sight
+	fn send_message_as(&mut self, message: &str, display: &str) -> Result<(), Error> {
 		if self.stream.is_none() {
 			return Err(Error::ConnectionError(String::from("No server connection")));
 		}
-		debug!(LOGGER, "sending request: {}", message);
-		let _ = self.stream.as_mut().unwrap().write(message.as_bytes());
-		let _ = self.stream.as_mut().unwrap().write(b"\n");
-		let _ = self.stream.as_mut().unwrap().flush();
-		Ok(())
+		if self.log_sampler.sample() {
+			debug!(LOGGER, "sending request: {}", display);
+		}
+		if let Ok(mut stats) = self.stats.write() {
+			stats.client_stats.set_raw_message_sent(display);
+		}
+		let stream = self.stream.as_mut().unwrap();
+		let res = stream
+			.write_all(message.as_bytes())
+			.and_then(|_| stream.write_all(b"\n"));
+		let _ = stream.flush();
+		res.map_err(|e| Error::ConnectionError(format!("{}", e)))
 	}
 
 	fn send_message_get_job_template(&mut self) -> Result<(), Error> {
@@ -263,35 +829,74 @@ impl Controller {
 		self.send_message(&req_str)
 	}
 
-	fn send_login(&mut self) -> Result<(), Error> {
-		// only send the login request if a login string is configured
-		let login_str = match self.server_login.clone() {
-			None => "".to_string(),
-			Some(server_login) => server_login,
-		};
-		if login_str == "" {
-			return Ok(());
-		}
-		let password_str = match self.server_password.clone() {
-			None => "".to_string(),
-			Some(server_password) => server_password,
-		};
+	/// The login request, and the same with the password redacted for the
+	/// logs and screen
+	fn login_request(&self, login: String) -> Result<(String, String), Error> {
 		let params = types::LoginParams {
-			login: login_str,
-			pass: password_str,
+			login,
+			pass: self.server_password.clone().unwrap_or_default(),
 			agent: "grin-miner".to_string(),
+			difficulty: self.suggested_difficulty,
 		};
-		let req = types::RpcRequest {
+		let mut req = types::RpcRequest {
 			id: self.last_request_id.to_string(),
 			jsonrpc: "2.0".to_string(),
 			method: "login".to_string(),
 			params: Some(serde_json::to_value(params)?),
 		};
 		let req_str = serde_json::to_string(&req)?;
+		if let Some(ref mut params) = req.params {
+			params["pass"] = serde_json::Value::from("***");
+		}
+		Ok((req_str, serde_json::to_string(&req)?))
+	}
+
+	fn send_login(&mut self) -> Result<(), Error> {
+		// only send the login request if a login string is configured
+		let login_str = match self.server_login.clone() {
+			None => "".to_string(),
+			Some(server_login) => server_login,
+		};
+		if login_str.is_empty() {
+			return Ok(());
+		}
+		self.awaiting_login = true;
+		let (req_str, redacted) = self.login_request(login_str)?;
 		{
 			let mut stats = self.stats.write()?;
 			stats.client_stats.last_message_sent = "Last Message Sent: Login".to_string();
 		}
+		self.send_message_as(&req_str, &redacted)?;
+		self.set_connection_state(stats::ConnectionState::Authenticating);
+		Ok(())
+	}
+
+	fn send_handshake(&mut self) -> Result<(), Error> {
+		for method in self.handshake.clone() {
+			if method == "login" {
+				self.send_login()?;
+				continue;
+			}
+			let req = types::RpcRequest {
+				id: self.last_request_id.to_string(),
+				jsonrpc: "2.0".to_string(),
+				method,
+				params: None,
+			};
+			let req_str = serde_json::to_string(&req)?;
+			self.send_message(&req_str)?;
+		}
+		Ok(())
+	}
+
+	fn send_logout(&mut self) -> Result<(), Error> {
+		let req = types::RpcRequest {
+			id: self.last_request_id.to_string(),
+			jsonrpc: "2.0".to_string(),
+			method: "logout".to_string(),
+			params: None,
+		};
+		let req_str = serde_json::to_string(&req)?;
 		self.send_message(&req_str)
 	}
 
@@ -306,24 +911,54 @@ impl Controller {
 		self.send_message(&req_str)
 	}
 
-	fn send_message_submit(
-		&mut self,
-		height: u64,
-		job_id: u64,
-		edge_bits: u32,
-		nonce: u64,
-		pow: Vec<u64>,
-	) -> Result<(), Error> {
+	fn send_message_keepalive(&mut self) -> Result<(), Error> {
+		let req = types::RpcRequest {
+			id: self.last_request_id.to_string(),
+			jsonrpc: "2.0".to_string(),
+			method: "keepalive".to_string(),
+			params: None,
+		};
+		let req_str = serde_json::to_string(&req)?;
+		self.send_message(&req_str)
+	}
+
+	fn send_message_submit(&mut self, share: PendingShare) -> Result<(), Error> {
+		// the server would reject a malformed proof, don't bother sending it
+		if share.pow.len() != PROOFSIZE {
+			error!(
+				LOGGER,
+				"Not submitting solution for height: {} - nonce: {}, proof has {} nonces, expected {}",
+				share.height,
+				share.nonce,
+				share.pow.len(),
+				PROOFSIZE
+			);
+			return Ok(());
+		}
+		// keep track of the share until the server acknowledges it, so it
+		// can be resubmitted if the connection drops in the meantime
+		self.last_request_id += 1;
+		let id = self.last_request_id.to_string();
+		self.pending_shares.insert(id.clone(), share.clone());
+		let pow = if self.server_compact_pow {
+			let proof = Proof {
+				edge_bits: share.edge_bits as u8,
+				nonces: share.pow,
+			};
+			types::SubmitPow::Packed(util::to_hex(proof.pack_nonces()))
+		} else {
+			types::SubmitPow::Nonces(share.pow)
+		};
 		let params_in = types::SubmitParams {
-			height,
-			job_id,
-			edge_bits,
-			nonce,
+			height: share.height,
+			job_id: share.job_id,
+			edge_bits: share.edge_bits,
+			nonce: share.nonce,
 			pow,
 		};
 		let params = serde_json::to_string(&params_in)?;
 		let req = types::RpcRequest {
-			id: self.last_request_id.to_string(),
+			id,
 			jsonrpc: "2.0".to_string(),
 			method: "submit".to_string(),
 			params: Some(serde_json::from_str(&params)?),
@@ -336,10 +971,102 @@ impl Controller {
 				params_in.height, params_in.nonce
 			);
 		}
-		self.send_message(&req_str)
+		self.send_share(&req_str)
+	}
+
+	/// Send a share, retrying transport errors before giving up on the
+	/// connection, so a single hiccup doesn't drop it
+	fn send_share(&mut self, req_str: &str) -> Result<(), Error> {
+		let mut res = self.send_share_once(req_str);
+		let mut retries = 0;
+		while let Err(e) = res {
+			self.stats.write()?.client_stats.submit_failures += 1;
+			if retries >= self.submit_retries {
+				error!(
+					LOGGER,
+					"Failed to send share after {} retries: {:?}", retries, e
+				);
+				return Err(e);
+			}
+			retries += 1;
+			warn!(
+				LOGGER,
+				"Failed to send share, retrying ({}/{}): {:?}", retries, self.submit_retries, e
+			);
+			thread::sleep(std::time::Duration::from_millis(SUBMIT_RETRY_DELAY_MS));
+			// the whole message is sent again, not knowing how much of it
+			// made it out
+			res = self.send_share_once(req_str);
+		}
+		Ok(())
+	}
+
+	fn send_share_once(&mut self, req_str: &str) -> Result<(), Error> {
+		self.send_message(req_str).and_then(|_| self.flush_stream())
+	}
+
+	fn flush_stream(&mut self) -> Result<(), Error> {
+		match self.stream.as_mut() {
+			Some(stream) => stream
+				.flush()
+				.map_err(|e| Error::ConnectionError(format!("{}", e))),
+			None => Err(Error::ConnectionError(String::from("No server connection"))),
+		}
+	}
+
+	/// Resubmit any shares that weren't acknowledged before the connection
+	/// dropped, discarding the ones that are stale for the job at the given
+	/// height
+	fn resubmit_pending_shares(&mut self, height: u64) {
+		let (shares, stale): (Vec<PendingShare>, Vec<PendingShare>) = self
+			.pending_shares
+			.drain()
+			.map(|(_, share)| share)
+			.partition(|share| share.height == height);
+		if !stale.is_empty() {
+			debug!(
+				LOGGER,
+				"Dropping {} unacknowledged stale share(s)",
+				stale.len()
+			);
+		}
+		for share in shares {
+			info!(
+				LOGGER,
+				"Resubmitting unacknowledged share for height: {} - nonce: {}",
+				share.height,
+				share.nonce
+			);
+			// shares are buffered again before sending, so a failure here
+			// leaves them pending for the next reconnect
+			let _ = self.send_message_submit(share);
+		}
 	}
 
 	fn send_miner_job(&mut self, job: types::JobTemplate) -> Result<(), Error> {
+		// the server only accepts shares once logged in, and its first job
+		// tells which of them are still current
+		if self.resubmit_on_job && !self.awaiting_login {
+			self.resubmit_on_job = false;
+			self.resubmit_pending_shares(job.height);
+		}
+		// polled templates are mostly the job already being worked on
+		if self.protocol == StratumProtocol::Http
+			&& job.height == self.current_height
+			&& self.current_job_id == Some(job.job_id)
+		{
+			return Ok(());
+		}
+		self.current_job_id = Some(job.job_id);
+		self.last_job_pre_pow = (job.job_id, job.pre_pow.clone());
+		if job.height != self.current_height {
+			// shares for older heights can no longer be accepted
+			self.pending_shares
+				.retain(|_, share| share.height == job.height);
+			self.current_height = job.height;
+		}
+		// only a logged in (or login-free) connection gets sent jobs
+		self.set_connection_state(stats::ConnectionState::Ready);
 		let miner_message =
 			types::MinerMessage::ReceivedJob(job.height, job.job_id, job.difficulty, job.pre_pow);
 		let mut stats = self.stats.write()?;
@@ -350,6 +1077,37 @@ impl Controller {
 		self.miner_tx.send(miner_message).map_err(|e| e.into())
 	}
 
+	/// Log the proof of a found block, and append it to the blocks found
+	/// file if configured, so it can be verified against the node
+	fn record_block_found(&self, share: &PendingShare) {
+		// the header is only known if the job is still the latest
+		let pre_pow = if self.last_job_pre_pow.0 == share.job_id {
+			Some(self.last_job_pre_pow.1.clone())
+		} else {
+			None
+		};
+		let record = json!({
+			"time": time::get_time().sec,
+			"height": share.height,
+			"job_id": share.job_id,
+			"edge_bits": share.edge_bits,
+			"nonce": share.nonce,
+			"pow": share.pow,
+			"pre_pow": pre_pow,
+		});
+		info!(LOGGER, "Block found: {}", record);
+		if let Some(ref file) = self.blocks_found_file {
+			let res = OpenOptions::new()
+				.create(true)
+				.append(true)
+				.open(file)
+				.and_then(|mut f| writeln!(f, "{}", record));
+			if let Err(e) = res {
+				error!(LOGGER, "Failed to write block to {}: {}", file, e);
+			}
+		}
+	}
+
 	fn send_miner_stop(&mut self) -> Result<(), Error> {
 		let miner_message = types::MinerMessage::StopJob;
 		self.miner_tx.send(miner_message).map_err(|e| e.into())
@@ -362,16 +1120,36 @@ impl Controller {
 				None => Err(Error::RequestError("No params in job request".to_owned())),
 				Some(params) => {
 					let job = serde_json::from_value::<types::JobTemplate>(params)?;
-					info!(LOGGER, "Got a new job: {:?}", job);
+					debug!(LOGGER, "Got a new job: {:?}", job);
+					log_new_job(&job);
 					self.send_miner_job(job)
 				}
 			},
+			m if SET_DIFFICULTY_METHODS.contains(&m) => {
+				match req.params.as_ref().and_then(pushed_difficulty) {
+					None => Err(Error::RequestError(
+						"No difficulty in set_difficulty request".to_owned(),
+					)),
+					Some(diff) => {
+						info!(LOGGER, "Server set share difficulty to {}", diff);
+						{
+							let mut stats = self.stats.write()?;
+							stats.client_stats.last_message_received =
+								format!("Last Message Received: Difficulty set to {}", diff);
+						}
+						self.miner_tx
+							.send(types::MinerMessage::SetDifficulty(diff))
+							.map_err(|e| e.into())
+					}
+				}
+			}
 			_ => Err(Error::RequestError("Unknonw method".to_owned())),
 		}
 	}
 
 	pub fn handle_response(&mut self, res: types::RpcResponse) -> Result<(), Error> {
 		debug!(LOGGER, "Received response with id: {}", res.id);
+		let res = sanitize_response(res);
 		match res.method.as_str() {
 			// "status" response can be used to further populate stats object
 			"status" => {
@@ -413,10 +1191,7 @@ impl Controller {
 							job.height, job.difficulty
 						);
 					}
-					info!(
-						LOGGER,
-						"Got a job at height {} and difficulty {}", job.height, job.difficulty
-					);
+					log_new_job(&job);
 					self.send_miner_job(job)
 				} else {
 					let err = res.error.unwrap_or_else(invalid_error_response);
@@ -431,18 +1206,33 @@ impl Controller {
 			}
 			// "submit" response
 			"submit" => {
-				if let Some(result) = res.result {
+				let share = self.pending_shares.remove(&res.id);
+				if res.result.is_some() {
 					info!(LOGGER, "Share Accepted!!");
+					if let Some(ref share) = share {
+						let _ = self
+							.miner_tx
+							.send(types::MinerMessage::ShareAccepted(share.device));
+					}
+					let block_found = res.block_found();
+					if block_found {
+						if let Some(ref share) = share {
+							self.record_block_found(share);
+						}
+					}
 					let mut stats = self.stats.write()?;
 					stats.client_stats.last_message_received =
 						"Last Message Received: Share Accepted!!".to_string();
-					stats.mining_stats.solution_stats.num_shares_accepted += 1;
-					let result = serde_json::to_string(&result)?;
-					if result.contains("blockfound") {
+					stats.client_stats.last_share_accepted_at = time::get_time().sec;
+					let difficulty = share.as_ref().map_or(0, |s| s.difficulty);
+					stats
+						.mining_stats
+						.solution_stats
+						.share_accepted(difficulty, block_found);
+					if block_found {
 						info!(LOGGER, "Block Found!!");
 						stats.client_stats.last_message_received =
 							"Last Message Received: Block Found!!".to_string();
-						stats.mining_stats.solution_stats.num_blocks_found += 1;
 					}
 				} else {
 					let err = res.error.unwrap_or_else(invalid_error_response);
@@ -479,8 +1269,20 @@ impl Controller {
 			// "login" response
 			"login" => {
 				if res.result.is_some() {
-					// Nothing to do for login "ok"
-					// dont update last_message_received with good login response
+					// dont update last_message_received with good login response,
+					// but confirm it in the status
+					let status = format!(
+						"Connection Status: Logged in to {} as {}",
+						self.server_url,
+						self.server_login.clone().unwrap_or_default()
+					);
+					info!(LOGGER, "{}", status);
+					self.awaiting_login = false;
+					let mut stats = self.stats.write()?;
+					stats.client_stats.connection_status = status;
+					stats
+						.client_stats
+						.set_connection_state(stats::ConnectionState::Ready);
 				} else {
 					// This is a fatal error
 					let err = res.error.unwrap_or_else(invalid_error_response);
@@ -489,8 +1291,20 @@ impl Controller {
 						format!("Last Message Received: Failed to log in: {:?}", err);
 					stats.client_stats.connection_status =
 						"Connection Status: Server requires login".to_string();
-					stats.client_stats.connected = false;
+					// still connected, but won't be sent any work
+					stats
+						.client_stats
+						.set_connection_state(stats::ConnectionState::Connected);
 					error!(LOGGER, "Failed to log in: {:?}", err);
+					self.login_error = Some(err.message);
+				}
+				Ok(())
+			}
+			// responses to the rest of the handshake
+			m if self.handshake.iter().any(|h| h == m) => {
+				match res.error {
+					Some(err) => warn!(LOGGER, "Handshake {} failed: {:?}", m, err),
+					None => debug!(LOGGER, "Handshake {} ok", m),
 				}
 				Ok(())
 			}
@@ -505,34 +1319,94 @@ impl Controller {
 		}
 	}
 
-	pub fn run(mut self) {
+	/// Connect, log in and wait for the first job without mining it, to
+	/// check the pool config. Gives up after timeout_secs
+	pub fn test_login(mut self, timeout_secs: i64) -> Result<types::JobTemplate, Error> {
+		self.try_connect()?;
+		self.send_handshake()?;
+		self.send_message_get_job_template()?;
+		let deadline = time::get_time().sec + timeout_secs;
+		while time::get_time().sec < deadline {
+			let m = match self.read_message()? {
+				Some(m) => m,
+				None => {
+					thread::sleep(std::time::Duration::from_millis(100));
+					continue;
+				}
+			};
+			let v = match serde_json::from_str::<serde_json::Value>(&m) {
+				Ok(v) => v,
+				Err(_) => continue,
+			};
+			// the job may be pushed rather than sent as the response
+			if v["method"] == "job" {
+				let req = serde_json::from_value::<types::RpcRequest>(v)?;
+				if let Some(params) = req.params {
+					return Ok(serde_json::from_value(params)?);
+				}
+				continue;
+			}
+			let res = match serde_json::from_value::<types::RpcResponse>(v) {
+				Ok(res) => res,
+				Err(_) => continue,
+			};
+			match (res.method.as_str(), res.result, res.error) {
+				("login", _, Some(err)) => return Err(Error::LoginError(err.message)),
+				("getjobtemplate", Some(result), _) => return Ok(serde_json::from_value(result)?),
+				("getjobtemplate", None, Some(err)) => {
+					return Err(Error::ResponseError(err.message))
+				}
+				_ => {}
+			}
+		}
+		Err(Error::ConnectionError(
+			"Timed out waiting for a job".to_string(),
+		))
+	}
+
+	/// Run the client until shut down, or until the server rejects our login
+	pub fn run(mut self) -> Result<(), Error> {
 		let server_read_interval = 1;
-		let server_retry_interval = 5;
 		let mut next_server_read = time::get_time().sec + server_read_interval;
 		let status_interval = 30;
 		let mut next_status_request = time::get_time().sec + status_interval;
+		let mut next_keepalive = time::get_time().sec + self.keepalive_secs;
+		let mut next_failback_probe = time::get_time().sec + self.failback_probe_secs;
 		let mut next_server_retry = time::get_time().sec;
+		let mut next_job_poll = time::get_time().sec + HTTP_POLL_INTERVAL_SECS;
 		// Request the first job template
 		thread::sleep(std::time::Duration::from_secs(1));
 		let mut was_disconnected = true;
 		loop {
+			// A rejected login is fatal, the server won't send us work
+			if let Some(e) = self.login_error.take() {
+				let _ = self.send_miner_stop();
+				return Err(Error::LoginError(e));
+			}
 			// Check our connection status, and try to correct if possible
 			if self.stream.is_none() {
 				if !was_disconnected {
 					let _ = self.send_miner_stop();
+					self.set_connection_state(stats::ConnectionState::Disconnected);
 				}
 				was_disconnected = true;
 				if time::get_time().sec > next_server_retry {
+					self.set_connection_state(stats::ConnectionState::Connecting);
 					if self.try_connect().is_err() {
-						let status = format!("Connection Status: Can't establish server connection to {}. Will retry every {} seconds",
+						let wait = self.connect_failed();
+						next_server_retry = time::get_time().sec + wait;
+						let status = format!("Connection Status: Can't establish server connection to {}. Retrying in {}s",
 							self.server_url,
-							server_retry_interval);
+							wait);
 						warn!(LOGGER, "{}", status);
 						let mut stats = self.stats.write().unwrap();
 						stats.client_stats.connection_status = status;
-						stats.client_stats.connected = false;
+						stats
+							.client_stats
+							.set_connection_state(stats::ConnectionState::Disconnected);
 						self.stream = None;
 					} else {
+						self.connect_succeeded();
 						let status = format!(
 							"Connection Status: Connected to Grin server at {}.",
 							self.server_url
@@ -540,8 +1414,10 @@ impl Controller {
 						warn!(LOGGER, "{}", status);
 						let mut stats = self.stats.write().unwrap();
 						stats.client_stats.connection_status = status;
+						stats
+							.client_stats
+							.set_connection_state(stats::ConnectionState::Connected);
 					}
-					next_server_retry = time::get_time().sec + server_retry_interval;
 					if self.stream.is_none() {
 						thread::sleep(std::time::Duration::from_secs(1));
 						continue;
@@ -550,7 +1426,9 @@ impl Controller {
 			} else {
 				// get new job template
 				if was_disconnected {
-					let _ = self.send_login();
+					self.awaiting_login = false;
+					self.resubmit_on_job = true;
+					let _ = self.send_handshake();
 					let _ = self.send_message_get_job_template();
 					was_disconnected = false;
 				}
@@ -560,17 +1438,15 @@ impl Controller {
 						Ok(message) => {
 							match message {
 								Some(m) => {
-									{
-										let mut stats = self.stats.write().unwrap();
-										stats.client_stats.connected = true;
-									}
 									// figure out what kind of message,
 									// and dispatch appropriately
-									debug!(LOGGER, "Received message: {}", m);
+									if self.log_sampler.sample() {
+										debug!(LOGGER, "Received message: {}", m);
+									}
 									// Deserialize to see what type of object it is
 									if let Ok(v) = serde_json::from_str::<serde_json::Value>(&m) {
 										// Is this a response or request?
-										if v["method"] == "job" {
+										if is_server_request(&v) {
 											// this is a request
 											match serde_json::from_str::<types::RpcRequest>(&m) {
 												Err(e) => error!(
@@ -626,24 +1502,80 @@ impl Controller {
 					next_server_read = time::get_time().sec + server_read_interval;
 				}
 
+				// Poll for new work, the server can't push it over HTTP
+				if self.protocol == StratumProtocol::Http && time::get_time().sec > next_job_poll {
+					if let Err(e) = self.send_message_get_job_template() {
+						error!(LOGGER, "Error polling for job template: {:?}", e);
+						self.stream = None;
+						continue;
+					}
+					next_job_poll = time::get_time().sec + HTTP_POLL_INTERVAL_SECS;
+				}
+
 				// Request a status message from the server
 				if time::get_time().sec > next_status_request {
 					let _ = self.send_message_get_status();
 					next_status_request = time::get_time().sec + status_interval;
 				}
+
+				// Go back to a higher priority server once it's reachable again
+				if self.failback
+					&& self.server_index > 0
+					&& self.failback_probe.is_none()
+					&& self.failback_stream.is_none()
+					&& time::get_time().sec > next_failback_probe
+				{
+					next_failback_probe = time::get_time().sec + self.failback_probe_secs;
+					self.start_failback_probe();
+				}
+				if self.check_failback() {
+					// the old server's job is no use on the new one
+					let _ = self.send_miner_stop();
+					was_disconnected = true;
+					continue;
+				}
+
+				// Keep the connection from being dropped as idle
+				if self.keepalive_secs > 0 && time::get_time().sec > next_keepalive {
+					if let Err(e) = self.send_message_keepalive() {
+						error!(LOGGER, "Error sending keepalive: {:?}", e);
+						self.stream = None;
+						continue;
+					}
+					next_keepalive = time::get_time().sec + self.keepalive_secs;
+				}
 			}
 
 			// Talk to the cuckoo miner plugin
 			while let Some(message) = self.rx.try_iter().next() {
 				debug!(LOGGER, "Client received message: {:?}", message);
 				let result = match message {
-					types::ClientMessage::FoundSolution(height, job_id, edge_bits, nonce, pow) => {
-						self.send_message_submit(height, job_id, edge_bits, nonce, pow)
-					}
+					types::ClientMessage::FoundSolution(
+						height,
+						job_id,
+						edge_bits,
+						nonce,
+						pow,
+						device,
+						difficulty,
+					) => self.send_message_submit(PendingShare {
+						height,
+						job_id,
+						edge_bits,
+						nonce,
+						pow,
+						device,
+						difficulty,
+					}),
 					types::ClientMessage::Shutdown => {
-						//TODO: Inform server?
+						// best effort, pools that don't support it will ignore it
+						if self.logout_on_shutdown && self.stream.is_some() {
+							if let Err(e) = self.send_logout() {
+								debug!(LOGGER, "Failed to log out: {:?}", e);
+							}
+						}
 						debug!(LOGGER, "Shutting down client controller");
-						return;
+						return Ok(());
 					}
 				};
 				if let Err(e) = result {
@@ -655,3 +1587,240 @@ impl Controller {
 		} // loop
 	}
 }
+
+/// Whether a message is a request pushed by the server, rather than a
+/// response to one of ours
+fn is_server_request(v: &serde_json::Value) -> bool {
+	v["method"] == "job" || SET_DIFFICULTY_METHODS.iter().any(|m| v["method"] == *m)
+}
+
+/// Difficulty pushed with set_difficulty, given either as a difficulty
+/// field or as the first of the params
+fn pushed_difficulty(params: &serde_json::Value) -> Option<u64> {
+	params["difficulty"]
+		.as_u64()
+		.or_else(|| params[0].as_u64())
+		.or_else(|| params.as_u64())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn test_controller(urls: &[&str]) -> (Controller, Arc<RwLock<stats::Stats>>) {
+		let (miner_tx, _miner_rx) = mpsc::channel();
+		let stats = Arc::new(RwLock::new(stats::Stats::default()));
+		let urls = urls.iter().map(|u| u.to_string()).collect();
+		let cc = Controller::new(urls, None, None, None, None, miner_tx, stats.clone()).unwrap();
+		(cc, stats)
+	}
+
+	#[test]
+	fn test_pushed_difficulty() {
+		let req = json!({"id": "1", "jsonrpc": "2.0", "method": "set_difficulty", "params": {"difficulty": 64}});
+		assert!(is_server_request(&req));
+		assert_eq!(pushed_difficulty(&req["params"]), Some(64));
+		assert_eq!(pushed_difficulty(&json!([128])), Some(128));
+		assert_eq!(pushed_difficulty(&json!({})), None);
+		// a response to one of our requests isn't pushed by the server
+		assert!(!is_server_request(
+			&json!({"id": "2", "method": "submit", "result": "ok"})
+		));
+	}
+
+	#[test]
+	fn test_tls_host() {
+		assert_eq!(tls_host("192.168.1.20:3416"), "192.168.1.20");
+		assert_eq!(tls_host("pool.example.com:4416"), "pool.example.com");
+		assert_eq!(tls_host("a.b.c.example.co.uk:4416"), "a.b.c.example.co.uk");
+		assert_eq!(tls_host("pool.example.com"), "pool.example.com");
+		assert_eq!(tls_host("[::1]:3416"), "::1");
+	}
+
+	#[test]
+	fn test_reconnect_backoff() {
+		let (mut cc, _) = test_controller(&["127.0.0.1:1"]);
+		let waits: Vec<i64> = (0..8).map(|_| cc.connect_failed()).collect();
+		assert_eq!(waits, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+		cc.connect_succeeded();
+		assert_eq!(cc.connect_failed(), 1);
+		assert_eq!(cc.connect_failed(), 2);
+	}
+
+	#[test]
+	fn test_failover() {
+		let (mut cc, _) = test_controller(&["primary:1", "backup:2"]);
+		assert_eq!(cc.server_url, "primary:1");
+		cc.connect_failed();
+		cc.connect_failed();
+		assert_eq!(cc.server_url, "primary:1");
+		// the third failure in a row moves on, and retries straight away
+		assert_eq!(cc.connect_failed(), 1);
+		assert_eq!(cc.server_url, "backup:2");
+		cc.connect_succeeded();
+		for _ in 0..FAILOVER_AFTER_FAILURES {
+			cc.connect_failed();
+		}
+		// wrapping round to the primary
+		assert_eq!(cc.server_url, "primary:1");
+		assert_eq!(cc.server_index, 0);
+		// unacknowledged shares go along, to be resubmitted if still current
+		cc.pending_shares.insert(
+			"1".to_string(),
+			PendingShare {
+				height: 1,
+				job_id: 1,
+				edge_bits: 29,
+				nonce: 1,
+				pow: vec![],
+				device: 0,
+				difficulty: 1,
+			},
+		);
+		for _ in 0..FAILOVER_AFTER_FAILURES {
+			cc.connect_failed();
+		}
+		assert_eq!(cc.server_url, "backup:2");
+		assert_eq!(cc.pending_shares.len(), 1);
+	}
+
+	#[test]
+	fn test_login_request_redacted() {
+		let (mut cc, _) = test_controller(&["pool:1"]);
+		cc.server_password = Some("hunter2".to_string());
+		let (req, redacted) = cc.login_request("miner".to_string()).unwrap();
+		assert!(req.contains("\"pass\":\"hunter2\""));
+		assert!(!redacted.contains("hunter2"));
+		assert!(redacted.contains("\"pass\":\"***\""));
+	}
+
+	#[test]
+	fn test_failback_waits_for_pending_shares() {
+		let (mut cc, _) = test_controller(&["primary:1", "backup:2"]);
+		cc.switch_server(1);
+		let share = PendingShare {
+			height: 1,
+			job_id: 1,
+			edge_bits: 29,
+			nonce: 1,
+			pow: vec![],
+			device: 0,
+			difficulty: 1,
+		};
+		cc.pending_shares.insert("1".to_string(), share);
+		cc.failback_stream = Some((0, Stream::new()));
+		assert!(!cc.check_failback());
+		assert_eq!(cc.server_url, "backup:2");
+		// switches once the backup has answered, on the probe's connection
+		cc.pending_shares.clear();
+		assert!(cc.check_failback());
+		assert_eq!(cc.server_url, "primary:1");
+		assert!(cc.stream.is_some());
+		assert!(cc.failback_stream.is_none());
+	}
+
+	#[test]
+	fn test_resubmit_after_login_and_job() {
+		let (mut cc, _) = test_controller(&["pool:1"]);
+		let share = |height| PendingShare {
+			height,
+			job_id: 1,
+			edge_bits: 29,
+			nonce: height,
+			pow: vec![0; PROOFSIZE],
+			device: 0,
+			difficulty: 1,
+		};
+		cc.pending_shares.insert("1".to_string(), share(1));
+		cc.pending_shares.insert("2".to_string(), share(2));
+		cc.last_request_id = 2;
+		// reconnected, and logging in again
+		cc.server_login = Some("miner".to_string());
+		cc.resubmit_on_job = true;
+		let _ = cc.send_handshake();
+		assert!(cc.awaiting_login);
+		let job = |height| types::RpcRequest {
+			id: "0".to_string(),
+			jsonrpc: "2.0".to_string(),
+			method: "job".to_string(),
+			params: Some(json!({"height": height, "job_id": 1, "difficulty": 1, "pre_pow": ""})),
+		};
+		// no resubmitting before the login is acknowledged
+		let _ = cc.handle_request(job(2));
+		assert!(cc.resubmit_on_job);
+		assert!(cc.pending_shares.contains_key("2"));
+		cc.handle_response(types::RpcResponse {
+			id: "1".to_string(),
+			method: "login".to_string(),
+			jsonrpc: "2.0".to_string(),
+			result: Some(json!("ok")),
+			error: None,
+		})
+		.unwrap();
+		assert!(!cc.awaiting_login);
+		// only the share for the new job's height is sent again
+		cc.pending_shares.insert("1".to_string(), share(1));
+		let _ = cc.handle_request(job(2));
+		assert!(!cc.resubmit_on_job);
+		let heights: Vec<u64> = cc.pending_shares.values().map(|s| s.height).collect();
+		assert_eq!(heights, vec![2]);
+		assert!(!cc.pending_shares.contains_key("2"));
+	}
+
+	#[test]
+	fn test_send_share() {
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap().to_string();
+		let (mut cc, stats) = test_controller(&[addr.as_str()]);
+		cc.set_submit_retries(1);
+		// a failed send is retried in full, then given up on
+		assert!(cc.send_share("{}").is_err());
+		assert_eq!(stats.read().unwrap().client_stats.submit_failures, 2);
+		cc.try_connect().unwrap();
+		let (server, _) = listener.accept().unwrap();
+		cc.send_share("{\"method\":\"submit\"}").unwrap();
+		let mut line = String::new();
+		io::BufReader::new(server).read_line(&mut line).unwrap();
+		assert_eq!(line, "{\"method\":\"submit\"}\n");
+	}
+
+	#[test]
+	fn test_submit_response_result_and_error() {
+		let (mut cc, stats) = test_controller(&["127.0.0.1:1"]);
+		let error = || {
+			Some(types::RpcError {
+				code: -32502,
+				message: "Failed to validate solution".to_string(),
+			})
+		};
+		let cases = vec![
+			(Some(json!("ok")), None, (1, 0)),
+			(None, error(), (1, 1)),
+			// an error sent with a result is still an error
+			(Some(json!("ok")), error(), (1, 2)),
+			// and no result isn't a success
+			(None, None, (1, 3)),
+		];
+		for (i, (result, error, expected)) in cases.into_iter().enumerate() {
+			cc.handle_response(types::RpcResponse {
+				id: i.to_string(),
+				method: "submit".to_string(),
+				jsonrpc: "2.0".to_string(),
+				result,
+				error,
+			})
+			.unwrap();
+			let s = stats.read().unwrap();
+			let solution_stats = &s.mining_stats.solution_stats;
+			assert_eq!(
+				(
+					solution_stats.num_shares_accepted,
+					solution_stats.num_rejected
+				),
+				expected,
+				"case {}",
+				i
+			);
+		}
+	}
+}
@@ -0,0 +1,192 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Local control socket, accepting one command per line and replying
+//! with one line per command
+
+use stats;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, RwLock};
+use types;
+use util::LOGGER;
+
+pub struct Controller {
+	addr: String,
+	stats: Arc<RwLock<stats::Stats>>,
+	miner_tx: mpsc::Sender<types::MinerMessage>,
+	client_tx: mpsc::Sender<types::ClientMessage>,
+	// tells the TUI to quit on "stop"
+	quit_tui: Arc<AtomicBool>,
+}
+
+impl Controller {
+	pub fn new(
+		addr: &str,
+		stats: Arc<RwLock<stats::Stats>>,
+		miner_tx: mpsc::Sender<types::MinerMessage>,
+		client_tx: mpsc::Sender<types::ClientMessage>,
+		quit_tui: Arc<AtomicBool>,
+	) -> Controller {
+		Controller {
+			addr: addr.to_string(),
+			stats,
+			miner_tx,
+			client_tx,
+			quit_tui,
+		}
+	}
+
+	/// Listen for and serve control connections, one at a time
+	pub fn run(&self) -> Result<(), io::Error> {
+		let listener = TcpListener::bind(&loopback_addrs(&self.addr)?[..])?;
+		info!(LOGGER, "Control socket listening on {}", self.addr);
+		for stream in listener.incoming() {
+			match stream {
+				Ok(s) => {
+					if let Err(e) = self.handle_connection(s) {
+						debug!(LOGGER, "Control connection closed: {:?}", e);
+					}
+				}
+				Err(e) => warn!(LOGGER, "Failed to accept control connection: {:?}", e),
+			}
+		}
+		Ok(())
+	}
+
+	fn handle_connection(&self, stream: TcpStream) -> Result<(), io::Error> {
+		let mut writer = stream.try_clone()?;
+		let reader = BufReader::new(stream);
+		for line in reader.lines() {
+			let line = line?;
+			let command = line.trim();
+			if command.is_empty() {
+				continue;
+			}
+			debug!(LOGGER, "Control command: {}", command);
+			let reply = match self.handle_command(command) {
+				Ok(r) => r,
+				Err(e) => format!("error: {}", e),
+			};
+			writeln!(writer, "{}", reply)?;
+			writer.flush()?;
+		}
+		Ok(())
+	}
+
+	fn handle_command(&self, command: &str) -> Result<String, String> {
+		let mut parts = command.split_whitespace();
+		let cmd = parts.next().unwrap_or("");
+		let arg = parts.next();
+		let send = |m| {
+			self.miner_tx
+				.send(m)
+				.map_err(|e| format!("miner unavailable: {:?}", e))
+		};
+		match cmd {
+			"pause" => send(types::MinerMessage::Pause)?,
+			"resume" => send(types::MinerMessage::Resume)?,
+			"pause-device" => send(types::MinerMessage::PauseDevice(parse_device(arg)?))?,
+			"resume-device" => send(types::MinerMessage::ResumeDevice(parse_device(arg)?))?,
+			"stop" => {
+				self.quit_tui.store(true, Ordering::Relaxed);
+				let _ = self.client_tx.send(types::ClientMessage::Shutdown);
+				send(types::MinerMessage::Shutdown)?;
+			}
+			// an error while any critical device is down, for monitoring
+			"health" => {
+				let stats = self
+					.stats
+					.read()
+					.map_err(|e| format!("failed to get stats lock: {:?}", e))?;
+				let down = &stats.mining_stats.critical_devices_down;
+				if !down.is_empty() {
+					return Err(format!("critical devices down: {:?}", down));
+				}
+			}
+			"status" => {
+				let stats = self
+					.stats
+					.read()
+					.map_err(|e| format!("failed to get stats lock: {:?}", e))?;
+				return Ok(stats.to_json().to_string());
+			}
+			_ => return Err(format!("unknown command '{}'", cmd)),
+		}
+		Ok("ok".to_string())
+	}
+}
+
+/// Addresses to listen on, refusing any that isn't loopback as anyone who
+/// can connect gets to control the miner
+fn loopback_addrs(addr: &str) -> Result<Vec<SocketAddr>, io::Error> {
+	let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+	if addrs.is_empty() || addrs.iter().any(|a| !a.ip().is_loopback()) {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!("control socket address {} is not a loopback address", addr),
+		));
+	}
+	Ok(addrs)
+}
+
+fn parse_device(arg: Option<&str>) -> Result<usize, String> {
+	match arg {
+		Some(a) => a
+			.parse::<usize>()
+			.map_err(|_| format!("invalid device index '{}'", a)),
+		None => Err("missing device index".to_string()),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_stop() {
+		let (miner_tx, miner_rx) = mpsc::channel();
+		let (client_tx, client_rx) = mpsc::channel();
+		let quit_tui = Arc::new(AtomicBool::new(false));
+		let stats = Arc::new(RwLock::new(stats::Stats::default()));
+		let control = Controller::new(
+			"127.0.0.1:3420",
+			stats,
+			miner_tx,
+			client_tx,
+			quit_tui.clone(),
+		);
+		assert_eq!(control.handle_command("stop"), Ok("ok".to_string()));
+		assert!(quit_tui.load(Ordering::Relaxed));
+		match miner_rx.try_recv() {
+			Ok(types::MinerMessage::Shutdown) => {}
+			_ => panic!("miner not shut down"),
+		}
+		match client_rx.try_recv() {
+			Ok(types::ClientMessage::Shutdown) => {}
+			_ => panic!("client not shut down"),
+		}
+	}
+
+	#[test]
+	fn test_loopback_addrs() {
+		assert!(loopback_addrs("127.0.0.1:3420").is_ok());
+		assert!(loopback_addrs("[::1]:3420").is_ok());
+		assert!(loopback_addrs("0.0.0.0:3420").is_err());
+		assert!(loopback_addrs("[::]:3420").is_err());
+		assert!(loopback_addrs("192.168.1.10:3420").is_err());
+		assert!(loopback_addrs("127.0.0.1").is_err());
+	}
+}
@@ -21,30 +21,64 @@ extern crate grin_miner_util as util;
 
 extern crate bufstream;
 extern crate native_tls;
+extern crate socket2;
 extern crate time;
+
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
 extern crate serde_json;
 #[macro_use]
 extern crate slog;
 
 #[cfg(feature = "tui")]
 extern crate cursive;
+#[cfg(unix)]
+extern crate signal_hook;
 
 pub mod client;
+pub mod control;
+pub mod http;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod mining;
+#[cfg(unix)]
+pub mod reload;
 pub mod stats;
+#[cfg(feature = "http-stats")]
+pub mod stats_http;
+pub mod stats_series;
 pub mod types;
+pub mod watchdog;
 
 #[cfg(feature = "tui")]
 pub mod tui;
 
 use config::GlobalConfig;
+use std::env;
+use std::fs;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, RwLock};
+use std::sync::{mpsc, Arc, RwLock};
 use std::thread;
 
-use util::{init_logger, LOGGER};
+use util::{init_logger, LogLevel, LOGGER};
+
+/// Seconds a lost connection is still shown as connected in the TUI
+const DEFAULT_DISCONNECT_GRACE_SECS: u64 = 3;
+
+/// Times a share that fails to send is retried before reconnecting
+const DEFAULT_SUBMIT_RETRIES: u32 = 3;
+
+/// Seconds between keepalive requests to the stratum server
+const DEFAULT_KEEPALIVE_SECS: u64 = 30;
+
+/// Seconds between checks of the higher priority stratum servers while
+/// failed over
+const DEFAULT_FAILBACK_PROBE_SECS: u64 = 60;
+
+/// Seconds --test-login waits for the first job
+const TEST_LOGIN_TIMEOUT_SECS: i64 = 30;
 
 // include build information
 pub mod built_info {
@@ -85,24 +119,25 @@ mod with_tui {
 	use std::thread;
 	use tui::ui;
 	use types;
+	use util::LOGGER;
 
 	pub fn start_tui(
 		s: Arc<RwLock<stats::Stats>>,
 		client_tx: mpsc::Sender<types::ClientMessage>,
 		miner_tx: mpsc::Sender<types::MinerMessage>,
+		quit: Arc<AtomicBool>,
 		stop: Arc<AtomicBool>,
 	) {
 		// Run the UI controller.. here for now for simplicity to access
 		// everything it might need
-		println!("Starting Grin Miner in UI mode...");
-		println!("Waiting for solvers to shutdown...");
+		info!(LOGGER, "Starting Grin Miner in UI mode...");
 		let _ = thread::Builder::new()
 			.name("ui".to_string())
 			.spawn(move || {
 				let mut controller = ui::Controller::new().unwrap_or_else(|e| {
 					panic!("Error loading UI controller: {}", e);
 				});
-				controller.run(s.clone());
+				controller.run(s.clone(), quit);
 				// Shut down everything else on tui exit
 				let _ = client_tx.send(types::ClientMessage::Shutdown);
 				let _ = miner_tx.send(types::MinerMessage::Shutdown);
@@ -111,15 +146,184 @@ mod with_tui {
 	}
 }
 
-fn main() {
-	// Init configuration
-	let mut global_config = GlobalConfig::new(None).unwrap_or_else(|e| {
-		panic!("Error parsing config file: {}", e);
+/// Stratum client controller for the mining config
+fn build_client(
+	mining_config: &config::MinerConfig,
+	miner_tx: mpsc::Sender<types::MinerMessage>,
+	stats: Arc<RwLock<stats::Stats>>,
+) -> client::Controller {
+	let mut server_urls = vec![mining_config.stratum_server_addr.clone()];
+	server_urls.extend(
+		mining_config
+			.stratum_server_failover
+			.clone()
+			.unwrap_or_default(),
+	);
+	let mut cc = client::Controller::new(
+		server_urls,
+		mining_config.stratum_server_login.clone(),
+		mining_config.stratum_server_password.clone(),
+		mining_config.stratum_server_tls_enabled,
+		mining_config.stratum_server_compact_pow,
+		miner_tx,
+		stats,
+	)
+	.unwrap_or_else(|e| {
+		eprintln!("Error loading stratum client controller: {:?}", e);
+		types::ExitCode::ConfigError.exit();
 	});
-	println!(
-		"Starting Grin-Miner from config file at: {}",
-		global_config.config_file_path.unwrap().to_str().unwrap()
+	cc.set_suggested_difficulty(mining_config.stratum_server_suggested_difficulty);
+	cc.set_protocol(mining_config.stratum_protocol.unwrap_or_default());
+	if let Some(handshake) = mining_config.stratum_server_handshake.clone() {
+		cc.set_handshake(handshake);
+	}
+	cc.set_submit_retries(
+		mining_config
+			.stratum_server_submit_retries
+			.unwrap_or(DEFAULT_SUBMIT_RETRIES),
+	);
+	cc.set_keepalive_secs(
+		mining_config
+			.stratum_server_keepalive_secs
+			.unwrap_or(DEFAULT_KEEPALIVE_SECS),
+	);
+	cc.set_failback(
+		mining_config.stratum_server_failback.unwrap_or(true),
+		mining_config
+			.stratum_server_failback_probe_secs
+			.unwrap_or(DEFAULT_FAILBACK_PROBE_SECS),
 	);
+	cc.set_blocks_found_file(mining_config.blocks_found_file.clone());
+	cc.set_bind_addr(mining_config.stratum_bind_addr.clone());
+	cc.set_tls_ca(mining_config.stratum_server_tls_ca.clone());
+	cc.set_logout_on_shutdown(
+		mining_config
+			.stratum_server_logout_on_shutdown
+			.unwrap_or(false),
+	);
+	cc
+}
+
+/// Log in to the pool and wait for a job without loading any plugins,
+/// then exit
+fn test_login(mining_config: &config::MinerConfig) -> ! {
+	let (miner_tx, _miner_rx) = mpsc::channel();
+	let stats = Arc::new(RwLock::new(stats::Stats::default()));
+	let cc = build_client(mining_config, miner_tx, stats);
+	match cc.test_login(TEST_LOGIN_TIMEOUT_SECS) {
+		Ok(job) => {
+			println!(
+				"Logged in to {}, first job at height {} with difficulty {}",
+				mining_config.stratum_server_addr, job.height, job.difficulty
+			);
+			types::ExitCode::CleanShutdown.exit();
+		}
+		Err(client::Error::LoginError(e)) => {
+			eprintln!("Pool rejected login: {}", e);
+			types::ExitCode::PoolAuthError.exit();
+		}
+		Err(e) => {
+			eprintln!(
+				"Failed to get a job from {}: {:?}",
+				mining_config.stratum_server_addr, e
+			);
+			types::ExitCode::NoJob.exit();
+		}
+	}
+}
+
+/// Print the plugins found in the plugin dir, without starting any of
+/// them, then exit
+fn list_plugins(mining_config: &config::MinerConfig) -> ! {
+	let plugins = config::resolve_plugin_dir(mining_config.miner_plugin_dir.clone())
+		.and_then(|dir| cuckoo::get_available_plugins(&dir));
+	let plugins = plugins.unwrap_or_else(|e| {
+		eprintln!("Error listing plugins: {:?}", e);
+		types::ExitCode::NoPlugins.exit();
+	});
+	for (p, build_info) in plugins {
+		println!("{}", p.name);
+		println!("  path: {}", p.file);
+		println!("  algorithm: {}", p.algorithm());
+		println!(
+			"  params: edge_bits {}, device {}, platform {}, nthreads {}, ntrims {}, mutate_nonce {}",
+			p.params.edge_bits,
+			p.params.device,
+			p.params.platform,
+			p.params.nthreads,
+			p.params.ntrims,
+			p.params.mutate_nonce
+		);
+		println!("  build: {}", build_info);
+	}
+	types::ExitCode::CleanShutdown.exit();
+}
+
+/// Write the default config to the given file, or stdout if none
+fn dump_default_config(path: Option<&str>) {
+	let config = GlobalConfig::default().ser_config().unwrap_or_else(|e| {
+		eprintln!("Error serializing default config: {}", e);
+		types::ExitCode::ConfigError.exit();
+	});
+	match path {
+		Some(p) => {
+			if let Err(e) = fs::write(p, config) {
+				eprintln!("Error writing config to {}: {}", p, e);
+				types::ExitCode::ConfigError.exit();
+			}
+			println!("Default config written to {}", p);
+		}
+		None => print!("{}", config),
+	}
+}
+
+fn main() {
+	// print a stats series file as CSV rather than mining
+	let args: Vec<String> = env::args().collect();
+	if let Some(i) = args.iter().position(|a| a == "--stats-dump") {
+		let file = args.get(i + 1).unwrap_or_else(|| {
+			eprintln!("Usage: grin-miner --stats-dump <file>");
+			types::ExitCode::ConfigError.exit();
+		});
+		if let Err(e) = stats_series::dump_csv(file) {
+			eprintln!("Error reading stats series {}: {}", file, e);
+			types::ExitCode::ConfigError.exit();
+		}
+		return;
+	}
+
+	// write out the default config, to a file or stdout, rather than mining
+	if let Some(i) = args.iter().position(|a| a == "--dump-config") {
+		let path = args.get(i + 1).filter(|a| !a.starts_with("--"));
+		dump_default_config(path.map(|p| p.as_str()));
+		return;
+	}
+
+	// Init configuration, optionally falling back to defaults if the
+	// config file is broken
+	let config_path = args.iter().position(|a| a == "--config").map(|i| {
+		args.get(i + 1).map(|p| p.as_str()).unwrap_or_else(|| {
+			eprintln!("Usage: grin-miner --config <file>");
+			types::ExitCode::ConfigError.exit();
+		})
+	});
+	let repair_config = env::args().any(|a| a == "--repair-config");
+	let quiet = env::args().any(|a| a == "--quiet");
+	let login_only = env::args().any(|a| a == "--test-login");
+	let plugins_only = env::args().any(|a| a == "--list-plugins");
+	let global_config = if repair_config {
+		GlobalConfig::new_or_repair(config_path)
+	} else {
+		GlobalConfig::new(config_path)
+	};
+	let mut global_config = global_config.unwrap_or_else(|e| {
+		eprintln!(
+			"Error parsing config file: {} (run with --repair-config to start with defaults)",
+			e
+		);
+		types::ExitCode::ConfigError.exit();
+	});
+	let config_file_path = global_config.config_file_path.clone();
 	// Init logging
 	let mut log_conf = global_config
 		.members
@@ -131,63 +335,97 @@ fn main() {
 
 	let mining_config = global_config.members.as_mut().unwrap().mining.clone();
 
-	if cfg!(feature = "tui") && mining_config.run_tui {
+	if cfg!(feature = "tui") && mining_config.run_tui && !login_only && !plugins_only {
 		log_conf.log_to_stdout = false;
 		log_conf.tui_running = Some(true);
 	}
 
+	// only warnings and errors on stdout, for process managers capturing it
+	if quiet {
+		log_conf.stdout_log_level = match log_conf.stdout_log_level {
+			LogLevel::Critical => LogLevel::Critical,
+			LogLevel::Error => LogLevel::Error,
+			_ => LogLevel::Warning,
+		};
+	}
+
 	init_logger(Some(log_conf));
+	match config_file_path.as_ref() {
+		Some(p) => info!(
+			LOGGER,
+			"Starting Grin-Miner from config file at: {}",
+			p.display()
+		),
+		None => info!(
+			LOGGER,
+			"Starting Grin-Miner with no config file, using built-in defaults"
+		),
+	}
 
 	log_build_info();
+	if login_only {
+		test_login(&mining_config);
+	}
+	if plugins_only {
+		list_plugins(&mining_config);
+	}
 	let stats = Arc::new(RwLock::new(stats::Stats::default()));
+	stats.write().unwrap().client_stats.disconnect_grace_secs = mining_config
+		.tui_disconnect_grace_secs
+		.unwrap_or(DEFAULT_DISCONNECT_GRACE_SECS);
 
 	let mut mc =
 		mining::Controller::new(mining_config.clone(), stats.clone()).unwrap_or_else(|e| {
-			panic!("Error loading mining controller: {}", e);
+			eprintln!("Error loading mining controller: {}", e);
+			types::ExitCode::ConfigError.exit();
 		});
-	let cc = client::Controller::new(
-		&mining_config.stratum_server_addr,
-		mining_config.stratum_server_login.clone(),
-		mining_config.stratum_server_password.clone(),
-		mining_config.stratum_server_tls_enabled,
-		mc.tx.clone(),
-		stats.clone(),
-	)
-	.unwrap_or_else(|e| {
-		panic!("Error loading stratum client controller: {:?}", e);
-	});
+	let cc = build_client(&mining_config, mc.tx.clone(), stats.clone());
 	let tui_stopped = Arc::new(AtomicBool::new(false));
+	let quit_tui = Arc::new(AtomicBool::new(false));
 	let miner_stopped = Arc::new(AtomicBool::new(false));
 	let client_stopped = Arc::new(AtomicBool::new(false));
+	let login_failed = Arc::new(AtomicBool::new(false));
 
 	// Load plugin configuration and start solvers first,
 	// so we can exit pre-tui if something is obviously wrong
 	debug!(LOGGER, "Starting solvers");
-	let result = config::read_configs(
-		mining_config.miner_plugin_dir.clone(),
-		mining_config.miner_plugin_config.clone(),
-	);
-	let mut miner = match result {
+	let mut miner = match config::read_miner_configs(&mining_config) {
 		Ok(cfgs) => cuckoo::CuckooMiner::new(cfgs),
 		Err(e) => {
-			println!("Error loading plugins. Please check logs for further info.");
-			println!("Error details:");
-			println!("{:?}", e);
-			println!("Exiting");
-			return;
+			eprintln!("Error loading plugins. Please check logs for further info.");
+			eprintln!("Error details:");
+			eprintln!("{:?}", e);
+			eprintln!("Exiting");
+			types::ExitCode::NoPlugins.exit();
 		}
 	};
+	miner.set_start_stagger_ms(mining_config.solver_start_stagger_ms.unwrap_or(0));
+	miner.set_shutdown_timeout_secs(
+		mining_config
+			.solver_shutdown_timeout_secs
+			.unwrap_or(cuckoo::DEFAULT_SHUTDOWN_TIMEOUT_SECS),
+	);
 	if let Err(e) = miner.start_solvers() {
-		println!("Error starting plugins. Please check logs for further info.");
-		println!("Error details:");
-		println!("{:?}", e);
-		println!("Exiting");
-		return;
+		eprintln!("Error starting plugins. Please check logs for further info.");
+		eprintln!("Error details:");
+		eprintln!("{:?}", e);
+		eprintln!("Exiting");
+		// plugins that loaded but couldn't set up their device
+		match e {
+			cuckoo::CuckooMinerError::PluginProcessingError(_) => types::ExitCode::NoDevices.exit(),
+			_ => types::ExitCode::NoPlugins.exit(),
+		}
 	}
 
 	if mining_config.run_tui {
 		#[cfg(feature = "tui")]
-		with_tui::start_tui(stats, cc.tx.clone(), mc.tx.clone(), tui_stopped.clone());
+		with_tui::start_tui(
+			stats.clone(),
+			cc.tx.clone(),
+			mc.tx.clone(),
+			quit_tui.clone(),
+			tui_stopped.clone(),
+		);
 
 		#[cfg(not(feature = "tui"))]
 		warn!(LOGGER, "Grin-miner was built with TUI support disabled!");
@@ -197,6 +435,69 @@ fn main() {
 
 	mc.set_client_tx(cc.tx.clone());
 
+	if let Some(addr) = mining_config.control_socket_addr.clone() {
+		let control = control::Controller::new(
+			&addr,
+			stats.clone(),
+			mc.tx.clone(),
+			cc.tx.clone(),
+			quit_tui.clone(),
+		);
+		let _ = thread::Builder::new()
+			.name("control_socket".to_string())
+			.spawn(move || {
+				if let Err(e) = control.run() {
+					error!(LOGGER, "Control socket error: {:?}", e);
+				}
+			});
+	}
+
+	// re-read the plugin configs from the config file on SIGHUP
+	#[cfg(unix)]
+	{
+		if let Some(p) = config_file_path {
+			if let Err(e) = reload::spawn_handler(p, mc.tx.clone()) {
+				warn!(LOGGER, "Failed to install SIGHUP handler: {:?}", e);
+			}
+		}
+	}
+
+	#[cfg(feature = "http-stats")]
+	{
+		if let Some(addr) = mining_config.stats_http_addr.clone() {
+			let server = stats_http::StatsServer::new(&addr, stats.clone());
+			let _ = thread::Builder::new()
+				.name("stats_http".to_string())
+				.spawn(move || {
+					if let Err(e) = server.run() {
+						error!(LOGGER, "Stats HTTP server error: {:?}", e);
+					}
+				});
+		}
+	}
+	#[cfg(not(feature = "http-stats"))]
+	{
+		if mining_config.stats_http_addr.is_some() {
+			warn!(
+				LOGGER,
+				"Grin-miner was built without the http-stats feature, not serving stats"
+			);
+		}
+	}
+
+	if let Some(secs) = mining_config.stall_watchdog_secs {
+		let watchdog = watchdog::Watchdog::new(
+			secs,
+			mining_config.stall_watchdog_exit.unwrap_or(false),
+			stats.clone(),
+			mc.tx.clone(),
+		);
+		let _ = thread::Builder::new()
+			.name("watchdog".to_string())
+			.spawn(move || watchdog.run());
+	}
+
+	let mc_tx = mc.tx.clone();
 	let miner_stopped_internal = miner_stopped.clone();
 	let _ = thread::Builder::new()
 		.name("mining_controller".to_string())
@@ -212,14 +513,26 @@ fn main() {
 		});
 
 	let client_stopped_internal = client_stopped.clone();
+	let login_failed_internal = login_failed.clone();
 	let _ = thread::Builder::new()
 		.name("client_controller".to_string())
 		.spawn(move || {
-			cc.run();
+			if let Err(e) = cc.run() {
+				error!(LOGGER, "Stratum client stopped: {:?}", e);
+				if let client::Error::LoginError(_) = e {
+					login_failed_internal.store(true, Ordering::Relaxed);
+					let _ = mc_tx.send(types::MinerMessage::Shutdown);
+				}
+			}
 			client_stopped_internal.store(true, Ordering::Relaxed);
 		});
 
 	loop {
+		// don't wait on the TUI, there's nothing left to show
+		if login_failed.load(Ordering::Relaxed) && miner_stopped.load(Ordering::Relaxed) {
+			eprintln!("Pool rejected login. Exiting");
+			types::ExitCode::PoolAuthError.exit();
+		}
 		if miner_stopped.load(Ordering::Relaxed)
 			&& client_stopped.load(Ordering::Relaxed)
 			&& tui_stopped.load(Ordering::Relaxed)
@@ -229,4 +542,5 @@ fn main() {
 		}
 		thread::sleep(std::time::Duration::from_millis(100));
 	}
+	types::ExitCode::CleanShutdown.exit();
 }
@@ -0,0 +1,261 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! HTTP transport for the stratum client. Each newline terminated request
+//! written is POSTed to the server, and the JSON response is made available
+//! to read back as a single line, so it can stand in for the TCP stream
+
+use native_tls::{Certificate, TlsConnector};
+use serde_json;
+use std::fs;
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+const HTTP_TIMEOUT_SECS: u64 = 10;
+
+/// TLS connector trusting the CA certificate in `ca_file`, PEM or DER
+/// encoded, as well as the system's, e.g. for pools with a self-signed
+/// certificate
+pub fn tls_connector(ca_file: Option<&str>) -> Result<TlsConnector, String> {
+	let mut builder = TlsConnector::builder();
+	if let Some(f) = ca_file {
+		let bytes = fs::read(f).map_err(|e| format!("Can't read TLS CA file {}: {}", f, e))?;
+		let cert = Certificate::from_pem(&bytes)
+			.or_else(|_| Certificate::from_der(&bytes))
+			.map_err(|e| format!("Invalid TLS CA certificate {}: {:?}", f, e))?;
+		builder.add_root_certificate(cert);
+	}
+	builder
+		.build()
+		.map_err(|e| format!("Can't create TLS connector: {:?}", e))
+}
+
+/// Host and port of a `host[:port]` authority, IPv6 hosts keeping their
+/// brackets
+fn split_authority(authority: &str) -> Result<(&str, Option<u16>), String> {
+	match authority.rfind(':') {
+		Some(i) if !authority[i + 1..].contains(']') => {
+			let port = authority[i + 1..]
+				.parse::<u16>()
+				.map_err(|_| format!("Invalid port in {}", authority))?;
+			Ok((&authority[..i], Some(port)))
+		}
+		_ => Ok((authority, None)),
+	}
+}
+
+pub struct HttpTransport {
+	// host:port to connect to
+	addr: String,
+	// host to verify the server's TLS certificate against
+	host: String,
+	// Host header, with the port unless it's the scheme's default
+	host_header: String,
+	path: String,
+	// set for https
+	connector: Option<TlsConnector>,
+	// request written so far, sent on newline
+	out_buf: Vec<u8>,
+	// responses not yet read
+	in_buf: Vec<u8>,
+	in_pos: usize,
+}
+
+impl HttpTransport {
+	/// Create a transport for the given url, e.g. `http://host:port/path`
+	/// or just `host:port`, trusting the CA certificate in `tls_ca` for
+	/// https if given
+	pub fn new(
+		url: &str,
+		tls: Option<bool>,
+		tls_ca: Option<&str>,
+	) -> Result<HttpTransport, String> {
+		let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+			(true, rest)
+		} else if let Some(rest) = url.strip_prefix("http://") {
+			(tls.unwrap_or(false), rest)
+		} else {
+			(tls.unwrap_or(false), url)
+		};
+		let (addr, path) = match rest.find('/') {
+			Some(i) => (&rest[..i], &rest[i..]),
+			None => (rest, "/"),
+		};
+		let (host, port) = split_authority(addr)?;
+		if host.is_empty() {
+			return Err(format!("Invalid server url: {}", url));
+		}
+		let default_port = if tls { 443 } else { 80 };
+		let port = port.unwrap_or(default_port);
+		let host_header = if port == default_port {
+			host.to_string()
+		} else {
+			format!("{}:{}", host, port)
+		};
+		let connector = if tls {
+			Some(tls_connector(tls_ca)?)
+		} else {
+			None
+		};
+		Ok(HttpTransport {
+			addr: format!("{}:{}", host, port),
+			host: host
+				.trim_start_matches('[')
+				.trim_end_matches(']')
+				.to_string(),
+			host_header,
+			path: path.to_string(),
+			connector,
+			out_buf: vec![],
+			in_buf: vec![],
+			in_pos: 0,
+		})
+	}
+
+	/// Check the server is reachable
+	pub fn check_connection(&self) -> io::Result<()> {
+		self.connect().map(|_| ())
+	}
+
+	fn connect(&self) -> io::Result<TcpStream> {
+		let timeout = Duration::from_secs(HTTP_TIMEOUT_SECS);
+		let addr =
+			self.addr.to_socket_addrs()?.next().ok_or_else(|| {
+				io::Error::new(ErrorKind::NotFound, "Can't resolve server address")
+			})?;
+		let conn = TcpStream::connect_timeout(&addr, timeout)?;
+		conn.set_read_timeout(Some(timeout))?;
+		conn.set_write_timeout(Some(timeout))?;
+		Ok(conn)
+	}
+
+	/// POST a request, returning the response body
+	fn post(&self, body: &[u8]) -> io::Result<Vec<u8>> {
+		// HTTP/1.0 so the server closes the connection and doesn't chunk
+		let mut req = format!(
+			"POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+			self.path,
+			self.host_header,
+			body.len()
+		)
+		.into_bytes();
+		req.extend_from_slice(body);
+
+		let conn = self.connect()?;
+		let mut res = vec![];
+		if let Some(ref connector) = self.connector {
+			let mut stream = connector
+				.connect(&self.host, conn)
+				.map_err(|e| io::Error::new(ErrorKind::Other, format!("{:?}", e)))?;
+			stream.write_all(&req)?;
+			stream.read_to_end(&mut res)?;
+		} else {
+			let mut stream = conn;
+			stream.write_all(&req)?;
+			stream.read_to_end(&mut res)?;
+		}
+
+		let header_end = res
+			.windows(4)
+			.position(|w| w == b"\r\n\r\n")
+			.ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Malformed HTTP response"))?;
+		let status_line = String::from_utf8_lossy(&res[..header_end]);
+		let status_line = status_line.lines().next().unwrap_or("");
+		if status_line.split_whitespace().nth(1) != Some("200") {
+			return Err(io::Error::new(
+				ErrorKind::Other,
+				format!("HTTP request failed: {}", status_line),
+			));
+		}
+		Ok(res.split_off(header_end + 4))
+	}
+}
+
+impl Write for HttpTransport {
+	fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+		self.out_buf.extend_from_slice(b);
+		while let Some(i) = self.out_buf.iter().position(|c| *c == b'\n') {
+			let req: Vec<u8> = self.out_buf.drain(..=i).collect();
+			let body = self.post(&req[..i])?;
+			// responses are read back one per line
+			let value: serde_json::Value = serde_json::from_slice(&body)
+				.map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("{}", e)))?;
+			self.in_buf.extend_from_slice(value.to_string().as_bytes());
+			self.in_buf.push(b'\n');
+		}
+		Ok(b.len())
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl Read for HttpTransport {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let n = {
+			let available = self.fill_buf()?;
+			let n = available.len().min(buf.len());
+			buf[..n].copy_from_slice(&available[..n]);
+			n
+		};
+		self.consume(n);
+		Ok(n)
+	}
+}
+
+impl BufRead for HttpTransport {
+	fn fill_buf(&mut self) -> io::Result<&[u8]> {
+		if self.in_pos >= self.in_buf.len() {
+			self.in_buf.clear();
+			self.in_pos = 0;
+			// nothing until the next request, as a nonblocking socket would
+			return Err(io::Error::new(ErrorKind::WouldBlock, "No response pending"));
+		}
+		Ok(&self.in_buf[self.in_pos..])
+	}
+	fn consume(&mut self, amt: usize) {
+		self.in_pos += amt;
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_server_url() {
+		let t = HttpTransport::new("http://pool.example.com/rpc", None, None).unwrap();
+		assert_eq!(t.addr, "pool.example.com:80");
+		assert_eq!(t.host_header, "pool.example.com");
+		assert_eq!(t.path, "/rpc");
+
+		let t = HttpTransport::new("pool.example.com:3416", None, None).unwrap();
+		assert_eq!(t.addr, "pool.example.com:3416");
+		assert_eq!(t.host_header, "pool.example.com:3416");
+		assert_eq!(t.path, "/");
+
+		let t = HttpTransport::new("http://[::1]:3416/", None, None).unwrap();
+		assert_eq!(t.addr, "[::1]:3416");
+		assert_eq!(t.host, "::1");
+		assert_eq!(t.host_header, "[::1]:3416");
+
+		let t = HttpTransport::new("[::1]", None, None).unwrap();
+		assert_eq!(t.addr, "[::1]:80");
+		assert_eq!(t.host_header, "[::1]");
+
+		assert!(HttpTransport::new("http://:3416/", None, None).is_err());
+		assert!(HttpTransport::new("pool.example.com:port", None, None).is_err());
+	}
+}
@@ -0,0 +1,133 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stats in the Prometheus text exposition format, served at /metrics by
+//! the stats HTTP server
+
+use stats::{ConnectionState, Stats};
+use std::fmt::Write;
+
+/// Content type of the Prometheus text format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Render the current stats as Prometheus metrics
+pub fn render(stats: &Stats) -> String {
+	let mining = &stats.mining_stats;
+	let solutions = &mining.solution_stats;
+	let connected = stats.client_stats.connection_state == ConnectionState::Ready;
+	let mut out = String::new();
+	metric(
+		&mut out,
+		"grin_miner_gps_total",
+		"gauge",
+		"Combined graphs per second of all devices",
+		mining.combined_gps(),
+	);
+	header(
+		&mut out,
+		"grin_miner_device_gps",
+		"gauge",
+		"Graphs per second of each device",
+	);
+	for (i, s) in mining.device_stats.iter().enumerate() {
+		let _ = writeln!(
+			out,
+			"grin_miner_device_gps{{device=\"{}\",name=\"{}\"}} {}",
+			i,
+			escape_label(&s.get_device_name()),
+			s.average_graphs_per_second().unwrap_or(0.0)
+		);
+	}
+	metric(
+		&mut out,
+		"grin_miner_shares_accepted_total",
+		"counter",
+		"Shares accepted by the pool",
+		solutions.num_shares_accepted,
+	);
+	metric(
+		&mut out,
+		"grin_miner_shares_rejected_total",
+		"counter",
+		"Shares rejected by the pool",
+		solutions.num_rejected,
+	);
+	metric(
+		&mut out,
+		"grin_miner_stale_total",
+		"counter",
+		"Shares submitted for a job the pool had moved on from",
+		solutions.num_staled,
+	);
+	metric(
+		&mut out,
+		"grin_miner_blocks_found_total",
+		"counter",
+		"Accepted shares that found a block",
+		solutions.num_blocks_found,
+	);
+	metric(
+		&mut out,
+		"grin_miner_connected",
+		"gauge",
+		"Whether the miner is connected and receiving jobs",
+		connected as u8,
+	);
+	out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+	let _ = writeln!(out, "# HELP {} {}", name, help);
+	let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn metric<T: ::std::fmt::Display>(out: &mut String, name: &str, kind: &str, help: &str, value: T) {
+	header(out, name, kind, help);
+	let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn escape_label(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use cuckoo::DeviceStats;
+
+	#[test]
+	fn test_render() {
+		let mut stats = Stats::default();
+		let mut device = DeviceStats::default();
+		device.set_device_name("GPU \"0\"");
+		device.rolling_gps = 1.5;
+		stats.mining_stats.device_stats = vec![device, DeviceStats::default()];
+		stats.mining_stats.solution_stats.share_accepted(1, true);
+		stats.mining_stats.solution_stats.num_rejected = 2;
+		stats.client_stats.connection_state = ConnectionState::Ready;
+
+		let out = render(&stats);
+		let lines: Vec<&str> = out.lines().collect();
+		assert!(lines.contains(&"# TYPE grin_miner_shares_accepted_total counter"));
+		assert!(lines.contains(&"grin_miner_device_gps{device=\"0\",name=\"GPU \\\"0\\\"\"} 1.5"));
+		assert!(lines.contains(&"grin_miner_device_gps{device=\"1\",name=\"\"} 0"));
+		assert!(lines.contains(&"grin_miner_shares_accepted_total 1"));
+		assert!(lines.contains(&"grin_miner_shares_rejected_total 2"));
+		assert!(lines.contains(&"grin_miner_blocks_found_total 1"));
+		assert!(lines.contains(&"grin_miner_connected 1"));
+	}
+}
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use stats_series::StatsSeriesWriter;
 /// Plugin controller, listens for messages sent from the stratum
 /// server, controls plugins and responds appropriately
 use std::sync::{mpsc, Arc, RwLock};
@@ -20,18 +21,84 @@ use time;
 use util::LOGGER;
 use {config, stats, types};
 
-use cuckoo::{CuckooMiner, CuckooMinerError};
+use cuckoo::{
+	header_siphash_keys, is_plausible_proof, CuckooMiner, CuckooMinerError, FoundSolutions, Proof,
+};
 
-use plugin::SolverStats;
+use plugin::Solution;
+
+/// Number of stat samples a device's rolling GPS is averaged over
+const DEVICE_GPS_SAMPLES: usize = 10;
+/// Default fraction of expected GPS below which a device underperforms
+const DEFAULT_UNDERPERFORMING_FRACTION: f64 = 0.8;
+/// Default size at which the stats series file is rotated
+const DEFAULT_STATS_SERIES_MAX_MB: u64 = 64;
+/// Seconds between share summary lines in the logs
+const SHARE_SUMMARY_INTERVAL_SECS: i64 = 60;
+/// Default seconds a critical device can go without a graph while working
+const DEFAULT_CRITICAL_STALL_SECS: u64 = 120;
+
+/// Plausible solutions across the given batches, highest difficulty first,
+/// so the likeliest block goes out before a new job can make it stale
+fn sorted_solutions(batches: &[FoundSolutions]) -> Vec<(&FoundSolutions, Solution, u64)> {
+	let mut sols = vec![];
+	for ss in batches {
+		for i in 0..ss.num_sols as usize {
+			if is_plausible_proof(&ss.sols[i].proof) {
+				sols.push((ss, ss.sols[i], ss.difficulties[i]));
+			}
+		}
+	}
+	sols.sort_by_key(|s| std::cmp::Reverse(s.2));
+	sols
+}
+
+/// Uptime as hours, minutes and seconds
+fn format_uptime(secs: i64) -> String {
+	format!("{}h{:02}m{:02}s", secs / 3600, (secs / 60) % 60, secs % 60)
+}
 
 pub struct Controller {
-	_config: config::MinerConfig,
+	config: config::MinerConfig,
 	rx: mpsc::Receiver<types::MinerMessage>,
 	pub tx: mpsc::Sender<types::MinerMessage>,
 	client_tx: Option<mpsc::Sender<types::ClientMessage>>,
 	current_height: u64,
 	current_job_id: u64,
 	current_target_diff: u64,
+	// header of the current job, up to the nonce
+	current_pre_pow: String,
+	// algorithm each device mines
+	algorithms: Vec<String>,
+	// when the current job was received, in seconds
+	job_received_at: i64,
+	// solvers paused because the current job is too old
+	job_too_old: bool,
+	// solvers paused because the current job's difficulty is out of bounds
+	difficulty_refused: bool,
+	// configured expected GPS of each device
+	expected_gps: Vec<Option<f64>>,
+	// recent GPS of each device, newest first
+	device_gps: Vec<Vec<f64>>,
+	// devices currently flagged as underperforming
+	underperforming: Vec<bool>,
+	// devices configured as critical
+	critical: Vec<bool>,
+	// iterations of each device, and when they last went up
+	graph_progress: Vec<(u32, i64)>,
+	// critical devices currently considered down
+	critical_down: Vec<bool>,
+	// binary stats samples, if enabled
+	stats_series: Option<StatsSeriesWriter>,
+	// when the controller was started, in seconds
+	started_at: i64,
+	// when the next headless summary line is due, in seconds
+	next_summary: i64,
+	// when the next share summary line is due, in seconds
+	next_share_summary: i64,
+	// solutions held back as below the minimum share difficulty, since
+	// last logged
+	num_below_share_floor: u32,
 	stats: Arc<RwLock<stats::Stats>>,
 }
 
@@ -45,14 +112,36 @@ impl Controller {
 			stats_w.client_stats.server_url = config.stratum_server_addr.clone();
 		}
 		let (tx, rx) = mpsc::channel::<types::MinerMessage>();
+		let stats_series = config.stats_series_file.as_ref().map(|f| {
+			let max_mb = config
+				.stats_series_max_mb
+				.unwrap_or(DEFAULT_STATS_SERIES_MAX_MB);
+			StatsSeriesWriter::new(f, max_mb * 1024 * 1024)
+		});
 		Ok(Controller {
-			_config: config,
+			config,
 			rx,
 			tx,
 			client_tx: None,
 			current_height: 0,
 			current_job_id: 0,
 			current_target_diff: 0,
+			current_pre_pow: String::new(),
+			algorithms: vec![],
+			job_received_at: 0,
+			job_too_old: false,
+			difficulty_refused: false,
+			expected_gps: vec![],
+			device_gps: vec![],
+			underperforming: vec![],
+			critical: vec![],
+			graph_progress: vec![],
+			critical_down: vec![],
+			stats_series,
+			started_at: time::get_time().sec,
+			next_summary: 0,
+			next_share_summary: 0,
+			num_below_share_floor: 0,
 			stats,
 		})
 	}
@@ -66,6 +155,14 @@ impl Controller {
 		// how often to output stats
 		let stat_output_interval = 2;
 		let mut next_stat_output = time::get_time().sec + stat_output_interval;
+		self.expected_gps = miner.expected_gps();
+		self.device_gps = vec![vec![]; self.expected_gps.len()];
+		self.underperforming = vec![false; self.expected_gps.len()];
+		self.critical = miner.critical_devices();
+		self.algorithms = miner.algorithms();
+		self.graph_progress = vec![(0, time::get_time().sec); self.critical.len()];
+		self.critical_down = vec![false; self.critical.len()];
+		self.stats.write().unwrap().mining_stats.plugin_build_info = miner.plugin_build_info();
 
 		loop {
 			while let Some(message) = self.rx.try_iter().next() {
@@ -75,19 +172,140 @@ impl Controller {
 						self.current_height = height;
 						self.current_job_id = job_id;
 						self.current_target_diff = diff;
-						miner.notify(
+						self.current_pre_pow = pre_pow.clone();
+						self.job_received_at = time::get_time().sec;
+						{
+							// show the new job right away, rather than on the next
+							// stat output
+							let mut s_stats = self.stats.write().unwrap();
+							s_stats.mining_stats.block_height = height;
+							s_stats.mining_stats.target_difficulty = diff;
+							s_stats.mining_stats.solution_stats.start_job(job_id);
+							s_stats.mining_stats.job_too_old = false;
+						}
+						if !self.difficulty_in_bounds(diff)
+							&& self.config.refuse_out_of_bounds_difficulty.unwrap_or(false)
+						{
+							warn!(
+								LOGGER,
+								"Refusing to mine job {} at difficulty {}", job_id, diff
+							);
+							self.difficulty_refused = true;
+							miner.pause_solvers();
+							continue;
+						}
+						let res = miner.notify(
 							self.current_job_id as u32,
 							self.current_height,
 							&pre_pow,
 							"",
 							diff,
-						)
+						);
+						if self.job_too_old || self.difficulty_refused {
+							info!(LOGGER, "Fresh job received, resuming mining");
+							self.job_too_old = false;
+							self.difficulty_refused = false;
+							miner.resume_solvers();
+						}
+						res
+					}
+					types::MinerMessage::SetDifficulty(diff) => {
+						self.current_target_diff = diff;
+						self.stats.write().unwrap().mining_stats.target_difficulty = diff;
+						if !self.difficulty_in_bounds(diff)
+							&& self.config.refuse_out_of_bounds_difficulty.unwrap_or(false)
+						{
+							warn!(LOGGER, "Refusing to mine at difficulty {}", diff);
+							self.difficulty_refused = true;
+							miner.pause_solvers();
+							Ok(())
+						} else if self.difficulty_refused {
+							info!(LOGGER, "Difficulty {} accepted, resuming mining", diff);
+							self.difficulty_refused = false;
+							// a refused job was never handed to the solvers
+							let res = miner.notify(
+								self.current_job_id as u32,
+								self.current_height,
+								&self.current_pre_pow,
+								"",
+								diff,
+							);
+							if !self.job_too_old {
+								miner.resume_solvers();
+							}
+							res
+						} else {
+							miner.set_difficulty(diff);
+							Ok(())
+						}
 					}
 					types::MinerMessage::StopJob => {
 						debug!(LOGGER, "Stopping jobs");
 						miner.pause_solvers();
 						Ok(())
 					}
+					types::MinerMessage::Pause => {
+						info!(LOGGER, "Pausing mining");
+						miner.set_paused(true);
+						Ok(())
+					}
+					types::MinerMessage::Resume => {
+						info!(LOGGER, "Resuming mining");
+						miner.set_paused(false);
+						// otherwise mining resumes with the next acceptable job
+						if !self.job_too_old && !self.difficulty_refused {
+							miner.resume_solvers();
+						}
+						Ok(())
+					}
+					types::MinerMessage::PauseDevice(i) => {
+						info!(LOGGER, "Pausing mining on solver {}", i);
+						miner.set_device_paused(i, true)
+					}
+					types::MinerMessage::ResumeDevice(i) => {
+						info!(LOGGER, "Resuming mining on solver {}", i);
+						let res = miner.set_device_paused(i, false);
+						if res.is_ok() && !self.job_too_old && !self.difficulty_refused {
+							miner.resume_solvers();
+						}
+						res
+					}
+					types::MinerMessage::ShareAccepted(i) => {
+						miner.reset_graphs_since_share(i);
+						Ok(())
+					}
+					types::MinerMessage::RestartSolvers => {
+						warn!(LOGGER, "Restarting solvers");
+						let res = miner.restart_solvers();
+						self.stats.write().unwrap().mining_stats.plugin_build_info =
+							miner.plugin_build_info();
+						if !self.job_too_old && !self.difficulty_refused {
+							miner.resume_solvers();
+						}
+						res
+					}
+					types::MinerMessage::ReloadPlugins(configs) => match miner.reconfigure(configs)
+					{
+						Ok(changed) => {
+							if changed.is_empty() {
+								info!(LOGGER, "Plugin configs unchanged, nothing to reconfigure");
+							}
+							for i in changed.iter() {
+								info!(LOGGER, "Reconfigured solver {}", i);
+							}
+							self.expected_gps = miner.expected_gps();
+							self.critical = miner.critical_devices();
+							self.algorithms = miner.algorithms();
+							self.stats.write().unwrap().mining_stats.plugin_build_info =
+								miner.plugin_build_info();
+							if !changed.is_empty() && !self.job_too_old && !self.difficulty_refused
+							{
+								miner.resume_solvers();
+							}
+							Ok(())
+						}
+						Err(e) => Err(e),
+					},
 					types::MinerMessage::Shutdown => {
 						debug!(LOGGER, "Stopping jobs and Shutting down mining controller");
 						miner.stop_solvers();
@@ -100,40 +318,297 @@ impl Controller {
 				}
 			}
 
+			self.check_job_age(&miner);
+
 			if time::get_time().sec > next_stat_output {
-				self.output_job_stats(miner.get_stats().unwrap());
+				let paused = miner.is_paused() || self.difficulty_refused;
+				{
+					let mut s_stats = self.stats.write().unwrap();
+					s_stats.mining_stats.uptime_secs = miner.uptime().as_secs();
+					s_stats.mining_stats.total_graphs = miner.total_graphs();
+				}
+				self.output_job_stats(miner.get_stats().unwrap(), paused);
 				next_stat_output = time::get_time().sec + stat_output_interval;
 			}
 
-			let solutions = miner.get_solutions();
-			if let Some(ss) = solutions {
-				let edge_bits = ss.edge_bits;
-				for i in 0..ss.num_sols {
-					let _ =
-						self.client_tx
-							.as_mut()
-							.unwrap()
-							.send(types::ClientMessage::FoundSolution(
-								self.current_height,
-								ss.sols[i as usize].id,
-								edge_bits,
-								ss.sols[i as usize].nonce,
-								ss.sols[i as usize].proof.to_vec(),
-							));
+			// everything found since the last pass, submitted best first
+			let mut batches = vec![];
+			while let Some(mut ss) = miner.get_solutions() {
+				let num_invalid = self.drop_invalid_solutions(&mut ss);
+				if num_invalid > 0 {
+					let mut s_stats = self.stats.write().unwrap();
+					s_stats.mining_stats.solution_stats.num_invalid += num_invalid;
+				}
+				batches.push(ss);
+			}
+			for (ss, sol, difficulty) in sorted_solutions(&batches) {
+				if self.below_share_floor(ss.edge_bits, &sol) {
+					self.num_below_share_floor += 1;
+					continue;
+				}
+				let _ = self
+					.client_tx
+					.as_mut()
+					.unwrap()
+					.send(types::ClientMessage::FoundSolution(
+						ss.height,
+						sol.id,
+						ss.edge_bits,
+						sol.nonce,
+						sol.proof.to_vec(),
+						ss.instance as usize,
+						difficulty,
+					));
+			}
+			for ss in batches {
+				let mut num_malformed = 0;
+				for sol in ss.sols.iter().take(ss.num_sols as usize) {
+					if !is_plausible_proof(&sol.proof) {
+						warn!(
+							LOGGER,
+							"Dropping malformed solution from solver {}: {:?}",
+							ss.instance,
+							sol.proof.to_vec()
+						);
+						num_malformed += 1;
+					}
 				}
+				let num_sols = ss.num_sols - num_malformed;
 				let mut s_stats = self.stats.write().unwrap();
-				s_stats.mining_stats.solution_stats.num_solutions_found += ss.num_sols;
+				s_stats.mining_stats.solution_stats.num_solutions_found += num_sols;
+				s_stats.mining_stats.solution_stats.num_malformed += num_malformed;
+				if num_sols > 0 {
+					let job_id = ss.sols[0].id;
+					s_stats
+						.mining_stats
+						.solution_stats
+						.add_job_solutions(job_id, num_sols);
+					debug!(
+						LOGGER,
+						"{} solution(s) found so far for job {}",
+						s_stats.mining_stats.solution_stats.job_solutions(job_id),
+						job_id
+					);
+				}
 			}
 			thread::sleep(std::time::Duration::from_millis(100));
 		}
 	}
 
-	fn output_job_stats(&mut self, stats: Vec<SolverStats>) {
-		let mut sps_total = 0.0;
+	/// Verify the cycle of each of the current job's solutions, dropping the
+	/// ones that aren't valid so they're never submitted. Solutions for an
+	/// earlier job are left alone, as its header is no longer at hand.
+	/// Returns the number dropped
+	fn drop_invalid_solutions(&self, ss: &mut FoundSolutions) -> u32 {
+		let algorithm = match self.algorithms.get(ss.instance as usize) {
+			Some(a) => a,
+			None => return 0,
+		};
+		let mut kept = 0;
+		for i in 0..ss.num_sols as usize {
+			let sol = ss.sols[i];
+			if sol.id == self.current_job_id && is_plausible_proof(&sol.proof) {
+				let proof = Proof {
+					edge_bits: ss.edge_bits as u8,
+					nonces: sol.proof.to_vec(),
+				};
+				let keys = header_siphash_keys(&self.current_pre_pow, sol.nonce);
+				if let Err(e) = proof.verify(&keys, algorithm) {
+					warn!(
+						LOGGER,
+						"Dropping invalid solution from solver {}: {}, {:?}", ss.instance, e, proof
+					);
+					continue;
+				}
+			}
+			ss.sols[kept] = sol;
+			ss.difficulties[kept] = ss.difficulties[i];
+			kept += 1;
+		}
+		let num_invalid = ss.num_sols - kept as u32;
+		ss.num_sols = kept as u32;
+		num_invalid
+	}
+
+	/// Whether a solution's unscaled difficulty is below the configured
+	/// minimum share difficulty, or the job's target if that's higher
+	fn below_share_floor(&self, edge_bits: u32, sol: &Solution) -> bool {
+		let floor = match self.config.min_share_difficulty {
+			Some(f) => f.max(self.current_target_diff),
+			None => return false,
+		};
+		let proof = Proof {
+			edge_bits: edge_bits as u8,
+			nonces: sol.proof.to_vec(),
+		};
+		proof.to_difficulty_unscaled().to_num() < floor
+	}
+
+	/// Check a job's target difficulty against the configured sanity
+	/// bounds, warning if it's outside them
+	fn difficulty_in_bounds(&self, diff: u64) -> bool {
+		if let Some(min) = self.config.min_accept_difficulty {
+			if diff < min {
+				warn!(
+					LOGGER,
+					"Job target difficulty {} is below the minimum of {}, pool may be misconfigured",
+					diff,
+					min
+				);
+				return false;
+			}
+		}
+		if let Some(max) = self.config.max_accept_difficulty {
+			if diff > max {
+				warn!(
+					LOGGER,
+					"Job target difficulty {} is above the maximum of {}, pool may be misconfigured",
+					diff,
+					max
+				);
+				return false;
+			}
+		}
+		true
+	}
+
+	/// Pause solvers if the current job is older than the configured max
+	fn check_job_age(&mut self, miner: &CuckooMiner) {
+		let max_age = match self.config.max_job_age_secs {
+			Some(a) => a as i64,
+			None => return,
+		};
+		if self.job_too_old || self.job_received_at == 0 {
+			return;
+		}
+		let age = time::get_time().sec - self.job_received_at;
+		if age > max_age {
+			warn!(
+				LOGGER,
+				"No new job for {}s, job too old, waiting for fresh work", age
+			);
+			self.job_too_old = true;
+			miner.pause_solvers();
+			let mut s_stats = self.stats.write().unwrap();
+			s_stats.mining_stats.job_too_old = true;
+		}
+	}
+
+	/// Average each device's GPS over the last few stat intervals, so the
+	/// rate shown doesn't jump around from graph to graph
+	fn update_device_gps(&mut self, stats: &mut [cuckoo::DeviceStats]) {
+		if self.device_gps.len() != stats.len() {
+			self.device_gps = vec![vec![]; stats.len()];
+		}
+		for (s, history) in stats.iter_mut().zip(self.device_gps.iter_mut()) {
+			if let Some(gps) = s.graphs_per_second() {
+				if !s.has_errored {
+					history.insert(0, gps);
+					history.truncate(DEVICE_GPS_SAMPLES);
+				}
+			}
+			if !history.is_empty() {
+				s.rolling_gps = history.iter().sum::<f64>() / history.len() as f64;
+			}
+		}
+	}
+
+	/// Flag devices whose rolling GPS has fallen below the configured
+	/// fraction of their expected GPS
+	fn check_underperforming(&mut self, stats: &mut [cuckoo::DeviceStats]) {
+		let fraction = self
+			.config
+			.underperforming_gps_fraction
+			.unwrap_or(DEFAULT_UNDERPERFORMING_FRACTION);
+		for (i, s) in stats.iter_mut().enumerate() {
+			let expected = match self.expected_gps.get(i) {
+				Some(Some(e)) => *e,
+				_ => continue,
+			};
+			if s.has_errored || s.rolling_gps == 0.0 {
+				continue;
+			}
+			let rolling_gps = s.rolling_gps;
+			let underperforming = rolling_gps < expected * fraction;
+			if underperforming && !self.underperforming[i] {
+				warn!(
+					LOGGER,
+					"Mining: Plugin {} - Device {} ({}) is underperforming at {:.4} gps, expected {:.4}",
+					i,
+					s.device_id,
+					s.get_device_name(),
+					rolling_gps,
+					expected
+				);
+			} else if !underperforming && self.underperforming[i] {
+				info!(
+					LOGGER,
+					"Mining: Plugin {} - Device {} ({}) is back up to {:.4} gps",
+					i,
+					s.device_id,
+					s.get_device_name(),
+					rolling_gps
+				);
+			}
+			self.underperforming[i] = underperforming;
+			s.underperforming = underperforming;
+		}
+	}
+
+	/// Escalate critical devices that have errored, or stopped completing
+	/// graphs while there's work for them
+	fn check_critical(&mut self, stats: &[cuckoo::DeviceStats], paused: bool) {
+		let now = time::get_time().sec;
+		let stall_secs = self
+			.config
+			.critical_stall_secs
+			.unwrap_or(DEFAULT_CRITICAL_STALL_SECS) as i64;
+		let idle = paused || self.job_too_old || self.current_height == 0;
+		for (i, s) in stats.iter().enumerate() {
+			if !self.critical.get(i).cloned().unwrap_or(false) {
+				continue;
+			}
+			let progress = &mut self.graph_progress[i];
+			if s.iterations != progress.0 || idle || s.paused {
+				*progress = (s.iterations, now);
+			}
+			let stalled = now - progress.1 > stall_secs;
+			let down = s.has_errored || stalled;
+			if down && !self.critical_down[i] {
+				let reason = if s.has_errored {
+					s.get_error_reason()
+				} else {
+					format!("no graphs completed in {}s", now - progress.1)
+				};
+				crit!(
+					LOGGER,
+					"CRITICAL DEVICE DOWN: Plugin {} - Device {} ({}): {}",
+					i,
+					s.device_id,
+					s.get_device_name(),
+					reason
+				);
+			} else if !down && self.critical_down[i] {
+				warn!(
+					LOGGER,
+					"Critical device back up: Plugin {} - Device {} ({})",
+					i,
+					s.device_id,
+					s.get_device_name()
+				);
+			}
+			self.critical_down[i] = down;
+		}
+	}
+
+	fn output_job_stats(&mut self, mut stats: Vec<cuckoo::DeviceStats>, paused: bool) {
+		self.update_device_gps(&mut stats);
+		self.check_underperforming(&mut stats);
+		self.check_critical(&stats, paused);
 		let mut i = 0;
 		for s in stats.clone() {
 			let last_solution_time_secs = s.last_solution_time as f64 / 1_000_000_000.0;
-			let last_hashes_per_sec = 1.0 / last_solution_time_secs;
+			let last_hashes_per_sec = s.average_graphs_per_second().unwrap_or(0.0);
 			let status = if s.has_errored { "ERRORED" } else { "OK" };
 			if !s.has_errored {
 				debug!(
@@ -151,8 +626,35 @@ impl Controller {
 					last_hashes_per_sec,
 					s.iterations
 				);
-				if last_hashes_per_sec.is_finite() {
-					sps_total += last_hashes_per_sec;
+				if s.edges_remaining > 0 {
+					debug!(
+						LOGGER,
+						"Mining: Plugin {} - Device {} edges remaining after trimming: {}",
+						i,
+						s.device_id,
+						s.edges_remaining
+					);
+				}
+				if s.siphash_key_cache_hits > 0 {
+					debug!(
+						LOGGER,
+						"Mining: Plugin {} - Device {} siphash key cache hits: {}",
+						i,
+						s.device_id,
+						s.siphash_key_cache_hits
+					);
+				}
+				if s.temperature_c > 0.0 || s.power_w > 0.0 {
+					debug!(
+						LOGGER,
+						"Mining: Plugin {} - Device {} temperature: {:.0}C, power: {:.0}W, fan: {}%, memory used: {}MB",
+						i,
+						s.device_id,
+						s.temperature_c,
+						s.power_w,
+						s.fan_percent,
+						s.memory_used_mb
+					);
 				}
 			} else {
 				debug!(
@@ -166,17 +668,134 @@ impl Controller {
 			}
 			i += 1;
 		}
+		// devices that haven't completed a graph yet don't count towards the total
+		let sps_total = cuckoo::DeviceStats::combined_graphs_per_second(&stats);
+		if let Some(ref mut series) = self.stats_series {
+			if let Err(e) = series.append(time::get_time().sec as u64, &stats) {
+				warn!(LOGGER, "Failed to write stats series: {}", e);
+			}
+		}
+
+		let summary_due = self.summary_due();
+		let mut s_stats = self.stats.write().unwrap();
+		if stats.iter().any(|s| s.graphs_per_second().is_some()) {
+			s_stats.mining_stats.add_combined_gps(sps_total);
+		}
 		info!(
 			LOGGER,
-			"Mining: Cucka*oo* at {} gps (graphs per second)", sps_total
+			"Mining: Cucka*oo* at {} gps (graphs per second)",
+			s_stats.mining_stats.combined_gps()
 		);
+		if self.num_below_share_floor > 0 {
+			info!(
+				LOGGER,
+				"Held back {} solution(s) below the minimum share difficulty of {}",
+				self.num_below_share_floor,
+				self.config.min_share_difficulty.unwrap_or(0)
+			);
+			self.num_below_share_floor = 0;
+		}
+		let now = time::get_time().sec;
+		let solution_stats = &s_stats.mining_stats.solution_stats;
+		if now >= self.next_share_summary && solution_stats.num_shares_submitted() > 0 {
+			info!(LOGGER, "{}", solution_stats.shares_summary());
+			self.next_share_summary = now + SHARE_SUMMARY_INTERVAL_SECS;
+		}
+		s_stats.mining_stats.target_difficulty = self.current_target_diff;
+		s_stats.mining_stats.block_height = self.current_height;
+		s_stats.mining_stats.device_stats = stats;
+		s_stats.mining_stats.paused = paused;
+		s_stats.mining_stats.critical_devices_down = self
+			.critical_down
+			.iter()
+			.enumerate()
+			.filter(|(_, down)| **down)
+			.map(|(i, _)| i)
+			.collect();
+		if summary_due {
+			let s = &s_stats.mining_stats;
+			info!(
+				LOGGER,
+				"Height {} | Diff {} | {:.2} GPS across {} devices | Shares A/R/S: {}/{}/{} | Uptime {}",
+				s.block_height,
+				s.target_difficulty,
+				sps_total,
+				s.device_stats.len(),
+				s.solution_stats.num_shares_accepted,
+				s.solution_stats.num_rejected,
+				s.solution_stats.num_staled,
+				format_uptime(time::get_time().sec - self.started_at),
+			);
+		}
+	}
 
-		if sps_total.is_finite() {
-			let mut s_stats = self.stats.write().unwrap();
-			s_stats.mining_stats.add_combined_gps(sps_total);
-			s_stats.mining_stats.target_difficulty = self.current_target_diff;
-			s_stats.mining_stats.block_height = self.current_height;
-			s_stats.mining_stats.device_stats = stats;
+	/// Whether a headless summary line is due, scheduling the next one if so
+	fn summary_due(&mut self) -> bool {
+		let interval = match self.config.headless_summary_secs {
+			Some(s) if !self.config.run_tui => s as i64,
+			_ => return false,
+		};
+		let now = time::get_time().sec;
+		if now < self.next_summary {
+			return false;
+		}
+		self.next_summary = now + interval;
+		true
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use plugin::{SolverSolutions, PROOFSIZE};
+
+	#[test]
+	fn test_share_floor() {
+		let stats = Arc::new(RwLock::new(stats::Stats::default()));
+		let mut c = Controller::new(config::MinerConfig::default(), stats).unwrap();
+		// a proof of unscaled difficulty 8455 at 29 edge bits
+		let mut sol = Solution::default();
+		for (i, n) in sol.proof.iter_mut().enumerate() {
+			*n = 4021 + i as u64 * 1000;
 		}
+		assert!(!c.below_share_floor(29, &sol));
+		c.config.min_share_difficulty = Some(8455);
+		assert!(!c.below_share_floor(29, &sol));
+		c.config.min_share_difficulty = Some(9000);
+		assert!(c.below_share_floor(29, &sol));
+		// the job's target still applies when above the floor
+		c.config.min_share_difficulty = Some(1);
+		c.current_target_diff = 9000;
+		assert!(c.below_share_floor(29, &sol));
+	}
+
+	#[test]
+	fn test_sorted_solutions() {
+		let batch = |instance: u32, difficulties: &[u64]| {
+			let mut ss = FoundSolutions {
+				solutions: SolverSolutions {
+					num_sols: difficulties.len() as u32,
+					..SolverSolutions::default()
+				},
+				instance,
+				..FoundSolutions::default()
+			};
+			for (i, d) in difficulties.iter().enumerate() {
+				for (j, n) in ss.sols[i].proof.iter_mut().enumerate() {
+					*n = j as u64 + 1;
+				}
+				ss.sols[i].nonce = *d;
+				ss.difficulties[i] = *d;
+			}
+			ss
+		};
+		let mut batches = vec![batch(0, &[5, 40]), batch(1, &[12, 90, 1])];
+		// a malformed solution is left out, whatever its difficulty
+		batches[1].sols[1].proof = [0; PROOFSIZE];
+		let sols = sorted_solutions(&batches);
+		let order: Vec<(u32, u64)> = sols.iter().map(|(ss, _, d)| (ss.instance, *d)).collect();
+		assert_eq!(order, vec![(0, 40), (1, 12), (0, 5), (1, 1)]);
+		// each solution keeps its own nonce
+		assert!(sols.iter().all(|(_, sol, d)| sol.nonce == *d));
 	}
 }
@@ -0,0 +1,65 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reloading the plugin configs from the config file on SIGHUP, so device
+//! params can be changed without restarting grin-miner
+
+use config::{self, GlobalConfig};
+use cuckoo::PluginConfig;
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use types;
+use util::LOGGER;
+
+/// Re-read the plugin configs from the config file on each SIGHUP, and
+/// send them to the mining controller to apply
+pub fn spawn_handler(
+	config_path: PathBuf,
+	miner_tx: mpsc::Sender<types::MinerMessage>,
+) -> Result<(), io::Error> {
+	let mut signals = Signals::new([SIGHUP])?;
+	thread::Builder::new()
+		.name("config_reload".to_string())
+		.spawn(move || {
+			for _ in signals.forever() {
+				info!(
+					LOGGER,
+					"SIGHUP received, reloading plugin configs from {}",
+					config_path.display()
+				);
+				match read_plugin_configs(&config_path) {
+					Ok(configs) => {
+						let _ = miner_tx.send(types::MinerMessage::ReloadPlugins(configs));
+					}
+					Err(e) => warn!(LOGGER, "Not reloading plugin configs: {}", e),
+				}
+			}
+		})?;
+	Ok(())
+}
+
+fn read_plugin_configs(path: &Path) -> Result<Vec<PluginConfig>, String> {
+	let path = path
+		.to_str()
+		.ok_or_else(|| "config file path isn't valid unicode".to_string())?;
+	let global_config = GlobalConfig::new(Some(path)).map_err(|e| e.to_string())?;
+	let members = global_config
+		.members
+		.ok_or_else(|| "config file has no settings".to_string())?;
+	config::read_miner_configs(&members.mining).map_err(|e| format!("{:?}", e))
+}
@@ -17,20 +17,43 @@
 
 /// Struct to return relevant information about the mining process
 /// back to interested callers (such as the TUI)
-use plugin;
+use cuckoo;
+use serde::{Serialize, Serializer};
+use serde_json::{self, Value};
+use time;
 
-#[derive(Clone)]
+/// Raw protocol messages kept for debugging are cut off at this length
+const MAX_RAW_MESSAGE_LEN: usize = 2048;
+
+#[derive(Clone, Serialize)]
 pub struct SolutionStats {
 	/// total solutions found
+	#[serde(rename = "solutions_found")]
 	pub num_solutions_found: u32,
-	/// total shares accepted
+	/// total shares accepted, including the ones that found a block
+	#[serde(rename = "shares_accepted")]
 	pub num_shares_accepted: u32,
+	/// total computed difficulty of the shares accepted
+	pub accepted_difficulty: u64,
 	/// total solutions rejected
+	#[serde(rename = "rejected")]
 	pub num_rejected: u32,
 	/// total solutions staled
+	#[serde(rename = "stale")]
 	pub num_staled: u32,
-	/// total blocks found
+	/// accepted shares that also found a block, a subset of
+	/// num_shares_accepted
+	#[serde(rename = "blocks_found")]
 	pub num_blocks_found: u32,
+	/// solutions dropped as their proof was malformed
+	#[serde(rename = "malformed")]
+	pub num_malformed: u32,
+	/// solutions dropped as their proof failed local verification
+	#[serde(rename = "invalid")]
+	pub num_invalid: u32,
+	/// solutions found for each recent job, most recent job first
+	#[serde(skip)]
+	solutions_per_job: Vec<(u64, u32)>,
 }
 
 impl Default for SolutionStats {
@@ -38,25 +61,127 @@ impl Default for SolutionStats {
 		SolutionStats {
 			num_solutions_found: 0,
 			num_shares_accepted: 0,
+			accepted_difficulty: 0,
 			num_rejected: 0,
 			num_staled: 0,
 			num_blocks_found: 0,
+			num_malformed: 0,
+			num_invalid: 0,
+			solutions_per_job: vec![],
+		}
+	}
+}
+
+impl SolutionStats {
+	/// start counting solutions for a new job
+	pub fn start_job(&mut self, job_id: u64) {
+		if self.solutions_per_job.iter().any(|(id, _)| *id == job_id) {
+			return;
+		}
+		self.solutions_per_job.insert(0, (job_id, 0));
+		self.solutions_per_job.truncate(50);
+	}
+
+	/// record a share accepted by the server. A share that found a block is
+	/// still an accepted share, and counts towards both totals
+	pub fn share_accepted(&mut self, difficulty: u64, block_found: bool) {
+		self.num_shares_accepted += 1;
+		self.accepted_difficulty += difficulty;
+		if block_found {
+			self.num_blocks_found += 1;
+		}
+	}
+
+	/// shares the server has responded to, whether accepted, rejected or
+	/// stale
+	pub fn num_shares_submitted(&self) -> u32 {
+		self.num_shares_accepted + self.num_rejected + self.num_staled
+	}
+
+	/// fraction of submitted shares the server accepted, 0 before any
+	/// were submitted
+	pub fn acceptance_rate(&self) -> f64 {
+		match self.num_shares_submitted() {
+			0 => 0.0,
+			n => self.num_shares_accepted as f64 / n as f64,
+		}
+	}
+
+	/// one line summary of the shares submitted, for the logs
+	pub fn shares_summary(&self) -> String {
+		format!(
+			"Shares: {} submitted, {} accepted ({:.1}%), {} rejected, {} stale",
+			self.num_shares_submitted(),
+			self.num_shares_accepted,
+			self.acceptance_rate() * 100.0,
+			self.num_rejected,
+			self.num_staled
+		)
+	}
+
+	/// accepted shares that didn't find a block
+	pub fn num_shares_accepted_without_block(&self) -> u32 {
+		self.num_shares_accepted
+			.saturating_sub(self.num_blocks_found)
+	}
+
+	/// record solutions found for the given job
+	pub fn add_job_solutions(&mut self, job_id: u64, num_sols: u32) {
+		match self
+			.solutions_per_job
+			.iter_mut()
+			.find(|(id, _)| *id == job_id)
+		{
+			Some((_, count)) => *count += num_sols,
+			None => {
+				self.start_job(job_id);
+				self.solutions_per_job[0].1 += num_sols;
+			}
 		}
 	}
+
+	/// number of solutions found for the given job
+	pub fn job_solutions(&self, job_id: u64) -> u32 {
+		self.solutions_per_job
+			.iter()
+			.find(|(id, _)| *id == job_id)
+			.map_or(0, |(_, count)| *count)
+	}
+
+	/// solutions found per recent job, most recent job first
+	pub fn solutions_per_job(&self) -> &[(u64, u32)] {
+		&self.solutions_per_job
+	}
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize)]
 pub struct MiningStats {
 	/// combined graphs per second
+	#[serde(skip)]
 	combined_gps: Vec<f64>,
 	/// what block height we're mining at
 	pub block_height: u64,
 	/// current target for share difficulty we're working on
 	pub target_difficulty: u64,
 	/// solution statistics
+	#[serde(flatten)]
 	pub solution_stats: SolutionStats,
 	/// Individual device status from Cuckoo-Miner
-	pub device_stats: Vec<plugin::SolverStats>,
+	#[serde(rename = "devices", serialize_with = "serialize_devices")]
+	pub device_stats: Vec<cuckoo::DeviceStats>,
+	/// whether mining is paused because no new job arrived in time
+	pub job_too_old: bool,
+	/// whether mining is paused by the user or a refused job
+	#[serde(skip)]
+	pub paused: bool,
+	/// critical devices currently down
+	pub critical_devices_down: Vec<usize>,
+	/// plugin name and build info of each device
+	pub plugin_build_info: Vec<String>,
+	/// seconds since mining started
+	pub uptime_secs: u64,
+	/// graphs attempted by all devices since mining started
+	pub total_graphs: u64,
 }
 
 impl Default for MiningStats {
@@ -67,16 +192,51 @@ impl Default for MiningStats {
 			target_difficulty: 0,
 			solution_stats: SolutionStats::default(),
 			device_stats: vec![],
+			job_too_old: false,
+			paused: false,
+			critical_devices_down: vec![],
+			plugin_build_info: vec![],
+			uptime_secs: 0,
+			total_graphs: 0,
 		}
 	}
 }
 
 impl MiningStats {
+	/// one line summary of the mining session so far
+	pub fn session_summary(&self) -> String {
+		format!(
+			"Uptime: {}h{:02}m, {} graphs",
+			self.uptime_secs / 3600,
+			(self.uptime_secs / 60) % 60,
+			self.total_graphs
+		)
+	}
+
 	pub fn add_combined_gps(&mut self, val: f64) {
 		self.combined_gps.insert(0, val);
 		self.combined_gps.truncate(50);
 	}
 
+	/// Estimated seconds between shares at the current combined rate, going
+	/// by the graph size of the first working device. Shares are checked
+	/// unscaled by grin's stratum server
+	pub fn expected_secs_per_share(&self) -> Option<f64> {
+		let edge_bits = self
+			.device_stats
+			.iter()
+			.find(|s| !s.has_errored && s.edge_bits > 0)?
+			.edge_bits;
+		cuckoo::expected_secs_per_share(
+			self.combined_gps(),
+			edge_bits as u8,
+			self.block_height,
+			self.target_difficulty,
+			cuckoo::DifficultyScaling::Unscaled,
+			0,
+		)
+	}
+
 	pub fn combined_gps(&self) -> f64 {
 		if self.combined_gps.is_empty() {
 			0.0
@@ -87,37 +247,140 @@ impl MiningStats {
 	}
 }
 
-#[derive(Clone)]
+/// Lifecycle of the connection to the stratum server
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub enum ConnectionState {
+	/// No connection to the server
+	#[default]
+	Disconnected,
+	/// Trying to establish a connection
+	Connecting,
+	/// Connection established, not yet logged in or sent work
+	Connected,
+	/// Login sent, awaiting the server's response
+	Authenticating,
+	/// Logged in (if required) and receiving jobs
+	Ready,
+}
+
+#[derive(Clone, Serialize)]
 pub struct ClientStats {
 	/// Server we're connected to
 	pub server_url: String,
-	/// whether we're connected
-	pub connected: bool,
+	/// State of the connection to the server
+	pub connection_state: ConnectionState,
 	/// Connection status
 	pub connection_status: String,
 	/// Last message sent to server
 	pub last_message_sent: String,
 	/// Last response/command received from server
 	pub last_message_received: String,
+	/// Last raw JSON line sent to the server, for debugging
+	#[serde(skip)]
+	pub last_raw_message_sent: String,
+	/// Last raw JSON line received from the server, for debugging
+	#[serde(skip)]
+	pub last_raw_message_received: String,
+	/// How long a lost connection keeps being displayed as ready, so brief
+	/// reconnects don't flash up as disconnected
+	#[serde(skip)]
+	pub disconnect_grace_secs: u64,
+	/// Attempts to send a share that failed at the transport level,
+	/// including ones retried successfully
+	#[serde(skip)]
+	pub submit_failures: u64,
+	/// When the server last accepted a share, in seconds, 0 if it hasn't
+	#[serde(skip)]
+	pub last_share_accepted_at: i64,
+	/// When the connection last stopped being ready, in seconds
+	#[serde(skip)]
+	last_ready_at: i64,
+	/// Connection status as of when the connection was last ready
+	#[serde(skip)]
+	last_ready_status: String,
 }
 
 impl Default for ClientStats {
 	fn default() -> ClientStats {
 		ClientStats {
 			server_url: "".to_string(),
-			connected: false,
+			connection_state: ConnectionState::default(),
 			connection_status: "Connection Status: Starting".to_string(),
 			last_message_sent: "Last Message Sent: None".to_string(),
 			last_message_received: "Last Message Received: None".to_string(),
+			last_raw_message_sent: "".to_string(),
+			last_raw_message_received: "".to_string(),
+			disconnect_grace_secs: 0,
+			submit_failures: 0,
+			last_share_accepted_at: 0,
+			last_ready_at: 0,
+			last_ready_status: "".to_string(),
 		}
 	}
 }
 
-#[derive(Clone)]
+impl ClientStats {
+	/// Update the connection state, remembering when it was last ready
+	pub fn set_connection_state(&mut self, state: ConnectionState) {
+		if self.connection_state == ConnectionState::Ready && state != ConnectionState::Ready {
+			self.last_ready_at = time::get_time().sec;
+			self.last_ready_status = self.connection_status.clone();
+		}
+		self.connection_state = state;
+	}
+
+	/// Connection state and status to display, still showing a lost
+	/// connection as ready until the grace period is up
+	pub fn displayed_connection(&self) -> (ConnectionState, String) {
+		let lost_for = time::get_time().sec - self.last_ready_at;
+		if self.connection_state != ConnectionState::Ready
+			&& self.last_ready_at != 0
+			&& lost_for < self.disconnect_grace_secs as i64
+		{
+			(ConnectionState::Ready, self.last_ready_status.clone())
+		} else {
+			(self.connection_state, self.connection_status.clone())
+		}
+	}
+
+	/// Seconds since the server last accepted a share, if it has
+	pub fn secs_since_share_accepted(&self) -> Option<i64> {
+		match self.last_share_accepted_at {
+			0 => None,
+			t => Some(time::get_time().sec - t),
+		}
+	}
+
+	/// Record a raw line sent to the server
+	pub fn set_raw_message_sent(&mut self, message: &str) {
+		self.last_raw_message_sent = truncate_raw_message(message);
+	}
+
+	/// Record a raw line received from the server
+	pub fn set_raw_message_received(&mut self, message: &str) {
+		self.last_raw_message_received = truncate_raw_message(message);
+	}
+}
+
+fn truncate_raw_message(message: &str) -> String {
+	let message = message.trim_end();
+	if message.len() <= MAX_RAW_MESSAGE_LEN {
+		return message.to_string();
+	}
+	let mut end = MAX_RAW_MESSAGE_LEN;
+	while !message.is_char_boundary(end) {
+		end -= 1;
+	}
+	format!("{}...", &message[..end])
+}
+
+#[derive(Clone, Serialize)]
 pub struct Stats {
 	/// Client/networking stats
+	#[serde(rename = "client")]
 	pub client_stats: ClientStats,
 	/// Mining stats
+	#[serde(rename = "mining", serialize_with = "serialize_mining")]
 	pub mining_stats: MiningStats,
 }
 
@@ -129,3 +392,179 @@ impl Default for Stats {
 		}
 	}
 }
+
+impl Stats {
+	/// Snapshot of the stats as JSON, for external consumers
+	pub fn to_json(&self) -> Value {
+		serde_json::to_value(self).unwrap_or(Value::Null)
+	}
+}
+
+/// Serializable view of a device's stats, with its names and error
+/// decoded from the fixed size buffers shared with the plugins
+#[derive(Serialize)]
+pub struct SolverStatsView {
+	pub plugin: String,
+	pub device_id: u32,
+	pub device_name: String,
+	pub edge_bits: u32,
+	pub errored: bool,
+	pub underperforming: bool,
+	pub error_reason: String,
+	pub iterations: u32,
+	pub last_solution_time: u64,
+	pub rolling_gps: f64,
+	pub edges_remaining: u64,
+	pub siphash_key_cache_hits: u64,
+	pub temperature_c: f32,
+	pub power_w: f32,
+	pub fan_percent: u32,
+	pub memory_used_mb: u64,
+	pub uptime_secs: u64,
+	pub restarts: u32,
+	pub graphs_since_share: u64,
+	pub paused: bool,
+	pub graphs_per_second: f64,
+}
+
+impl From<&cuckoo::DeviceStats> for SolverStatsView {
+	fn from(s: &cuckoo::DeviceStats) -> SolverStatsView {
+		SolverStatsView {
+			plugin: s.get_plugin_name(),
+			device_id: s.device_id,
+			device_name: s.get_device_name(),
+			edge_bits: s.edge_bits,
+			errored: s.has_errored,
+			underperforming: s.underperforming,
+			error_reason: s.get_error_reason(),
+			iterations: s.iterations,
+			last_solution_time: s.last_solution_time,
+			rolling_gps: s.rolling_gps,
+			edges_remaining: s.edges_remaining,
+			siphash_key_cache_hits: s.siphash_key_cache_hits,
+			temperature_c: s.temperature_c,
+			power_w: s.power_w,
+			fan_percent: s.fan_percent,
+			memory_used_mb: s.memory_used_mb,
+			uptime_secs: s.uptime_secs(),
+			restarts: s.restarts,
+			graphs_since_share: s.graphs_since_share,
+			paused: s.paused,
+			graphs_per_second: s.graphs_per_second().unwrap_or(0.0),
+		}
+	}
+}
+
+fn serialize_devices<S>(devices: &[cuckoo::DeviceStats], serializer: S) -> Result<S::Ok, S::Error>
+where
+	S: Serializer,
+{
+	let views: Vec<SolverStatsView> = devices.iter().map(SolverStatsView::from).collect();
+	views.serialize(serializer)
+}
+
+/// Mining stats along with the figures computed from them
+#[derive(Serialize)]
+struct MiningStatsView<'a> {
+	#[serde(flatten)]
+	stats: &'a MiningStats,
+	combined_gps: f64,
+	expected_secs_per_share: Option<f64>,
+	acceptance_rate: f64,
+	shares_accepted_without_block: u32,
+}
+
+fn serialize_mining<S>(stats: &MiningStats, serializer: S) -> Result<S::Ok, S::Error>
+where
+	S: Serializer,
+{
+	MiningStatsView {
+		stats,
+		combined_gps: stats.combined_gps(),
+		expected_secs_per_share: stats.expected_secs_per_share(),
+		acceptance_rate: stats.solution_stats.acceptance_rate(),
+		shares_accepted_without_block: stats.solution_stats.num_shares_accepted_without_block(),
+	}
+	.serialize(serializer)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn keys(value: &Value) -> Vec<String> {
+		let mut keys: Vec<String> = value.as_object().unwrap().keys().cloned().collect();
+		keys.sort();
+		keys
+	}
+
+	#[test]
+	fn test_to_json_keys() {
+		let mut stats = Stats::default();
+		stats.mining_stats.device_stats = vec![cuckoo::DeviceStats::default()];
+		let json = stats.to_json();
+		assert_eq!(keys(&json), vec!["client", "mining"]);
+		assert_eq!(
+			keys(&json["client"]),
+			vec![
+				"connection_state",
+				"connection_status",
+				"last_message_received",
+				"last_message_sent",
+				"server_url",
+			]
+		);
+		assert_eq!(
+			keys(&json["mining"]),
+			vec![
+				"acceptance_rate",
+				"accepted_difficulty",
+				"block_height",
+				"blocks_found",
+				"combined_gps",
+				"critical_devices_down",
+				"devices",
+				"expected_secs_per_share",
+				"invalid",
+				"job_too_old",
+				"malformed",
+				"plugin_build_info",
+				"rejected",
+				"shares_accepted",
+				"shares_accepted_without_block",
+				"solutions_found",
+				"stale",
+				"target_difficulty",
+				"total_graphs",
+				"uptime_secs",
+			]
+		);
+		assert_eq!(
+			keys(&json["mining"]["devices"][0]),
+			vec![
+				"device_id",
+				"device_name",
+				"edge_bits",
+				"edges_remaining",
+				"error_reason",
+				"errored",
+				"fan_percent",
+				"graphs_per_second",
+				"graphs_since_share",
+				"iterations",
+				"last_solution_time",
+				"memory_used_mb",
+				"paused",
+				"plugin",
+				"power_w",
+				"restarts",
+				"rolling_gps",
+				"siphash_key_cache_hits",
+				"temperature_c",
+				"underperforming",
+				"uptime_secs",
+			]
+		);
+		assert_eq!(json["client"]["connection_state"], "Disconnected");
+	}
+}
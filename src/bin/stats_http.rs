@@ -0,0 +1,141 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Minimal HTTP server serving the current stats as JSON at /stats, and
+//! as Prometheus metrics at /metrics in builds with the metrics feature,
+//! for external monitoring
+
+#[cfg(feature = "metrics")]
+use metrics;
+use stats;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use util::LOGGER;
+
+/// Seconds to wait for a client to send its request
+const READ_TIMEOUT_SECS: u64 = 5;
+
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+pub struct StatsServer {
+	addr: String,
+	stats: Arc<RwLock<stats::Stats>>,
+}
+
+impl StatsServer {
+	pub fn new(addr: &str, stats: Arc<RwLock<stats::Stats>>) -> StatsServer {
+		StatsServer {
+			addr: addr.to_string(),
+			stats,
+		}
+	}
+
+	/// Listen for and serve stats requests, one at a time
+	pub fn run(&self) -> Result<(), io::Error> {
+		let listener = TcpListener::bind(&self.addr)?;
+		info!(LOGGER, "Stats HTTP server listening on {}", self.addr);
+		for stream in listener.incoming() {
+			match stream {
+				Ok(s) => {
+					if let Err(e) = self.handle_connection(s) {
+						debug!(LOGGER, "Stats HTTP connection closed: {:?}", e);
+					}
+				}
+				Err(e) => warn!(LOGGER, "Failed to accept stats HTTP connection: {:?}", e),
+			}
+		}
+		Ok(())
+	}
+
+	fn handle_connection(&self, stream: TcpStream) -> Result<(), io::Error> {
+		stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SECS)))?;
+		let mut writer = stream.try_clone()?;
+		let mut reader = BufReader::new(stream);
+		let mut request_line = String::new();
+		reader.read_line(&mut request_line)?;
+		// skip the headers, nothing in them is needed
+		let mut line = String::new();
+		while reader.read_line(&mut line)? > 0 && !line.trim().is_empty() {
+			line.clear();
+		}
+		let (status, content_type, body) = match self.stats.read() {
+			Ok(stats) => route(&request_line, &stats),
+			Err(_) => (
+				"500 Internal Server Error",
+				JSON_CONTENT_TYPE,
+				String::new(),
+			),
+		};
+		write!(
+			writer,
+			"HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+			status,
+			content_type,
+			body.len(),
+			body
+		)?;
+		writer.flush()
+	}
+}
+
+fn json(stats: &stats::Stats) -> String {
+	stats.to_json().to_string()
+}
+
+/// Status line, content type and body for a request
+fn route(request_line: &str, stats: &stats::Stats) -> (&'static str, &'static str, String) {
+	let mut parts = request_line.split_whitespace();
+	let method = parts.next().unwrap_or("");
+	let path = parts.next().unwrap_or("");
+	let (content_type, render): (&'static str, fn(&stats::Stats) -> String) = match path {
+		"/stats" => (JSON_CONTENT_TYPE, json),
+		#[cfg(feature = "metrics")]
+		"/metrics" => (metrics::CONTENT_TYPE, metrics::render),
+		_ => return ("404 Not Found", JSON_CONTENT_TYPE, String::new()),
+	};
+	if method != "GET" {
+		return ("405 Method Not Allowed", content_type, String::new());
+	}
+	// nothing to report until the mining controller's first stats update
+	if stats.mining_stats.device_stats.is_empty() {
+		return ("503 Service Unavailable", content_type, String::new());
+	}
+	("200 OK", content_type, render(stats))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use cuckoo::DeviceStats;
+
+	#[test]
+	fn test_route() {
+		let mut stats = stats::Stats::default();
+		assert_eq!(
+			route("GET /stats HTTP/1.1", &stats).0,
+			"503 Service Unavailable"
+		);
+		stats.mining_stats.device_stats = vec![DeviceStats::default()];
+		let (status, _, body) = route("GET /stats HTTP/1.1", &stats);
+		assert_eq!(status, "200 OK");
+		assert_eq!(body, stats.to_json().to_string());
+		assert_eq!(route("GET / HTTP/1.1", &stats).0, "404 Not Found");
+		assert_eq!(
+			route("POST /stats HTTP/1.1", &stats).0,
+			"405 Method Not Allowed"
+		);
+	}
+}
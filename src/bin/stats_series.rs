@@ -0,0 +1,208 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compact binary time series of solver stats, one fixed size record per
+//! device per stat interval, for long term performance analysis without
+//! a metrics stack. The file is rotated to `<file>.1` once it reaches its
+//! maximum size
+
+use cuckoo::DeviceStats;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Identifies a stats series file, and its record format version
+const MAGIC: &[u8; 8] = b"GMSTATS1";
+
+/// Size of an encoded record
+const RECORD_LEN: usize = 28;
+
+/// One sample of a device's stats
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatsRecord {
+	/// Seconds since the unix epoch
+	pub timestamp: u64,
+	pub device_id: u32,
+	pub edge_bits: u32,
+	/// Graphs per second
+	pub gps: f64,
+	/// Device temperature in degrees Celsius, 0 if not reported
+	pub temperature_c: f32,
+}
+
+impl StatsRecord {
+	/// Sample the given device stats
+	pub fn from_stats(timestamp: u64, s: &DeviceStats) -> StatsRecord {
+		StatsRecord {
+			timestamp,
+			device_id: s.device_id,
+			edge_bits: s.edge_bits,
+			gps: s.graphs_per_second().unwrap_or(0.0),
+			temperature_c: s.temperature_c,
+		}
+	}
+
+	/// Little endian encoding
+	pub fn to_bytes(&self) -> [u8; RECORD_LEN] {
+		let mut b = [0u8; RECORD_LEN];
+		b[0..8].copy_from_slice(&self.timestamp.to_le_bytes());
+		b[8..12].copy_from_slice(&self.device_id.to_le_bytes());
+		b[12..16].copy_from_slice(&self.edge_bits.to_le_bytes());
+		b[16..24].copy_from_slice(&self.gps.to_bits().to_le_bytes());
+		b[24..28].copy_from_slice(&self.temperature_c.to_bits().to_le_bytes());
+		b
+	}
+
+	pub fn from_bytes(b: &[u8; RECORD_LEN]) -> StatsRecord {
+		let mut u64_buf = [0u8; 8];
+		let mut u32_buf = [0u8; 4];
+		u64_buf.copy_from_slice(&b[0..8]);
+		let timestamp = u64::from_le_bytes(u64_buf);
+		u32_buf.copy_from_slice(&b[8..12]);
+		let device_id = u32::from_le_bytes(u32_buf);
+		u32_buf.copy_from_slice(&b[12..16]);
+		let edge_bits = u32::from_le_bytes(u32_buf);
+		u64_buf.copy_from_slice(&b[16..24]);
+		let gps = f64::from_bits(u64::from_le_bytes(u64_buf));
+		u32_buf.copy_from_slice(&b[24..28]);
+		let temperature_c = f32::from_bits(u32::from_le_bytes(u32_buf));
+		StatsRecord {
+			timestamp,
+			device_id,
+			edge_bits,
+			gps,
+			temperature_c,
+		}
+	}
+}
+
+/// Appends stats records to a rotating file
+pub struct StatsSeriesWriter {
+	path: PathBuf,
+	max_bytes: u64,
+}
+
+impl StatsSeriesWriter {
+	pub fn new(path: &str, max_bytes: u64) -> StatsSeriesWriter {
+		StatsSeriesWriter {
+			path: PathBuf::from(path),
+			max_bytes,
+		}
+	}
+
+	/// Append a record for each device
+	pub fn append(&mut self, timestamp: u64, stats: &[DeviceStats]) -> io::Result<()> {
+		let len = fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+		let len = if len >= self.max_bytes {
+			let mut rotated = self.path.clone().into_os_string();
+			rotated.push(".1");
+			fs::rename(&self.path, rotated)?;
+			0
+		} else {
+			len
+		};
+		let mut buf = vec![];
+		if len == 0 {
+			buf.extend_from_slice(MAGIC);
+		}
+		for s in stats {
+			buf.extend_from_slice(&StatsRecord::from_stats(timestamp, s).to_bytes());
+		}
+		let mut f = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(&self.path)?;
+		f.write_all(&buf)
+	}
+}
+
+/// Read back all records in a stats series file. A partly written last
+/// record is ignored
+pub fn read_records<P: AsRef<Path>>(path: P) -> io::Result<Vec<StatsRecord>> {
+	let mut data = vec![];
+	File::open(path)?.read_to_end(&mut data)?;
+	if data.len() < MAGIC.len() || &data[..MAGIC.len()] != MAGIC {
+		return Err(io::Error::new(
+			ErrorKind::InvalidData,
+			"Not a grin-miner stats series file",
+		));
+	}
+	let mut records = vec![];
+	let mut b = [0u8; RECORD_LEN];
+	for chunk in data[MAGIC.len()..].chunks_exact(RECORD_LEN) {
+		b.copy_from_slice(chunk);
+		records.push(StatsRecord::from_bytes(&b));
+	}
+	Ok(records)
+}
+
+/// Print a stats series file to stdout as CSV
+pub fn dump_csv<P: AsRef<Path>>(path: P) -> io::Result<()> {
+	let records = read_records(path)?;
+	let stdout = io::stdout();
+	let mut out = stdout.lock();
+	writeln!(out, "timestamp,device_id,edge_bits,gps,temperature_c")?;
+	for r in records {
+		writeln!(
+			out,
+			"{},{},{},{},{}",
+			r.timestamp, r.device_id, r.edge_bits, r.gps, r.temperature_c
+		)?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use plugin::SolverStats;
+	use std::env;
+
+	#[test]
+	fn test_stats_series_roundtrip() {
+		let path = env::temp_dir().join(format!("grin-miner-stats-{}", std::process::id()));
+		let path_str = path.to_str().unwrap().to_string();
+		let _ = fs::remove_file(&path);
+
+		let stats = DeviceStats {
+			solver: SolverStats {
+				device_id: 3,
+				edge_bits: 31,
+				temperature_c: 64.5,
+				..SolverStats::default()
+			},
+			..DeviceStats::default()
+		};
+		let mut writer = StatsSeriesWriter::new(&path_str, 1024);
+		writer.append(100, &[stats.clone(), stats.clone()]).unwrap();
+		writer.append(102, &[stats]).unwrap();
+
+		let records = read_records(&path).unwrap();
+		assert_eq!(records.len(), 3);
+		assert_eq!(records[2].timestamp, 102);
+		assert_eq!(records[0].device_id, 3);
+		assert_eq!(records[0].edge_bits, 31);
+		assert_eq!(records[0].temperature_c, 64.5);
+
+		let r = StatsRecord {
+			timestamp: 1,
+			device_id: 2,
+			edge_bits: 29,
+			gps: 1.25,
+			temperature_c: 71.0,
+		};
+		assert_eq!(StatsRecord::from_bytes(&r.to_bytes()), r);
+		let _ = fs::remove_file(&path);
+	}
+}
@@ -20,9 +20,14 @@ pub const VIEW_MINING: &str = "mining_view";
 /// Mining status
 pub const TABLE_MINING_STATUS: &str = "mining_status_table";
 
+/// Debug view
+pub const VIEW_DEBUG: &str = "debug_view";
+
 // Mining View
 /// Version view
 pub const VIEW_VERSION: &str = "version_view";
+/// Plugin build info, in the version view
+pub const TEXT_PLUGIN_BUILD_INFO: &str = "plugin_build_info";
 
 // Menu and root elements
 /// Main menu
@@ -0,0 +1,67 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Raw protocol messages, for diagnosing pool issues
+
+use std::sync::{Arc, RwLock};
+
+use cursive::direction::Orientation;
+use cursive::traits::*;
+use cursive::view::View;
+use cursive::views::{Dialog, LinearLayout, ResizedView, TextView};
+use cursive::Cursive;
+
+use tui::constants::*;
+use tui::types::*;
+
+use stats::Stats;
+
+/// Debug view
+pub struct TUIDebugView;
+
+impl TUIStatusListener for TUIDebugView {
+	/// Create debug view
+	fn create() -> Box<dyn View> {
+		let debug_view = ResizedView::with_full_screen(
+			LinearLayout::new(Orientation::Vertical)
+				.child(TextView::new("Share send failures: 0").with_name("submit_failures"))
+				.child(
+					Dialog::around(TextView::new("None").with_name("raw_message_sent"))
+						.title("Last Raw Message Sent"),
+				)
+				.child(
+					Dialog::around(TextView::new("None").with_name("raw_message_received"))
+						.title("Last Raw Message Received"),
+				),
+		);
+		Box::new(debug_view.with_name(VIEW_DEBUG))
+	}
+
+	/// update
+	fn update(c: &mut Cursive, stats: Arc<RwLock<Stats>>) {
+		let client_stats = stats.read().unwrap().client_stats.clone();
+		c.call_on_name("submit_failures", |t: &mut TextView| {
+			t.set_content(format!(
+				"Share send failures: {}",
+				client_stats.submit_failures
+			));
+		});
+		c.call_on_name("raw_message_sent", |t: &mut TextView| {
+			t.set_content(client_stats.last_raw_message_sent.clone());
+		});
+		c.call_on_name("raw_message_received", |t: &mut TextView| {
+			t.set_content(client_stats.last_raw_message_received.clone());
+		});
+	}
+}
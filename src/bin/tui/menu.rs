@@ -30,6 +30,7 @@ use tui::constants::*;
 pub fn create() -> Box<dyn View> {
 	let mut main_menu = SelectView::new().h_align(HAlign::Left).with_name(MAIN_MENU);
 	main_menu.get_mut().add_item("Mining", VIEW_MINING);
+	main_menu.get_mut().add_item("Debug", VIEW_DEBUG);
 	main_menu.get_mut().add_item("Version Info", VIEW_VERSION);
 	let change_view = |s: &mut Cursive, v: &&str| {
 		if *v == "" {
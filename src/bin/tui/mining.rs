@@ -18,7 +18,9 @@ use std::cmp::Ordering;
 use std::sync::{Arc, RwLock};
 
 use cursive::direction::Orientation;
+use cursive::theme::{BaseColor, Color, ColorStyle};
 use cursive::traits::*;
+use cursive::utils::markup::StyledString;
 use cursive::view::View;
 use cursive::views::{Dialog, LinearLayout, ResizedView, StackView, TextView};
 use cursive::Cursive;
@@ -26,7 +28,7 @@ use cursive::Cursive;
 use tui::constants::*;
 use tui::types::*;
 
-use plugin::SolverStats;
+use cuckoo::DeviceStats;
 use stats;
 use tui::table::{TableView, TableViewItem};
 
@@ -39,6 +41,10 @@ enum MiningDeviceColumn {
 	ErrorStatus,
 	LastGraphTime,
 	GraphsPerSecond,
+	Uptime,
+	Restarts,
+	GraphsSinceShare,
+	Temperature,
 }
 
 impl MiningDeviceColumn {
@@ -51,13 +57,16 @@ impl MiningDeviceColumn {
 			MiningDeviceColumn::ErrorStatus => "Status",
 			MiningDeviceColumn::LastGraphTime => "Last Graph Time",
 			MiningDeviceColumn::GraphsPerSecond => "GPS",
+			MiningDeviceColumn::Uptime => "Uptime",
+			MiningDeviceColumn::Restarts => "Restarts",
+			MiningDeviceColumn::GraphsSinceShare => "Since Share",
+			MiningDeviceColumn::Temperature => "Temp",
 		}
 	}
 }
 
-impl TableViewItem<MiningDeviceColumn> for SolverStats {
+impl TableViewItem<MiningDeviceColumn> for DeviceStats {
 	fn to_column(&self, column: MiningDeviceColumn) -> String {
-		let last_solution_time_secs = self.last_solution_time as f64 / 1_000_000_000.0;
 		match column {
 			MiningDeviceColumn::Plugin => self.get_plugin_name(),
 			MiningDeviceColumn::DeviceId => format!("{}", self.device_id),
@@ -66,35 +75,80 @@ impl TableViewItem<MiningDeviceColumn> for SolverStats {
 			MiningDeviceColumn::ErrorStatus => {
 				if self.has_errored {
 					String::from("Errored")
+				} else if self.paused {
+					String::from("Paused")
+				} else if self.underperforming {
+					String::from("Underperforming")
 				} else {
 					String::from("OK")
 				}
 			}
-			MiningDeviceColumn::LastGraphTime => format!("{}s", last_solution_time_secs),
-			MiningDeviceColumn::GraphsPerSecond => {
-				format!("{:.*}", 4, 1.0 / last_solution_time_secs)
+			// no graph time or rate until the device completes a graph
+			MiningDeviceColumn::LastGraphTime => match self.last_solution_time {
+				0 => String::from("-"),
+				t => format!("{}s", t as f64 / 1_000_000_000.0),
+			},
+			MiningDeviceColumn::GraphsPerSecond => match self.average_graphs_per_second() {
+				Some(gps) => format!("{:.*}", 4, gps),
+				None => String::from("-"),
+			},
+			MiningDeviceColumn::Uptime => {
+				let uptime = self.uptime_secs();
+				format!("{}h{:02}m", uptime / 3600, (uptime / 60) % 60)
+			}
+			MiningDeviceColumn::Restarts => format!("{}", self.restarts),
+			MiningDeviceColumn::GraphsSinceShare => format!("{}", self.graphs_since_share),
+			// only plugins that can read the device's sensors report it
+			MiningDeviceColumn::Temperature => {
+				if self.temperature_c > 0.0 {
+					format!("{:.0}C", self.temperature_c)
+				} else {
+					String::from("-")
+				}
 			}
 		}
 	}
 
+	fn to_column_color(&self, _column: MiningDeviceColumn) -> Option<ColorStyle> {
+		// whole row colored by device health
+		let color = if self.has_errored {
+			BaseColor::Red
+		} else if self.paused || self.underperforming {
+			BaseColor::Yellow
+		} else {
+			BaseColor::Green
+		};
+		Some(ColorStyle::from(Color::Dark(color)))
+	}
+
 	fn cmp(&self, other: &Self, column: MiningDeviceColumn) -> Ordering
 	where
 		Self: Sized,
 	{
-		let last_solution_time_secs_self = self.last_solution_time as f64 / 1_000_000_000.0;
-		let gps_self = 1.0 / last_solution_time_secs_self;
-		let last_solution_time_secs_other = other.last_solution_time as f64 / 1_000_000_000.0;
-		let gps_other = 1.0 / last_solution_time_secs_other;
 		match column {
 			MiningDeviceColumn::Plugin => self.plugin_name.cmp(&other.plugin_name),
 			MiningDeviceColumn::DeviceId => self.device_id.cmp(&other.device_id),
 			MiningDeviceColumn::DeviceName => self.device_name.cmp(&other.device_name),
 			MiningDeviceColumn::EdgeBits => self.edge_bits.cmp(&other.edge_bits),
-			MiningDeviceColumn::ErrorStatus => self.has_errored.cmp(&other.has_errored),
+			MiningDeviceColumn::ErrorStatus => (self.has_errored, self.underperforming)
+				.cmp(&(other.has_errored, other.underperforming)),
 			MiningDeviceColumn::LastGraphTime => {
 				self.last_solution_time.cmp(&other.last_solution_time)
 			}
-			MiningDeviceColumn::GraphsPerSecond => gps_self.partial_cmp(&gps_other).unwrap(),
+			MiningDeviceColumn::GraphsPerSecond => self
+				.average_graphs_per_second()
+				.partial_cmp(&other.average_graphs_per_second())
+				.unwrap_or(Ordering::Equal),
+			// earlier start means longer uptime
+			MiningDeviceColumn::Uptime => other.start_time.cmp(&self.start_time),
+			MiningDeviceColumn::Restarts => self.restarts.cmp(&other.restarts),
+			MiningDeviceColumn::GraphsSinceShare => {
+				self.graphs_since_share.cmp(&other.graphs_since_share)
+			}
+			MiningDeviceColumn::Temperature => self
+				.temperature_c
+				.partial_cmp(&other.temperature_c)
+				.unwrap_or(Ordering::Equal),
 		}
 	}
 }
@@ -105,26 +159,39 @@ pub struct TUIMiningView;
 impl TUIStatusListener for TUIMiningView {
 	/// Create the mining view
 	fn create() -> Box<dyn View> {
-		let table_view = TableView::<SolverStats, MiningDeviceColumn>::new()
+		let table_view = TableView::<DeviceStats, MiningDeviceColumn>::new()
 			.column(MiningDeviceColumn::Plugin, "Plugin", |c| {
-				c.width_percent(20)
+				c.width_percent(14)
 			})
 			.column(MiningDeviceColumn::DeviceId, "Device ID", |c| {
 				c.width_percent(5)
 			})
 			.column(MiningDeviceColumn::DeviceName, "Device Name", |c| {
-				c.width_percent(20)
+				c.width_percent(14)
 			})
 			.column(MiningDeviceColumn::EdgeBits, "Size", |c| c.width_percent(5))
 			.column(MiningDeviceColumn::ErrorStatus, "Status", |c| {
-				c.width_percent(8)
+				c.width_percent(12)
 			})
 			.column(MiningDeviceColumn::LastGraphTime, "Graph Time", |c| {
 				c.width_percent(10)
 			})
 			.column(MiningDeviceColumn::GraphsPerSecond, "GPS", |c| {
-				c.width_percent(10)
-			});
+				c.width_percent(10).ordering(Ordering::Greater)
+			})
+			.column(MiningDeviceColumn::Uptime, "Uptime", |c| c.width_percent(8))
+			.column(MiningDeviceColumn::Restarts, "Restarts", |c| {
+				c.width_percent(6)
+			})
+			.column(MiningDeviceColumn::GraphsSinceShare, "Since Share", |c| {
+				c.width_percent(8)
+			})
+			.column(MiningDeviceColumn::Temperature, "Temp", |c| {
+				c.width_percent(6)
+			})
+			// busiest devices first by default, the table keeps whichever
+			// sort the user picks across updates
+			.default_column(MiningDeviceColumn::GraphsPerSecond);
 
 		let status_view = LinearLayout::new(Orientation::Vertical)
 			.child(LinearLayout::new(Orientation::Horizontal).child(
@@ -142,6 +209,10 @@ impl TUIStatusListener for TUIMiningView {
 				LinearLayout::new(Orientation::Horizontal)
 					.child(TextView::new("  ").with_name("mining_statistics")),
 			)
+			.child(
+				LinearLayout::new(Orientation::Horizontal)
+					.child(TextView::new("  ").with_name("mining_session")),
+			)
 			.child(
 				LinearLayout::new(Orientation::Horizontal)
 					.child(TextView::new("Last Message Sent:  ").with_name("last_message_sent")),
@@ -174,13 +245,28 @@ impl TUIStatusListener for TUIMiningView {
 			(stats.client_stats.clone(), stats.mining_stats.clone())
 		};
 
+		// brief reconnects shouldn't flash up as disconnected
+		let (connection_state, connection_status) = client_stats.displayed_connection();
+		let status_color = match connection_state {
+			stats::ConnectionState::Disconnected => BaseColor::Red,
+			stats::ConnectionState::Ready => BaseColor::Green,
+			_ => BaseColor::Yellow,
+		};
 		c.call_on_name("mining_server_status", |t: &mut TextView| {
-			t.set_content(client_stats.connection_status.clone());
+			t.set_content(StyledString::styled(
+				connection_status,
+				Color::Dark(status_color),
+			));
 		});
 
 		let (basic_mining_status, basic_network_info) = {
-			if client_stats.connected {
-				if mining_stats.combined_gps() == 0.0 {
+			if connection_state == stats::ConnectionState::Ready {
+				if mining_stats.job_too_old {
+					(
+						"Mining Status: Job too old, waiting for fresh work".to_string(),
+						" ".to_string(),
+					)
+				} else if mining_stats.combined_gps() == 0.0 {
 					(
 						"Mining Status: Starting miner and awaiting first graph time..."
 							.to_string(),
@@ -194,10 +280,16 @@ impl TUIStatusListener for TUIMiningView {
 							4,
 							mining_stats.combined_gps()
 						),
-						format!(
-							"Cucka*oo* - Target Share Difficulty {}",
-							mining_stats.target_difficulty.to_string()
-						),
+						match mining_stats.expected_secs_per_share() {
+							Some(secs) => format!(
+								"Cucka*oo* - Target Share Difficulty {} - ~Est. share every {:.0}s",
+								mining_stats.target_difficulty, secs
+							),
+							None => format!(
+								"Cucka*oo* - Target Share Difficulty {}",
+								mining_stats.target_difficulty
+							),
+						},
 					)
 				}
 			} else {
@@ -209,6 +301,17 @@ impl TUIStatusListener for TUIMiningView {
 		};
 
 		// device
+		let basic_mining_status = if mining_stats.critical_devices_down.is_empty() {
+			StyledString::plain(basic_mining_status)
+		} else {
+			StyledString::styled(
+				format!(
+					"{} - CRITICAL DEVICE DOWN: {:?}",
+					basic_mining_status, mining_stats.critical_devices_down
+				),
+				Color::Dark(BaseColor::Red),
+			)
+		};
 		c.call_on_name("mining_status", |t: &mut TextView| {
 			t.set_content(basic_mining_status);
 		});
@@ -216,6 +319,10 @@ impl TUIStatusListener for TUIMiningView {
 			t.set_content(basic_network_info);
 		});
 
+		c.call_on_name("mining_session", |t: &mut TextView| {
+			t.set_content(mining_stats.session_summary());
+		});
+
 		c.call_on_name("last_message_sent", |t: &mut TextView| {
 			t.set_content(client_stats.last_message_sent.clone());
 		});
@@ -224,14 +331,35 @@ impl TUIStatusListener for TUIMiningView {
 		});
 
 		if mining_stats.solution_stats.num_solutions_found > 0 {
-			let sol_stat = format!(
-				"Solutions found: {}. Accepted: {}, Rejected: {}, Stale: {}, Blocks found: {}",
+			let job_sols = match mining_stats.solution_stats.solutions_per_job().first() {
+				Some((_, count)) => *count,
+				None => 0,
+			};
+			let mut sol_stat = format!(
+				"Solutions found: {} ({} this job). Accepted: {} (difficulty {}), Rejected: {}, Stale: {}, Blocks found: {}",
 				mining_stats.solution_stats.num_solutions_found,
+				job_sols,
 				mining_stats.solution_stats.num_shares_accepted,
+				mining_stats.solution_stats.accepted_difficulty,
 				mining_stats.solution_stats.num_rejected,
 				mining_stats.solution_stats.num_staled,
 				mining_stats.solution_stats.num_blocks_found,
 			);
+			if mining_stats.solution_stats.num_malformed > 0 {
+				sol_stat.push_str(&format!(
+					", Malformed: {}",
+					mining_stats.solution_stats.num_malformed
+				));
+			}
+			if mining_stats.solution_stats.num_invalid > 0 {
+				sol_stat.push_str(&format!(
+					", Invalid: {}",
+					mining_stats.solution_stats.num_invalid
+				));
+			}
+			if let Some(secs) = client_stats.secs_since_share_accepted() {
+				sol_stat.push_str(&format!(". Last accepted: {}s ago", secs));
+			}
 			c.call_on_name("mining_statistics", |t: &mut TextView| {
 				t.set_content(sol_stat);
 			});
@@ -239,7 +367,7 @@ impl TUIStatusListener for TUIMiningView {
 
 		let _ = c.call_on_name(
 			TABLE_MINING_STATUS,
-			|t: &mut TableView<SolverStats, MiningDeviceColumn>| {
+			|t: &mut TableView<DeviceStats, MiningDeviceColumn>| {
 				t.set_items(mining_stats.device_stats);
 			},
 		);
@@ -21,6 +21,7 @@
 #![warn(missing_docs)]
 
 pub mod constants;
+pub mod debug;
 pub mod menu;
 pub mod mining;
 pub mod table;
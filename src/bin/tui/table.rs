@@ -81,6 +81,12 @@ where
 	/// specified column from type `H`.
 	fn to_column(&self, column: H) -> String;
 
+	/// Method returning the color to draw the item's specified column in,
+	/// or `None` for the table's default.
+	fn to_column_color(&self, _column: H) -> Option<ColorStyle> {
+		None
+	}
+
 	/// Method comparing two items via their specified column from type `H`.
 	fn cmp(&self, other: &Self, column: H) -> Ordering
 	where
@@ -448,8 +454,9 @@ impl<T: TableViewItem<H>, H: Eq + Hash + Copy + Clone + 'static> TableView<T, H>
 	/// Sets the contained items of the table.
 	///
 	/// The currently active sort order is preserved and will be applied to all
-	/// items.
+	/// items. The selected row is kept where possible.
 	pub fn set_items(&mut self, items: Vec<T>) {
+		let selected_row = self.focus;
 		self.items = items;
 		self.rows_to_items = Vec::with_capacity(self.items.len());
 
@@ -464,7 +471,11 @@ impl<T: TableViewItem<H>, H: Eq + Hash + Copy + Clone + 'static> TableView<T, H>
 		self.scrollbase
 			.set_heights(self.last_size.y.saturating_sub(2), self.rows_to_items.len());
 
-		self.set_selected_row(0);
+		if selected_row < self.rows_to_items.len() {
+			self.set_selected_row(selected_row);
+		} else {
+			self.set_selected_row(0);
+		}
 	}
 
 	/// Sets the contained items of the table.
@@ -631,10 +642,17 @@ impl<T: TableViewItem<H>, H: Eq + Hash + Copy + Clone + 'static> TableView<T, H>
 		}
 	}
 
-	fn draw_item(&self, printer: &Printer, i: usize) {
+	fn draw_item(&self, printer: &Printer, i: usize, focused: bool) {
 		self.draw_columns(printer, "┆ ", |printer, column| {
-			let value = self.items[self.rows_to_items[i]].to_column(column.column);
-			column.draw_row(printer, value.as_str());
+			let item = &self.items[self.rows_to_items[i]];
+			let value = item.to_column(column.column);
+			// the focused row keeps its highlight
+			match item.to_column_color(column.column) {
+				Some(color) if !focused => printer.with_color(color, |printer| {
+					column.draw_row(printer, value.as_str());
+				}),
+				_ => column.draw_row(printer, value.as_str()),
+			}
 		});
 	}
 
@@ -741,7 +759,7 @@ impl<T: TableViewItem<H> + 'static, H: Eq + Hash + Copy + Clone + 'static> View
 			};
 
 			printer.with_color(ColorStyle::from(color), |printer| {
-				self.draw_item(printer, i);
+				self.draw_item(printer, i, i == self.focus);
 			});
 		});
 	}
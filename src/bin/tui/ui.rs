@@ -15,6 +15,7 @@
 //! Basic TUI to better output the overall system status and status
 //! of various subsystems
 
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{mpsc, Arc, RwLock};
 use std::{self, thread};
 use time;
@@ -31,7 +32,7 @@ use cursive::Cursive;
 
 use tui::constants::*;
 use tui::types::*;
-use tui::{menu, mining, version};
+use tui::{debug, menu, mining, version};
 
 use stats;
 
@@ -70,12 +71,14 @@ impl UI {
 
 		// Create UI objects, etc
 		let mining_view = mining::TUIMiningView::create();
+		let debug_view = debug::TUIDebugView::create();
 		let version_view = version::TUIVersionView::create();
 
 		let main_menu = menu::create();
 
 		let root_stack = StackView::new()
 			.layer(version_view)
+			.layer(debug_view)
 			.layer(mining_view)
 			.with_name(ROOT_STACK);
 
@@ -122,6 +125,7 @@ impl UI {
 			match message {
 				UIMessage::UpdateStatus(update) => {
 					mining::TUIMiningView::update(&mut self.cursive, update.clone());
+					debug::TUIDebugView::update(&mut self.cursive, update.clone());
 					version::TUIVersionView::update(&mut self.cursive, update.clone());
 				}
 			}
@@ -160,11 +164,15 @@ impl Controller {
 			ui: UI::new(tx),
 		})
 	}
-	/// Run the controller
-	pub fn run(&mut self, stats: Arc<RwLock<stats::Stats>>) {
+	/// Run the controller, until the UI is closed or quit is set
+	pub fn run(&mut self, stats: Arc<RwLock<stats::Stats>>, quit: Arc<AtomicBool>) {
 		let stat_update_interval = 1;
 		let mut next_stat_update = time::get_time().sec + stat_update_interval;
 		while self.ui.step() {
+			if quit.load(Ordering::Relaxed) {
+				self.ui.stop();
+				return;
+			}
 			if let Some(message) = self.rx.try_iter().next() {
 				match message {
 					ControllerMessage::Shutdown => {
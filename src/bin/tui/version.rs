@@ -39,11 +39,28 @@ impl TUIStatusListener for TUIVersionView {
 			LinearLayout::new(Orientation::Vertical)
 				.child(TextView::new(basic_info))
 				.child(TextView::new(" "))
-				.child(TextView::new(detailed_info)),
+				.child(TextView::new(detailed_info))
+				.child(TextView::new(" "))
+				.child(TextView::new("Plugins:"))
+				.child(TextView::new("").with_name(TEXT_PLUGIN_BUILD_INFO)),
 		);
 		Box::new(basic_status_view.with_name(VIEW_VERSION))
 	}
 
 	/// update
-	fn update(_c: &mut Cursive, _stats: Arc<RwLock<Stats>>) {}
+	fn update(c: &mut Cursive, stats: Arc<RwLock<Stats>>) {
+		let build_info = {
+			let stats = stats.read().unwrap();
+			stats
+				.mining_stats
+				.plugin_build_info
+				.iter()
+				.map(|b| format!(" {}", b))
+				.collect::<Vec<_>>()
+				.join("\n")
+		};
+		c.call_on_name(TEXT_PLUGIN_BUILD_INFO, |t: &mut TextView| {
+			t.set_content(build_info);
+		});
+	}
 }
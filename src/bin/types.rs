@@ -12,16 +12,55 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use cuckoo::PluginConfig;
 use serde_json::Value;
+use std::process;
+
+/// Process exit codes, so supervisors can tell why the miner stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExitCode {
+	/// Shut down cleanly, e.g. on user request
+	CleanShutdown = 0,
+	/// Config file missing or invalid
+	ConfigError = 2,
+	/// Mining plugins couldn't be found or loaded
+	NoPlugins = 3,
+	/// Plugins loaded, but failed to initialize their devices
+	NoDevices = 4,
+	/// The pool rejected our login
+	PoolAuthError = 5,
+	/// The watchdog found the solvers stalled
+	Stalled = 6,
+	/// No job could be had from the pool with --test-login
+	NoJob = 7,
+}
+
+impl ExitCode {
+	/// Exit the process with this code
+	pub fn exit(self) -> ! {
+		process::exit(self as i32)
+	}
+}
 
 /// Types used for stratum
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct JobTemplate {
+	// aliases cover field names used by other pool and node versions,
+	// unknown fields are ignored
 	pub height: u64,
+	#[serde(alias = "jobid", alias = "jobId")]
 	pub job_id: u64,
+	#[serde(alias = "diff")]
 	pub difficulty: u64,
+	#[serde(alias = "prepow")]
 	pub pre_pow: String,
+	/// extra nonce, only sent by some pools
+	#[serde(default, alias = "extranonce")]
+	pub xn: Option<String>,
+	/// whether previous jobs should be discarded, only sent by some pools
+	#[serde(default, alias = "clean_job", alias = "clean_jobs")]
+	pub cleanjob: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -41,6 +80,17 @@ pub struct RpcResponse {
 	pub error: Option<RpcError>,
 }
 
+impl RpcResponse {
+	/// Whether this is an accepted submit that also found a block, which
+	/// grin's stratum server reports as a "blockfound - <hash>" result
+	pub fn block_found(&self) -> bool {
+		match self.result {
+			Some(ref r) => r.to_string().contains("blockfound"),
+			None => false,
+		}
+	}
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RpcError {
 	pub code: i32,
@@ -52,6 +102,10 @@ pub struct LoginParams {
 	pub login: String,
 	pub pass: String,
 	pub agent: String,
+	/// starting share difficulty suggested to the pool, ignored by pools
+	/// that don't support it
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub difficulty: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -60,7 +114,17 @@ pub struct SubmitParams {
 	pub job_id: u64,
 	pub edge_bits: u32,
 	pub nonce: u64,
-	pub pow: Vec<u64>,
+	pub pow: SubmitPow,
+}
+
+/// Encoding of the proof in a submit request
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum SubmitPow {
+	/// List of the `PROOFSIZE` proof nonces
+	Nonces(Vec<u64>),
+	/// Hex of the nonces bit-packed at edge_bits width
+	Packed(String),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -79,12 +143,81 @@ pub enum MinerMessage {
 	// Height, difficulty, pre_pow
 	ReceivedJob(u64, u64, u64, String),
 	StopJob,
+	// Pause/resume mining on all devices
+	Pause,
+	Resume,
+	// Pause/resume mining on a device, by solver index
+	PauseDevice(usize),
+	ResumeDevice(usize),
+	// Tear down and recreate all solvers
+	RestartSolvers,
+	// Share difficulty pushed by the server for the current job
+	SetDifficulty(u64),
+	// A share found by the solver with this index was accepted
+	ShareAccepted(usize),
+	// Plugin configs re-read from the config file, to restart the solvers
+	// whose config changed
+	ReloadPlugins(Vec<PluginConfig>),
 	Shutdown,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ClientMessage {
-	// height, job_id, edge_bits, nonce, pow
-	FoundSolution(u64, u64, u32, u64, Vec<u64>),
+	// height, job_id, edge_bits, nonce, pow, solver index, difficulty
+	FoundSolution(u64, u64, u32, u64, Vec<u64>, usize, u64),
 	Shutdown,
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use serde_json;
+
+	#[test]
+	fn test_job_template_fields() {
+		// grin's stratum server
+		let job: JobTemplate =
+			serde_json::from_str(r#"{"height":1000,"job_id":2,"difficulty":4,"pre_pow":"00ff"}"#)
+				.unwrap();
+		assert_eq!((job.height, job.job_id, job.difficulty), (1000, 2, 4));
+		assert_eq!(job.pre_pow, "00ff");
+		assert_eq!(job.xn, None);
+		assert_eq!(job.cleanjob, None);
+
+		// pool style names, with fields we don't know about
+		let job: JobTemplate = serde_json::from_str(
+			r#"{"height":1001,"jobId":3,"diff":8,"prepow":"ff00","extranonce":"ab",
+			"clean_jobs":true,"algo":"cuckatoo32"}"#,
+		)
+		.unwrap();
+		assert_eq!((job.height, job.job_id, job.difficulty), (1001, 3, 8));
+		assert_eq!(job.pre_pow, "ff00");
+		assert_eq!(job.xn, Some("ab".to_owned()));
+		assert_eq!(job.cleanjob, Some(true));
+	}
+
+	#[test]
+	fn test_block_found() {
+		let res: RpcResponse = serde_json::from_str(
+			r#"{"id":"5","jsonrpc":"2.0","method":"submit",
+			"result":"blockfound - 23025af9032de812d15228121d5e4b0e977d30ad8036ab07131104787b9dcf10",
+			"error":null}"#,
+		)
+		.unwrap();
+		assert!(res.block_found());
+
+		let res: RpcResponse = serde_json::from_str(
+			r#"{"id":"6","jsonrpc":"2.0","method":"submit","result":"ok","error":null}"#,
+		)
+		.unwrap();
+		assert!(!res.block_found());
+
+		// rejected shares never found a block
+		let res: RpcResponse = serde_json::from_str(
+			r#"{"id":"7","jsonrpc":"2.0","method":"submit","result":null,
+			"error":{"code":-32502,"message":"Solution Submitted too late"}}"#,
+		)
+		.unwrap();
+		assert!(!res.block_found());
+	}
+}
@@ -0,0 +1,131 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Last resort liveness check, restarting the solvers (or exiting) if no
+//! graphs are completed for too long while there's work to do
+
+use cuckoo;
+use stats;
+use std::sync::{mpsc, Arc, RwLock};
+use std::{thread, time};
+use types;
+use util::LOGGER;
+
+/// How often the watchdog looks at the stats
+const CHECK_INTERVAL_SECS: u64 = 5;
+
+pub struct Watchdog {
+	timeout_secs: u64,
+	exit_on_stall: bool,
+	stats: Arc<RwLock<stats::Stats>>,
+	miner_tx: mpsc::Sender<types::MinerMessage>,
+}
+
+impl Watchdog {
+	pub fn new(
+		timeout_secs: u64,
+		exit_on_stall: bool,
+		stats: Arc<RwLock<stats::Stats>>,
+		miner_tx: mpsc::Sender<types::MinerMessage>,
+	) -> Watchdog {
+		Watchdog {
+			timeout_secs,
+			exit_on_stall,
+			stats,
+			miner_tx,
+		}
+	}
+
+	/// Whether the solvers should be making progress, and the total number
+	/// of graphs completed so far by the devices that aren't paused
+	fn progress(&self) -> (bool, u64) {
+		let stats = self.stats.read().unwrap();
+		let mining = &stats.mining_stats;
+		let active: Vec<&cuckoo::DeviceStats> =
+			mining.device_stats.iter().filter(|s| !s.paused).collect();
+		let working = stats.client_stats.connection_state == stats::ConnectionState::Ready
+			&& mining.block_height > 0
+			&& !mining.job_too_old
+			&& !mining.paused
+			&& !active.is_empty();
+		let iterations = active.iter().map(|s| s.iterations as u64).sum();
+		(working, iterations)
+	}
+
+	/// Watch for stalls until the process exits
+	pub fn run(&self) {
+		let mut last_iterations = 0;
+		let mut stalled_since = time::Instant::now();
+		loop {
+			thread::sleep(time::Duration::from_secs(CHECK_INTERVAL_SECS));
+			let (working, iterations) = self.progress();
+			if !working || iterations != last_iterations {
+				last_iterations = iterations;
+				stalled_since = time::Instant::now();
+				continue;
+			}
+			let stalled_secs = stalled_since.elapsed().as_secs();
+			if stalled_secs < self.timeout_secs {
+				continue;
+			}
+			if self.exit_on_stall {
+				error!(
+					LOGGER,
+					"Watchdog: no graphs completed in {}s, exiting", stalled_secs
+				);
+				types::ExitCode::Stalled.exit();
+			}
+			error!(
+				LOGGER,
+				"Watchdog: no graphs completed in {}s, restarting solvers", stalled_secs
+			);
+			if self
+				.miner_tx
+				.send(types::MinerMessage::RestartSolvers)
+				.is_err()
+			{
+				return;
+			}
+			stalled_since = time::Instant::now();
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_paused_devices_are_idle() {
+		let stats = Arc::new(RwLock::new(stats::Stats::default()));
+		let (miner_tx, _miner_rx) = mpsc::channel();
+		let watchdog = Watchdog::new(60, false, stats.clone(), miner_tx);
+		{
+			let mut s = stats.write().unwrap();
+			s.client_stats
+				.set_connection_state(stats::ConnectionState::Ready);
+			s.mining_stats.block_height = 1;
+			s.mining_stats.device_stats = vec![cuckoo::DeviceStats::default(); 2];
+			s.mining_stats.device_stats[0].solver.iterations = 5;
+			s.mining_stats.device_stats[1].solver.iterations = 7;
+		}
+		assert_eq!(watchdog.progress(), (true, 12));
+		// a paused device's graphs don't count
+		stats.write().unwrap().mining_stats.device_stats[1].paused = true;
+		assert_eq!(watchdog.progress(), (true, 5));
+		// and with every device paused there's nothing to watch
+		stats.write().unwrap().mining_stats.device_stats[0].paused = true;
+		assert!(!watchdog.progress().0);
+	}
+}
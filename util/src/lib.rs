@@ -38,7 +38,7 @@ extern crate serde_derive;
 
 // Logging related
 pub mod logger;
-pub use logger::{init_logger, init_test_logger, LOGGER};
+pub use logger::{init_logger, init_test_logger, LogSampler, LOGGER};
 
 pub mod types;
 pub use types::{LogLevel, LoggingConfig};
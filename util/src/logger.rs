@@ -12,12 +12,17 @@
 // limitations under the License.
 
 //! Logging wrapper to be used throughout all crates in the workspace
-use slog::{Discard, Drain, Duplicate, Level, LevelFilter, Logger};
+use slog::{Discard, Drain, Duplicate, Level, LevelFilter, Logger, Never, OwnedKVList, Record};
 use slog_async;
 use slog_term;
 use std::fs::OpenOptions;
 use std::ops::Deref;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::process;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use backtrace::Backtrace;
 use std::{panic, thread};
@@ -35,6 +40,71 @@ fn convert_log_level(in_level: &LogLevel) -> Level {
 	}
 }
 
+/// Syslog severity for a log level
+fn syslog_severity(level: Level) -> u8 {
+	match level {
+		Level::Critical => 2,
+		Level::Error => 3,
+		Level::Warning => 4,
+		Level::Info => 6,
+		Level::Debug | Level::Trace => 7,
+	}
+}
+
+/// Path of the local syslog socket, journald listens on it too
+#[cfg(unix)]
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Drain sending records to the local syslog socket at the matching
+/// severity, under the user facility
+struct SyslogDrain {
+	#[cfg(unix)]
+	socket: Option<UnixDatagram>,
+}
+
+impl SyslogDrain {
+	#[cfg(unix)]
+	fn new() -> SyslogDrain {
+		let socket = UnixDatagram::unbound()
+			.and_then(|s| s.connect(SYSLOG_SOCKET).map(|_| s))
+			.map_err(|e| eprintln!("Can't connect to syslog at {}: {}", SYSLOG_SOCKET, e))
+			.ok();
+		SyslogDrain { socket }
+	}
+
+	#[cfg(not(unix))]
+	fn new() -> SyslogDrain {
+		eprintln!("Logging to syslog is only supported on unix");
+		SyslogDrain {}
+	}
+}
+
+impl Drain for SyslogDrain {
+	type Ok = ();
+	type Err = Never;
+
+	#[cfg(unix)]
+	fn log(&self, record: &Record, _values: &OwnedKVList) -> Result<(), Never> {
+		if let Some(ref socket) = self.socket {
+			// user facility
+			let priority = 8 + syslog_severity(record.level());
+			let msg = format!(
+				"<{}>grin-miner[{}]: {}",
+				priority,
+				process::id(),
+				record.msg()
+			);
+			let _ = socket.send(msg.as_bytes());
+		}
+		Ok(())
+	}
+
+	#[cfg(not(unix))]
+	fn log(&self, _record: &Record, _values: &OwnedKVList) -> Result<(), Never> {
+		Ok(())
+	}
+}
+
 lazy_static! {
 	/// Flag to observe whether logging was explicitly initialised (don't output otherwise)
 	static ref WAS_INIT: Mutex<bool> = Mutex::new(false);
@@ -81,8 +151,19 @@ lazy_static! {
 			file_drain_final = slog_async::Async::new(file_drain).build().fuse();
 		}
 
-		//Compose file and terminal drains
+		//Syslog drain
+		let mut syslog_drain = slog_async::Async::new(Discard{}).build().fuse();
+		if config.log_to_syslog.unwrap_or(false) && was_init {
+			let slog_level_syslog = convert_log_level(
+				config.syslog_log_level.as_ref().unwrap_or(&LogLevel::Info),
+			);
+			let drain = LevelFilter::new(SyslogDrain::new(), slog_level_syslog).fuse();
+			syslog_drain = slog_async::Async::new(drain).build().fuse();
+		}
+
+		//Compose file, terminal and syslog drains
 		let composite_drain = Duplicate::new(terminal_drain, file_drain_final).fuse();
+		let composite_drain = Duplicate::new(composite_drain, syslog_drain).fuse();
 
 		Logger::root(composite_drain, o!())
 	};
@@ -156,3 +237,57 @@ fn send_panic_to_log() {
 		}
 	}));
 }
+
+/// Samples high-frequency log messages, letting at most the configured
+/// number through each second so bursts don't flood the logs (or the TUI)
+pub struct LogSampler {
+	max_per_sec: Option<u32>,
+	window_start: Instant,
+	count: u32,
+	suppressed: u64,
+}
+
+impl LogSampler {
+	/// Create a sampler using the limit from the logging configuration
+	pub fn new() -> LogSampler {
+		let max_per_sec = LOGGING_CONFIG.lock().unwrap().max_noisy_logs_per_sec;
+		LogSampler {
+			max_per_sec,
+			window_start: Instant::now(),
+			count: 0,
+			suppressed: 0,
+		}
+	}
+
+	/// Whether the next message should be logged
+	pub fn sample(&mut self) -> bool {
+		let max_per_sec = match self.max_per_sec {
+			Some(m) => m,
+			None => return true,
+		};
+		if self.window_start.elapsed() >= Duration::from_secs(1) {
+			if self.suppressed > 0 {
+				debug!(
+					LOGGER,
+					"{} high-frequency log messages suppressed", self.suppressed
+				);
+			}
+			self.window_start = Instant::now();
+			self.count = 0;
+			self.suppressed = 0;
+		}
+		if self.count < max_per_sec {
+			self.count += 1;
+			true
+		} else {
+			self.suppressed += 1;
+			false
+		}
+	}
+}
+
+impl Default for LogSampler {
+	fn default() -> LogSampler {
+		LogSampler::new()
+	}
+}
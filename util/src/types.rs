@@ -48,6 +48,15 @@ pub struct LoggingConfig {
 	pub log_file_append: bool,
 	/// Whether the tui is running (optional)
 	pub tui_running: Option<bool>,
+	/// Maximum number of high-frequency debug messages (e.g. every message
+	/// sent to or received from the stratum server) logged per second.
+	/// Unlimited if not set
+	pub max_noisy_logs_per_sec: Option<u32>,
+	/// Whether to log to the local syslog socket, which journald also
+	/// reads (unix only)
+	pub log_to_syslog: Option<bool>,
+	/// Log level for syslog (defaults to Info)
+	pub syslog_log_level: Option<LogLevel>,
 }
 
 impl Default for LoggingConfig {
@@ -60,6 +69,9 @@ impl Default for LoggingConfig {
 			log_file_path: String::from("grin.log"),
 			log_file_append: false,
 			tui_running: None,
+			max_noisy_logs_per_sec: None,
+			log_to_syslog: None,
+			syslog_log_level: None,
 		}
 	}
 }